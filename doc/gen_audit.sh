@@ -0,0 +1,7 @@
+#!/usr/bin/env bash
+
+SUBCOMMAND="audit" envsubst < ./doc/subcommand.template
+
+echo "\`\`\`"
+./target/release/mgt audit --help
+echo "\`\`\`"
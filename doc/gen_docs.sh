@@ -10,3 +10,8 @@
 ./doc/gen_topbase.sh > doc/topbase.md
 ./doc/gen_check.sh > doc/check.md
 ./doc/gen_verify_rf.sh > doc/verify-rf.md
+./doc/gen_audit.sh > doc/audit.md
+./doc/gen_lint_rf.sh > doc/lint-rf.md
+./doc/gen_fmt_rf.sh > doc/fmt-rf.md
+./doc/gen_init_rf.sh > doc/init-rf.md
+./doc/gen_history.sh > doc/history.md
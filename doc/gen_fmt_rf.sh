@@ -0,0 +1,7 @@
+#!/usr/bin/env bash
+
+SUBCOMMAND="fmt-rf" envsubst < ./doc/subcommand.template
+
+echo "\`\`\`"
+./target/release/mgt fmt-rf --help
+echo "\`\`\`"
@@ -0,0 +1,7 @@
+#!/usr/bin/env bash
+
+SUBCOMMAND="history" envsubst < ./doc/subcommand.template
+
+echo "\`\`\`"
+./target/release/mgt history --help
+echo "\`\`\`"
@@ -0,0 +1,7 @@
+#!/usr/bin/env bash
+
+SUBCOMMAND="init-rf" envsubst < ./doc/subcommand.template
+
+echo "\`\`\`"
+./target/release/mgt init-rf --help
+echo "\`\`\`"
@@ -0,0 +1,7 @@
+#!/usr/bin/env bash
+
+SUBCOMMAND="lint-rf" envsubst < ./doc/subcommand.template
+
+echo "\`\`\`"
+./target/release/mgt lint-rf --help
+echo "\`\`\`"
@@ -67,6 +67,10 @@ fn main() {
         branch: filter.branch,
         default_include: filter.default_include,
         with_blobs: filter.with_data,
+        synthesize_gitfiles: false,
+        overlay: vec![],
+        versioned_rules: vec![],
+        detect_destination_collisions: false,
     };
     let mut filter_rules = vec![];
 
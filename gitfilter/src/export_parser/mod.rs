@@ -289,6 +289,16 @@ pub fn write_to_stream<W: Write>(stream: W, obj: StructuredExportObject) -> io::
                     write_data.push(b' ');
                     write_data.extend(path.as_bytes());
                 }
+                FileOpsOwned::FileModifyInline(mode, data, path) => {
+                    write_data.extend(b"M ");
+                    write_data.extend(mode.as_bytes());
+                    write_data.extend(b" inline ");
+                    write_data.extend(path.as_bytes());
+                    write_data.extend(b"\ndata ");
+                    write_data.extend(data.len().to_string().as_bytes());
+                    write_data.push(b'\n');
+                    write_data.extend(data);
+                }
                 FileOpsOwned::FileDelete(path) => {
                     write_data.extend(b"D ");
                     write_data.extend(path.as_bytes());
@@ -242,6 +242,9 @@ pub enum FileOps<'a> {
 #[derive(Debug, PartialEq, Hash)]
 pub enum FileOpsOwned {
     FileModify(String, String, String),
+    /// like FileModify, but instead of a dataref,
+    /// the contents are written inline: (mode, data, path)
+    FileModifyInline(String, Vec<u8>, String),
     FileDelete(String),
     FileCopy(String, String),
     FileRename(String, String),
@@ -4,6 +4,9 @@ use export_parser::FileOpsOwned;
 use super::filter_state::FilterState;
 use super::filter_state::MAPS_TO_EMPTY;
 use super::filter_state::UNKNOWN_MAP;
+use super::gitmodules::{GITLINK_MODE, GITMODULES_PATH};
+use super::gitmodules::{read_blob, rewrite_gitmodules, should_use_gitlink};
+use super::synthesize::{update_synthesize_sources, add_synthesized_files};
 use std::io::Write;
 use std::process::Stdio;
 use std::{path::{PathBuf, Path}, io};
@@ -17,6 +20,52 @@ pub enum FilterRule {
 pub use FilterRule::*;
 
 pub type FilterRules = Vec<FilterRule>;
+
+impl FilterRule {
+    /// the path that this rule matches against
+    pub fn source(&self) -> &str {
+        match self {
+            FilterRulePathInclude(src) |
+            FilterRulePathExclude(src) |
+            FilterRulePathRename(src, _) => src,
+        }
+    }
+}
+
+/// sort rules by the path they match against. the last rule
+/// that matches a file wins, so this is what makes a rule of a
+/// subdirectory take precedence over a rule of its parent.
+/// rules of the same path keep their order
+pub fn sort_filter_rules(filter_rules: &mut FilterRules) {
+    filter_rules.sort_by(|a, b| a.source().cmp(b.source()));
+}
+
+/// filter rules that only apply to part of the history, in addition
+/// to the regular filter rules. `since` is the original oid of the
+/// first commit in the export stream that these rules apply to, and `until`
+/// is the original oid of the first commit that they no longer apply to.
+/// if `since` is None, these rules apply from the start of the stream,
+/// and if `until` is None they apply until the end.
+#[derive(Clone, Debug, Default)]
+pub struct VersionedFilterRules {
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub rules: FilterRules,
+}
+
+/// where the contents of an overlay file come from. they are added as
+/// normal file modifications in the commits where they change, so
+/// the history before that stays the same
+#[derive(Clone, Debug, PartialEq)]
+pub enum OverlayContents {
+    /// follows the history of this path of the input, so the
+    /// overlay file changes in the commits that this path changes in
+    InputPath(String),
+    /// these contents, added in the commit with this original oid,
+    /// or in the root commits of the output if None
+    Inline(Vec<u8>, Option<String>),
+}
+
 #[derive(Debug)]
 pub struct FilterError(String);
 
@@ -77,6 +126,17 @@ pub struct FilterOptions<T: Write> {
     pub branch: Option<String>,
     pub default_include: bool,
     pub with_blobs: bool,
+    /// synthesize a .gitignore and .gitattributes at the root
+    /// of the output from the ones above the included directories
+    pub synthesize_gitfiles: bool,
+    /// files to add to the output that dont exist in the input,
+    /// as pairs of (destination path, where its contents come from)
+    pub overlay: Vec<(String, OverlayContents)>,
+    /// rules that only apply to part of the history. see VersionedFilterRules
+    pub versioned_rules: Vec<VersionedFilterRules>,
+    /// fail if two different source paths end up at the same
+    /// destination path, which would interleave their histories
+    pub detect_destination_collisions: bool,
     // TODO:
     // pub num_threads: Option<usize>,
 }
@@ -88,6 +148,10 @@ impl<T: Write> From<T> for FilterOptions<T> {
             branch: None,
             default_include: false,
             with_blobs: false,
+            synthesize_gitfiles: false,
+            overlay: vec![],
+            versioned_rules: vec![],
+            detect_destination_collisions: false,
         }
     }
 }
@@ -105,6 +169,20 @@ pub fn should_use_file(
     filter_rules: &FilterRules,
     default_include: bool,
 ) -> bool {
+    should_use_file_ex(path, filter_rules, default_include, |_| {}).0
+}
+
+/// same as `should_use_file`, but calls `on_match` with the index
+/// of every filter rule that matches the path, in order.
+/// returns whether or not to use the file, and the index of the rule
+/// that made that decision. that is None if no rule matched, and
+/// the decision was the default.
+pub fn should_use_file_ex<F: FnMut(usize)>(
+    path: &mut String,
+    filter_rules: &FilterRules,
+    default_include: bool,
+    mut on_match: F,
+) -> (bool, Option<usize>) {
     let pathlen = path.len();
     let (check_path, re_add_quotes) = if path.starts_with('"') && path.ends_with('"') {
         (&path[1..(pathlen - 1)], true)
@@ -112,26 +190,33 @@ pub fn should_use_file(
         (&path[..], false)
     };
     let mut should_keep = default_include;
+    let mut decided_by = None;
     let mut replace = None;
-    for filter_rule in filter_rules {
+    for (i, filter_rule) in filter_rules.iter().enumerate() {
         match filter_rule {
             FilterRulePathInclude(include) => {
                 if check_path.starts_with(include) {
+                    on_match(i);
+                    decided_by = Some(i);
                     should_keep = true;
                 }
             }
             FilterRulePathExclude(exclude) => {
                 if check_path.starts_with(exclude) {
+                    on_match(i);
                     if check_path == exclude {
                         // if it matches exactly, we should not iterate anymore
                         // this is a definitive exclude
-                        return false;
+                        return (false, Some(i));
                     }
+                    decided_by = Some(i);
                     should_keep = false;
                 }
             }
             FilterRulePathRename(src, dest) => {
                 if check_path.starts_with(src) {
+                    on_match(i);
+                    decided_by = Some(i);
                     replace = Some(check_path.replacen(src, dest, 1));
                     should_keep = true;
                 }
@@ -158,15 +243,83 @@ pub fn should_use_file(
         *path = format!("\"{}\"", path);
     }
 
-    should_keep
+    (should_keep, decided_by)
+}
+
+/// true if any of the parents of a commit (after
+/// mapping them to what they got filtered to) contain this path
+pub fn parents_have_path(
+    filter_state: &FilterState,
+    parents: &[usize],
+    path: &str,
+) -> bool {
+    for p in parents {
+        let mapped_to = match filter_state.get_mapped_mark(*p) {
+            Some(m) => *m,
+            None => continue,
+        };
+        if mapped_to == UNKNOWN_MAP || mapped_to == MAPS_TO_EMPTY {
+            continue;
+        }
+        if filter_state.mark_has_path(mapped_to, path) {
+            return true;
+        }
+    }
+    false
+}
+
+/// get the contents of a blob (ie: .gitmodules), reading it from the
+/// repository if we havent seen it yet. returns None if
+/// it cannot be read, which is the case if the dataref is a mark
+/// (ie: when filtering with blobs) rather than a sha.
+pub fn get_blob_contents(
+    filter_state: &mut FilterState,
+    dataref: &str,
+) -> Option<String> {
+    if !filter_state.blob_contents.contains_key(dataref) {
+        if dataref.starts_with(':') {
+            return None;
+        }
+        let contents = read_blob(dataref, filter_state.location.as_ref()).ok()?;
+        filter_state.blob_contents.insert(dataref.to_string(), contents);
+    }
+    let contents = filter_state.blob_contents.get(dataref)?;
+    Some(String::from_utf8_lossy(contents).to_string())
+}
+
+/// the files that a commit deletes no longer end up at their destination.
+/// fast-export sorts the fileops of a commit by path, so when a file moves,
+/// the delete can come after the modify that moved it. that is why
+/// these are handled before any of the modifies
+pub fn forget_deleted_destination_sources(
+    filter_state: &mut FilterState,
+    commit: &StructuredCommit,
+    filter_rules: &FilterRules,
+    default_include: bool,
+) {
+    for op in commit.fileops.iter() {
+        match op {
+            FileOpsOwned::FileDeleteAll => filter_state.destination_sources.clear(),
+            FileOpsOwned::FileDelete(path) => {
+                let mut dest = path.clone();
+                if should_use_file(&mut dest, filter_rules, default_include) {
+                    filter_state.forget_destination_source(path, &dest);
+                }
+            }
+            _ => (),
+        }
+    }
 }
 
 pub fn apply_filter_rules_to_fileops(
     default_include: bool,
-    _filter_state: &mut FilterState,
+    filter_state: &mut FilterState,
     commit: &mut StructuredCommit,
     filter_rules: &FilterRules,
 ) -> Vec<FileOpsOwned> {
+    if filter_state.detect_destination_collisions {
+        forget_deleted_destination_sources(filter_state, commit, filter_rules, default_include);
+    }
     let mut newfileops = vec![];
     for op in commit.fileops.drain(..) {
         match op {
@@ -194,17 +347,70 @@ pub fn apply_filter_rules_to_fileops(
                 // }
             }
 
+            // the .gitmodules file is not filtered by its path. instead
+            // we rewrite it to only contain the submodules that
+            // are kept by the filter rules, and at their renamed paths.
+            // if there are no submodules left, then it should not exist at all.
+            FileOpsOwned::FileModify(mode, dataref, path) if path == GITMODULES_PATH => {
+                let contents = match get_blob_contents(filter_state, &dataref) {
+                    Some(c) => c,
+                    None => {
+                        // cant read it, so just filter it like any other file
+                        let mut path = path;
+                        if should_use_file(&mut path, filter_rules, default_include) {
+                            newfileops.push(FileOpsOwned::FileModify(mode, dataref, path));
+                        }
+                        continue;
+                    }
+                };
+                match rewrite_gitmodules(&contents, filter_rules, default_include) {
+                    Some(new_contents) => {
+                        newfileops.push(FileOpsOwned::FileModifyInline(mode, new_contents.into_bytes(), path));
+                    }
+                    None => if parents_have_path(filter_state, &commit.merges, &path) {
+                        newfileops.push(FileOpsOwned::FileDelete(path));
+                    }
+                }
+            }
+            FileOpsOwned::FileDelete(path) if path == GITMODULES_PATH => {
+                if parents_have_path(filter_state, &commit.merges, &path) {
+                    newfileops.push(FileOpsOwned::FileDelete(path));
+                }
+            }
+
             // easiest cases. if it exists, keep it
             FileOpsOwned::FileModify(mode, dataref, mut path) => {
-                if should_use_file(&mut path, filter_rules, default_include) {
+                let original_path = if filter_state.detect_destination_collisions {
+                    Some(path.clone())
+                } else {
+                    None
+                };
+                let should_use = if mode == GITLINK_MODE {
+                    should_use_gitlink(&mut path, filter_rules, default_include)
+                } else {
+                    should_use_file(&mut path, filter_rules, default_include)
+                };
+                if should_use {
+                    if let Some(original_path) = original_path {
+                        filter_state.record_destination_source(original_path, &path);
+                    }
                     newfileops.push(FileOpsOwned::FileModify(mode, dataref, path));
                 }
             }
             FileOpsOwned::FileDelete(mut path) => {
-                if should_use_file(&mut path, filter_rules, default_include) {
+                // a delete doesnt tell us the mode of what got deleted,
+                // so it might have been a gitlink
+                if should_use_file(&mut path, filter_rules, default_include) ||
+                    should_use_gitlink(&mut path, filter_rules, default_include)
+                {
                     newfileops.push(FileOpsOwned::FileDelete(path));
                 }
             }
+            // we only create these ourselves, so
+            // they are already filtered
+            FileOpsOwned::FileModifyInline(mode, data, path) => {
+                newfileops.push(FileOpsOwned::FileModifyInline(mode, data, path));
+            }
         }
     }
     newfileops
@@ -325,6 +531,75 @@ pub fn parent_has_same_contents(
     Ok(answer)
 }
 
+/// the overlay files take the place of
+/// anything that would otherwise be filtered to the same path
+pub fn remove_overlayed_fileops(
+    filter_state: &FilterState,
+    commit: &mut StructuredCommit,
+) {
+    commit.fileops.retain(|op| {
+        let path = match op {
+            FileOpsOwned::FileModify(_, _, p) |
+            FileOpsOwned::FileModifyInline(_, _, p) |
+            FileOpsOwned::FileDelete(p) => p,
+            _ => return true,
+        };
+        !filter_state.overlay.iter().any(|(dest, _)| dest == path)
+    });
+}
+
+/// the overlay fileops of this commit, from its fileops before
+/// they were filtered. an overlay file that follows an input path
+/// changes with it, and inline contents are added in their commit
+pub fn get_overlay_fileops(
+    filter_state: &FilterState,
+    commit: &StructuredCommit,
+) -> Vec<FileOpsOwned> {
+    let mut out = vec![];
+    for (dest, contents) in filter_state.overlay.iter() {
+        match contents {
+            OverlayContents::InputPath(src) => {
+                for op in commit.fileops.iter() {
+                    match op {
+                        FileOpsOwned::FileModify(mode, dataref, p) if p == src => {
+                            out.push(FileOpsOwned::FileModify(mode.clone(), dataref.clone(), dest.clone()));
+                        }
+                        FileOpsOwned::FileModifyInline(mode, data, p) if p == src => {
+                            out.push(FileOpsOwned::FileModifyInline(mode.clone(), data.clone(), dest.clone()));
+                        }
+                        FileOpsOwned::FileDelete(p) if p == src => {
+                            out.push(FileOpsOwned::FileDelete(dest.clone()));
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            OverlayContents::Inline(data, Some(since)) if *since == commit.original_oid => {
+                out.push(FileOpsOwned::FileModifyInline("100644".into(), data.clone(), dest.clone()));
+            }
+            OverlayContents::Inline(..) => (),
+        }
+    }
+    out
+}
+
+/// inline contents without a commit to add them in
+/// are added to every commit that ends up being a root
+/// of the output, so that they exist throughout the
+/// entire filtered history
+pub fn add_root_overlay_fileops(
+    filter_state: &FilterState,
+    commit: &mut StructuredCommit,
+) {
+    for (dest, contents) in filter_state.overlay.iter() {
+        if let OverlayContents::Inline(data, None) = contents {
+            let fileop = FileOpsOwned::FileModifyInline(
+                "100644".into(), data.clone(), dest.clone());
+            commit.fileops.push(fileop);
+        }
+    }
+}
+
 /// we expect the commit.fileops to have already
 /// applied the filter rules, so we have
 /// to check for if we even want this commit
@@ -468,15 +743,82 @@ pub fn perform_filter2_for_merge_commit(
     Ok(FilterResponse::UseAsIs)
 }
 
+/// update which of the versioned rules are active now that we
+/// are at this commit in the export stream, and return the
+/// regular filter rules combined with the active versioned rules
+pub fn get_active_filter_rules(
+    filter_state: &mut FilterState,
+    original_oid: &str,
+    filter_rules: &FilterRules,
+) -> FilterRules {
+    let mut active_rules = filter_rules.clone();
+    let mut active_rules_changed = false;
+    for (versioned, is_active) in filter_state.versioned_rules.iter()
+        .zip(filter_state.versioned_rules_active.iter_mut())
+    {
+        if versioned.since.as_deref() == Some(original_oid) {
+            active_rules_changed = active_rules_changed || !*is_active;
+            *is_active = true;
+        }
+        if versioned.until.as_deref() == Some(original_oid) {
+            active_rules_changed = active_rules_changed || *is_active;
+            *is_active = false;
+        }
+        if *is_active {
+            active_rules.extend(versioned.rules.iter().cloned());
+        }
+    }
+    // otherwise a versioned rule would win over a
+    // regular rule of one of its subdirectories
+    sort_filter_rules(&mut active_rules);
+    // the point of versioned rules is that a different source
+    // can end up at the same destination as before, so
+    // that is not a collision
+    if active_rules_changed {
+        filter_state.destination_sources.clear();
+    }
+    active_rules
+}
+
 pub fn perform_filter2(
     default_include: bool,
     filter_state: &mut FilterState,
     commit: &mut StructuredCommit,
     filter_rules: &FilterRules,
 ) -> Result<FilterResponse, FilterError> {
+    let active_rules;
+    let filter_rules = if filter_state.versioned_rules.is_empty() {
+        filter_rules
+    } else {
+        active_rules = get_active_filter_rules(filter_state, &commit.original_oid, filter_rules);
+        &active_rules
+    };
+    let should_synthesize = filter_state.synthesize_gitfiles &&
+        update_synthesize_sources(filter_state, commit, filter_rules, default_include);
+    let overlay_fileops = if filter_state.overlay.is_empty() {
+        vec![]
+    } else {
+        get_overlay_fileops(filter_state, commit)
+    };
     let newfileops = apply_filter_rules_to_fileops(
         default_include, filter_state, commit, filter_rules);
     commit.fileops = newfileops;
+    if let Some((dest, first_src, second_src)) = filter_state.destination_collision.take() {
+        return Err(FilterError(format!(
+            "Both '{}' and '{}' end up at '{}', which would mix their histories together. \
+            If this is intended, set allow_destination_collisions = true",
+            first_src, second_src, dest
+        )));
+    }
+    if should_synthesize {
+        add_synthesized_files(filter_state, commit, filter_rules, default_include);
+    }
+    if !filter_state.overlay.is_empty() {
+        remove_overlayed_fileops(filter_state, commit);
+        // before checking if this commit is empty, because
+        // a commit that only changes an overlay file is used
+        commit.fileops.extend(overlay_fileops);
+    }
 
     let resp = match commit.merges.len() {
         // this is an initial commit, doesnt have a from line
@@ -491,6 +833,11 @@ pub fn perform_filter2(
         // two or more parents: merge commit
         _ => perform_filter2_for_merge_commit(filter_state, commit)
     };
+    if let Ok(FilterResponse::UseAsIs) = resp {
+        if commit.merges.is_empty() {
+            add_root_overlay_fileops(filter_state, commit);
+        }
+    }
     resp
 }
 
@@ -500,7 +847,14 @@ pub fn filter_with_rules<P: AsRef<Path>, T: Write>(
     location: Option<P>,
 ) -> io::Result<()> {
     // eprintln!("Filter rules: {:#?}", filter_rules);
-    let mut filter_state = FilterState::default();
+    let mut filter_state = FilterState {
+        location: location.as_ref().map(|l| l.as_ref().to_path_buf()),
+        synthesize_gitfiles: filter_options.synthesize_gitfiles,
+        overlay: filter_options.overlay.clone(),
+        detect_destination_collisions: filter_options.detect_destination_collisions,
+        ..FilterState::default()
+    };
+    filter_state.set_versioned_rules(filter_options.versioned_rules.clone());
     let default_include = filter_options.default_include;
     let cb = |obj: &mut StructuredExportObject| -> io::Result<bool> {
         // TODO: filter on blobs as well:
@@ -584,6 +938,10 @@ pub fn filter_with_rules_direct_ex<P: AsRef<Path>, T: Write>(
         branch: filter_options.branch,
         default_include: filter_options.default_include,
         with_blobs: filter_options.with_blobs,
+        synthesize_gitfiles: filter_options.synthesize_gitfiles,
+        overlay: filter_options.overlay,
+        versioned_rules: filter_options.versioned_rules,
+        detect_destination_collisions: filter_options.detect_destination_collisions,
     };
 
     let res = filter_with_rules(overwritten_options, filter_rules, location);
@@ -956,4 +1314,259 @@ mod test {
         }
         assert_eq!(new_fileops, expected_fileops);
     }
+
+    #[test]
+    fn filter_rules_rewrite_gitmodules_and_gitlinks() {
+        let mut filter_state = FilterState::default();
+        let gitmodules = "[submodule \"a\"]\n\tpath = lib/a\n[submodule \"b\"]\n\tpath = lib/b\n";
+        filter_state.blob_contents.insert("abc".into(), gitmodules.as_bytes().to_vec());
+        let mut commit = StructuredCommit::default();
+        commit.fileops = vec![
+            FileOpsOwned::FileModify("100644".into(), "abc".into(), ".gitmodules".into()),
+            FileOpsOwned::FileModify("160000".into(), "def".into(), "lib/a".into()),
+            FileOpsOwned::FileModify("160000".into(), "fed".into(), "lib/b".into()),
+        ];
+        let filter_rule = FilterRule::FilterRulePathRename("lib/a/".into(), "a/".into());
+        let filter_rules = vec![filter_rule];
+        let new_fileops = apply_filter_rules_to_fileops(
+            false,
+            &mut filter_state,
+            &mut commit,
+            &filter_rules
+        );
+
+        let expected = vec![
+            FileOpsOwned::FileModifyInline(
+                "100644".into(),
+                "[submodule \"a\"]\n\tpath = a\n".as_bytes().to_vec(),
+                ".gitmodules".into(),
+            ),
+            FileOpsOwned::FileModify("160000".into(), "def".into(), "a".into()),
+        ];
+        assert_eq!(new_fileops, expected);
+    }
+
+    #[test]
+    fn overlay_files_only_added_to_root_commits() {
+        let mut filter_state = FilterState::default();
+        filter_state.overlay = vec![("LICENSE".into(), OverlayContents::Inline(b"MIT".to_vec(), None))];
+        let filter_rules = vec![FilterRule::FilterRulePathRename("lib/".into(), "".into())];
+
+        let mut commit = current_commit_state(&["lib/a.txt", "lib/LICENSE"]);
+        commit.mark = 1;
+        let resp = perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).unwrap();
+        assert!(resp.is_used());
+        let expected = vec![
+            FileOpsOwned::FileModify("".into(), "".into(), "a.txt".into()),
+            FileOpsOwned::FileModifyInline("100644".into(), b"MIT".to_vec(), "LICENSE".into()),
+        ];
+        assert_eq!(commit.fileops, expected);
+
+        let mut commit = current_commit_state(&["lib/b.txt"]);
+        commit.mark = 2;
+        commit.merges = vec![1];
+        perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).unwrap();
+        let expected = vec![
+            FileOpsOwned::FileModify("".into(), "".into(), "b.txt".into()),
+        ];
+        assert_eq!(commit.fileops, expected);
+    }
+
+    #[test]
+    fn overlay_files_change_where_their_contents_change() {
+        let mut filter_state = FilterState::default();
+        filter_state.overlay = vec![
+            ("LICENSE".into(), OverlayContents::InputPath("legal/LICENSE-MIT".into())),
+            (".ci.yml".into(), OverlayContents::Inline(b"build: true".to_vec(), Some("ccc".into()))),
+        ];
+        let filter_rules = vec![FilterRule::FilterRulePathRename("lib/".into(), "".into())];
+        let modify = |dataref: &str, path: &str| FileOpsOwned::FileModify("100644".into(), dataref.into(), path.into());
+
+        // before the overlay files exist, the history stays the same
+        let mut commit = StructuredCommit::default();
+        commit.mark = 1;
+        commit.original_oid = "aaa".into();
+        commit.fileops = vec![modify("1", "lib/a.txt")];
+        perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).unwrap();
+        assert_eq!(commit.fileops, vec![modify("1", "a.txt")]);
+
+        let mut commit = StructuredCommit::default();
+        commit.mark = 2;
+        commit.merges = vec![1];
+        commit.original_oid = "bbb".into();
+        commit.fileops = vec![modify("2", "legal/LICENSE-MIT")];
+        let resp = perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).unwrap();
+        assert!(resp.is_used());
+        assert_eq!(commit.fileops, vec![modify("2", "LICENSE")]);
+
+        let mut commit = StructuredCommit::default();
+        commit.mark = 3;
+        commit.merges = vec![2];
+        commit.original_oid = "ccc".into();
+        commit.fileops = vec![modify("3", "other.txt")];
+        let resp = perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).unwrap();
+        assert!(resp.is_used());
+        let expected = vec![
+            FileOpsOwned::FileModifyInline("100644".into(), b"build: true".to_vec(), ".ci.yml".into()),
+        ];
+        assert_eq!(commit.fileops, expected);
+
+        // the overlay takes the place of what would end up at the same path
+        let mut commit = StructuredCommit::default();
+        commit.mark = 4;
+        commit.merges = vec![3];
+        commit.original_oid = "ddd".into();
+        commit.fileops = vec![modify("4", "lib/LICENSE")];
+        let resp = perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).unwrap();
+        assert!(!resp.is_used());
+    }
+
+    #[test]
+    fn versioned_rules_apply_by_position_in_stream() {
+        let mut filter_state = FilterState::default();
+        filter_state.set_versioned_rules(vec![
+            VersionedFilterRules {
+                since: None,
+                until: Some("bbb".into()),
+                rules: vec![FilterRule::FilterRulePathRename("libs/foo/".into(), "".into())],
+            },
+            VersionedFilterRules {
+                since: Some("bbb".into()),
+                until: None,
+                rules: vec![FilterRule::FilterRulePathRename("platform/foo/".into(), "".into())],
+            },
+        ]);
+        let filter_rules = vec![];
+
+        let mut commit = current_commit_state(&["libs/foo/a.txt", "platform/foo/b.txt"]);
+        commit.mark = 1;
+        commit.original_oid = "aaa".into();
+        perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).unwrap();
+        let expected = vec![
+            FileOpsOwned::FileModify("".into(), "".into(), "a.txt".into()),
+        ];
+        assert_eq!(commit.fileops, expected);
+
+        let mut commit = current_commit_state(&["libs/foo/c.txt", "platform/foo/d.txt"]);
+        commit.mark = 2;
+        commit.merges = vec![1];
+        commit.original_oid = "bbb".into();
+        perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).unwrap();
+        let expected = vec![
+            FileOpsOwned::FileModify("".into(), "".into(), "d.txt".into()),
+        ];
+        assert_eq!(commit.fileops, expected);
+    }
+
+    #[test]
+    fn versioned_rules_dont_override_regular_rules_of_subdirectories() {
+        let mut filter_state = FilterState::default();
+        filter_state.set_versioned_rules(vec![
+            VersionedFilterRules {
+                since: None,
+                until: None,
+                rules: vec![FilterRule::FilterRulePathRename("platform/foo/".into(), "".into())],
+            },
+        ]);
+        let filter_rules = vec![FilterRule::FilterRulePathExclude("platform/foo/secret/".into())];
+
+        let mut commit = current_commit_state(&["platform/foo/a.txt", "platform/foo/secret/key.txt"]);
+        commit.mark = 1;
+        commit.original_oid = "aaa".into();
+        perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).unwrap();
+        let expected = vec![
+            FileOpsOwned::FileModify("".into(), "".into(), "a.txt".into()),
+        ];
+        assert_eq!(commit.fileops, expected);
+    }
+
+    #[test]
+    fn should_use_file_ex_reports_matching_rules() {
+        let filter_rules = vec![
+            FilterRulePathRename("src/".into(), "lib/".into()),
+            FilterRulePathExclude("src/secret/".into()),
+            FilterRulePathInclude("src/secret/public.txt".into()),
+            FilterRulePathInclude("docs/".into()),
+        ];
+        let mut matched = vec![];
+        let mut path = "src/secret/public.txt".to_string();
+        let decision = should_use_file_ex(&mut path, &filter_rules, false, |i| matched.push(i));
+        assert_eq!(decision, (true, Some(2)));
+        assert_eq!(matched, vec![0, 1, 2]);
+        assert_eq!(path, "lib/secret/public.txt");
+
+        let mut matched = vec![];
+        let mut path = "src/secret/key.txt".to_string();
+        let decision = should_use_file_ex(&mut path, &filter_rules, false, |i| matched.push(i));
+        assert_eq!(decision, (false, Some(1)));
+        assert_eq!(matched, vec![0, 1]);
+
+        let mut path = "other.txt".to_string();
+        assert_eq!(should_use_file_ex(&mut path, &filter_rules, false, |_| {}), (false, None));
+    }
+
+    #[test]
+    fn destination_collisions_get_detected() {
+        let filter_rules = vec![
+            FilterRulePathRename("a/config.yml".into(), "config.yml".into()),
+            FilterRulePathRename("b/".into(), "".into()),
+        ];
+        let mut filter_state = FilterState::default();
+        filter_state.detect_destination_collisions = true;
+        let mut commit = StructuredCommit::default();
+        commit.fileops = vec![
+            FileOpsOwned::FileModify("100644".into(), "a".into(), "a/config.yml".into()),
+            FileOpsOwned::FileModify("100644".into(), "b".into(), "b/other.yml".into()),
+        ];
+        let resp = perform_filter2(false, &mut filter_state, &mut commit, &filter_rules);
+        assert!(resp.is_ok());
+
+        commit.fileops = vec![
+            FileOpsOwned::FileModify("100644".into(), "c".into(), "b/config.yml".into()),
+        ];
+        let resp = perform_filter2(false, &mut filter_state, &mut commit, &filter_rules);
+        assert!(resp.is_err());
+    }
+
+    #[test]
+    fn destination_can_be_reused_after_its_source_is_deleted() {
+        let filter_rules = vec![
+            FilterRulePathRename("a/".into(), "".into()),
+            FilterRulePathRename("b/".into(), "".into()),
+        ];
+        let mut filter_state = FilterState::default();
+        filter_state.detect_destination_collisions = true;
+        let mut commit = StructuredCommit::default();
+        commit.fileops = vec![
+            FileOpsOwned::FileModify("100644".into(), "a".into(), "a/x".into()),
+        ];
+        assert!(perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).is_ok());
+
+        // a/x and b/x never exist at the same time
+        commit.fileops = vec![FileOpsOwned::FileDelete("a/x".into())];
+        assert!(perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).is_ok());
+        commit.fileops = vec![
+            FileOpsOwned::FileModify("100644".into(), "b".into(), "b/x".into()),
+        ];
+        assert!(perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).is_ok());
+
+        // moving it back in one commit lists the modify before the delete
+        commit.fileops = vec![
+            FileOpsOwned::FileModify("100644".into(), "b".into(), "a/x".into()),
+            FileOpsOwned::FileDelete("b/x".into()),
+        ];
+        assert!(perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).is_ok());
+        commit.fileops = vec![FileOpsOwned::FileDelete("a/x".into())];
+        assert!(perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).is_ok());
+        commit.fileops = vec![
+            FileOpsOwned::FileModify("100644".into(), "b".into(), "b/x".into()),
+        ];
+        assert!(perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).is_ok());
+
+        // but a/x coming back while b/x still exists is a collision
+        commit.fileops = vec![
+            FileOpsOwned::FileModify("100644".into(), "c".into(), "a/x".into()),
+        ];
+        assert!(perform_filter2(false, &mut filter_state, &mut commit, &filter_rules).is_err());
+    }
 }
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 use crate::export_parser::FileOpsOwned;
+use crate::filter::{OverlayContents, VersionedFilterRules};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
@@ -12,6 +14,27 @@ pub struct FilterState {
     pub graph: Vec<Vec<usize>>,
     pub mark_map: Vec<usize>,
     pub contents_hash_map: HashMap<usize, HashMap<u64, u64>>,
+    /// where the repository we are filtering is. needed
+    /// for when we have to read the contents of a blob, ie: .gitmodules
+    pub location: Option<PathBuf>,
+    /// contents of the blobs we have already read, by dataref
+    pub blob_contents: HashMap<String, Vec<u8>>,
+    pub synthesize_gitfiles: bool,
+    /// for every mark, the path and dataref of the .gitignore/.gitattributes
+    /// files that we synthesize from. see synthesize.rs
+    pub synthesize_sources: HashMap<usize, HashMap<String, String>>,
+    /// (destination path, contents) of files we add to the output. see FilterOptions
+    pub overlay: Vec<(String, OverlayContents)>,
+    pub versioned_rules: Vec<VersionedFilterRules>,
+    /// for every versioned rule, whether it applies to the current
+    /// position in the export stream
+    pub versioned_rules_active: Vec<bool>,
+    pub detect_destination_collisions: bool,
+    /// for every destination path, the source path that ended up there
+    pub destination_sources: HashMap<String, String>,
+    /// (destination, first source, second source) of the first
+    /// time two different source paths ended up at the same destination
+    pub destination_collision: Option<(String, String, String)>,
 }
 
 fn calculate_hash<T: Hash>(t: &T) -> u64 {
@@ -21,6 +44,35 @@ fn calculate_hash<T: Hash>(t: &T) -> u64 {
 }
 
 impl FilterState {
+    pub fn set_versioned_rules(&mut self, versioned_rules: Vec<VersionedFilterRules>) {
+        // rules without a since apply from the very start
+        self.versioned_rules_active = versioned_rules.iter()
+            .map(|v| v.since.is_none())
+            .collect();
+        self.versioned_rules = versioned_rules;
+    }
+
+    /// remember that `src` was filtered to `dest`. if a different
+    /// source was already filtered to `dest`, that is a collision
+    pub fn record_destination_source(&mut self, src: String, dest: &str) {
+        match self.destination_sources.get(dest) {
+            Some(existing) => if *existing != src && self.destination_collision.is_none() {
+                self.destination_collision = Some((dest.to_string(), existing.clone(), src));
+            },
+            None => {
+                self.destination_sources.insert(dest.to_string(), src);
+            }
+        }
+    }
+
+    /// `src` was deleted, so it no longer ends up at `dest`. another
+    /// source can end up there from now on without colliding with it
+    pub fn forget_destination_source(&mut self, src: &str, dest: &str) {
+        if self.destination_sources.get(dest).map(|s| s.as_str()) == Some(src) {
+            self.destination_sources.remove(dest);
+        }
+    }
+
     pub fn using_commit_with_contents(
         &mut self,
         mark: usize,
@@ -47,6 +99,7 @@ impl FilterState {
         for fileop in contents {
             let hash_key_str = match &fileop {
                 FileOpsOwned::FileModify(_, _, p) => p,
+                FileOpsOwned::FileModifyInline(_, _, p) => p,
                 FileOpsOwned::FileDelete(p) => p,
                 FileOpsOwned::FileCopy(_, p) => p,
                 FileOpsOwned::FileRename(_, p) => p,
@@ -71,6 +124,7 @@ impl FilterState {
                 for fileop in contents {
                     let hash_key = match &fileop {
                         FileOpsOwned::FileModify(_, _, p) => p,
+                        FileOpsOwned::FileModifyInline(_, _, p) => p,
                         FileOpsOwned::FileDelete(p) => p,
                         FileOpsOwned::FileCopy(_, p) => p,
                         FileOpsOwned::FileRename(_, p) => p,
@@ -99,6 +153,22 @@ impl FilterState {
         }
     }
 
+    /// true if the filtered contents at this mark still
+    /// have this path, ie: it was modified at some point and not deleted since
+    pub fn mark_has_path(&self, mark: usize, path: &str) -> bool {
+        let parent_hash_map = match self.contents_hash_map.get(&mark) {
+            Some(m) => m,
+            None => return false,
+        };
+        let path = path.to_string();
+        let hash_key = calculate_hash(&&path[..]);
+        let deleted_value = calculate_hash(&FileOpsOwned::FileDelete(path));
+        match parent_hash_map.get(&hash_key) {
+            Some(hash_value) => *hash_value != deleted_value,
+            None => false,
+        }
+    }
+
     pub fn extend_mark_map_until(&mut self, mark: usize) {
         let len = self.mark_map.len();
         let desired_len = mark + 1;
@@ -0,0 +1,199 @@
+use super::filter::FilterRules;
+use super::filter::should_use_file;
+use std::process::Stdio;
+use std::{path::Path, io};
+use crate::ioerre;
+
+/// git only looks at the .gitmodules file at the root of the tree
+pub const GITMODULES_PATH: &str = ".gitmodules";
+
+/// the mode git uses for gitlinks (submodule entries) in a tree
+pub const GITLINK_MODE: &str = "160000";
+
+/// a gitlink is a single entry like `lib/sub`, but from the point
+/// of view of a repo file it is a directory. So we check
+/// the path as if it had a trailing slash to let rules like `lib/sub/` apply,
+/// and then strip the slash back off of whatever the path was renamed to.
+pub fn should_use_gitlink(
+    path: &mut String,
+    filter_rules: &FilterRules,
+    default_include: bool,
+) -> bool {
+    let pathlen = path.len();
+    let is_quoted = pathlen > 1 && path.starts_with('"') && path.ends_with('"');
+    let mut dir_path = if is_quoted {
+        format!("{}/\"", &path[0..(pathlen - 1)])
+    } else {
+        format!("{}/", path)
+    };
+    if !should_use_file(&mut dir_path, filter_rules, default_include) {
+        return false;
+    }
+
+    let has_quotes = dir_path.len() > 1 && dir_path.starts_with('"') && dir_path.ends_with('"');
+    let mut new_path = if has_quotes {
+        dir_path[1..(dir_path.len() - 1)].to_string()
+    } else {
+        dir_path
+    };
+    if new_path.ends_with('/') {
+        new_path.pop();
+    }
+    // a submodule cannot live at the root of a repository,
+    // so if it was renamed to the root, we have to drop it
+    if new_path.is_empty() {
+        return false;
+    }
+    *path = if has_quotes { format!("\"{}\"", new_path) } else { new_path };
+    true
+}
+
+/// given the contents of a .gitmodules file, apply the filter rules
+/// to the path of every submodule section. sections whose path
+/// gets filtered out are removed, and sections whose path gets renamed
+/// have their path value rewritten. returns None if no
+/// submodule sections remain.
+pub fn rewrite_gitmodules(
+    contents: &str,
+    filter_rules: &FilterRules,
+    default_include: bool,
+) -> Option<String> {
+    // first split the file into sections. anything before
+    // the first section header (ie: comments) is kept as is
+    let mut preamble = vec![];
+    let mut sections: Vec<Vec<&str>> = vec![];
+    for line in contents.lines() {
+        if line.trim_start().starts_with('[') {
+            sections.push(vec![line]);
+        } else if let Some(current) = sections.last_mut() {
+            current.push(line);
+        } else {
+            preamble.push(line);
+        }
+    }
+
+    let mut out = String::new();
+    for line in preamble {
+        out.push_str(line);
+        out.push('\n');
+    }
+    let mut kept_a_submodule = false;
+    for section in sections {
+        let mut new_section = String::new();
+        let mut keep_section = true;
+        for line in section.iter() {
+            match split_path_line(line) {
+                Some((key_part, path)) => {
+                    let mut new_path = path.to_string();
+                    if should_use_gitlink(&mut new_path, filter_rules, default_include) {
+                        new_section.push_str(key_part);
+                        new_section.push_str(&new_path);
+                    } else {
+                        keep_section = false;
+                        break;
+                    }
+                }
+                None => new_section.push_str(line),
+            }
+            new_section.push('\n');
+        }
+        if keep_section {
+            if section[0].trim_start().starts_with("[submodule") {
+                kept_a_submodule = true;
+            }
+            out.push_str(&new_section);
+        }
+    }
+
+    if kept_a_submodule {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+/// if this is a `path = something` line, split it into
+/// the `path = ` part, and the something
+fn split_path_line(line: &str) -> Option<(&str, &str)> {
+    let equals_index = line.find('=')?;
+    if line[0..equals_index].trim() != "path" {
+        return None;
+    }
+    let after_equals = &line[(equals_index + 1)..];
+    let value_start = equals_index + 1 + (after_equals.len() - after_equals.trim_start().len());
+    let value = line[value_start..].trim_end();
+    if value.is_empty() {
+        return None;
+    }
+    Some((&line[0..value_start], value))
+}
+
+/// read the contents of a blob from the repository at location.
+/// the dataref must be something git can resolve, ie: a sha, not a mark.
+pub fn read_blob<P: AsRef<Path>>(
+    dataref: &str,
+    location: Option<P>,
+) -> io::Result<Vec<u8>> {
+    let exe_and_args = ["git", "cat-file", "blob", dataref];
+    let child = exechelper::spawn_with_env_ex2(
+        &exe_and_args,
+        &[], &[],
+        location,
+        Some(Stdio::null()),
+        Some(Stdio::null()),
+        Some(Stdio::piped()),
+    )?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return ioerre!("Failed to read blob {}", dataref);
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::filter::FilterRule;
+
+    const GITMODULES: &str = "[submodule \"a\"]\n\tpath = lib/a\n\turl = https://example.com/a.git\n[submodule \"b\"]\n\tpath = lib/b\n\turl = https://example.com/b.git\n";
+
+    #[test]
+    fn gitlink_matches_directory_rules() {
+        let filter_rules = vec![
+            FilterRule::FilterRulePathRename("lib/a/".into(), "vendor/a/".into()),
+        ];
+        let mut path = "lib/a".to_string();
+        assert!(should_use_gitlink(&mut path, &filter_rules, false));
+        assert_eq!(path, "vendor/a");
+
+        let mut path = "lib/ab".to_string();
+        assert!(!should_use_gitlink(&mut path, &filter_rules, false));
+    }
+
+    #[test]
+    fn gitlink_cannot_be_renamed_to_root() {
+        let filter_rules = vec![
+            FilterRule::FilterRulePathRename("lib/a/".into(), "".into()),
+        ];
+        let mut path = "lib/a".to_string();
+        assert!(!should_use_gitlink(&mut path, &filter_rules, false));
+    }
+
+    #[test]
+    fn gitmodules_sections_get_renamed_and_dropped() {
+        let filter_rules = vec![
+            FilterRule::FilterRulePathRename("lib/a/".into(), "a/".into()),
+        ];
+        let rewritten = rewrite_gitmodules(GITMODULES, &filter_rules, false).unwrap();
+        let expected = "[submodule \"a\"]\n\tpath = a\n\turl = https://example.com/a.git\n";
+        assert_eq!(rewritten, expected);
+    }
+
+    #[test]
+    fn gitmodules_removed_if_no_submodules_remain() {
+        let filter_rules = vec![
+            FilterRule::FilterRulePathInclude("src/".into()),
+        ];
+        assert_eq!(rewrite_gitmodules(GITMODULES, &filter_rules, false), None);
+    }
+}
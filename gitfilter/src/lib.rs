@@ -2,6 +2,8 @@
 pub mod export_parser;
 pub mod filter;
 pub mod filter_state;
+pub mod gitmodules;
+pub mod synthesize;
 
 
 /// used to make a simple io error with a string formatted message
@@ -0,0 +1,434 @@
+use super::export_parser::{FileOpsOwned, StructuredCommit};
+use super::filter::{FilterRules, FilterRule};
+use super::filter::{should_use_file, get_blob_contents, parents_have_path};
+use super::filter_state::FilterState;
+use std::collections::HashMap;
+
+pub const GITIGNORE_PATH: &str = ".gitignore";
+pub const GITATTRIBUTES_PATH: &str = ".gitattributes";
+
+/// the files that we synthesize at the root of the output
+pub const SYNTHESIZED_FILES: [&str; 2] = [GITIGNORE_PATH, GITATTRIBUTES_PATH];
+
+/// get every directory that gets included, and where it ends up
+/// as a (src, dest) pair. files are not considered
+/// because we only carry over patterns that apply within directories.
+pub fn get_included_dirs(filter_rules: &FilterRules) -> Vec<(String, String)> {
+    let mut out = vec![];
+    for filter_rule in filter_rules {
+        match filter_rule {
+            FilterRule::FilterRulePathInclude(src) => {
+                if src.ends_with('/') {
+                    out.push((src.clone(), src.clone()));
+                }
+            }
+            FilterRule::FilterRulePathRename(src, dest) => {
+                if src.is_empty() || src.ends_with('/') {
+                    out.push((src.clone(), dest.clone()));
+                }
+            }
+            FilterRule::FilterRulePathExclude(_) => {}
+        }
+    }
+    out
+}
+
+/// if this path is a .gitignore or .gitattributes file,
+/// return which one it is
+pub fn synthesized_file_name(path: &str) -> Option<&'static str> {
+    for file_name in SYNTHESIZED_FILES.iter() {
+        if path == *file_name || path.ends_with(&format!("/{}", file_name)) {
+            return Some(file_name);
+        }
+    }
+    None
+}
+
+/// a .gitignore/.gitattributes file is something we synthesize from
+/// if it is in a directory above one of the included directories (and
+/// does not get included itself), or if it gets included
+/// at the root of the output, in which case we have to combine it
+/// with whatever we synthesize.
+pub fn is_synthesize_source(
+    path: &str,
+    filter_rules: &FilterRules,
+    default_include: bool,
+    included_dirs: &[(String, String)],
+) -> bool {
+    let file_name = match synthesized_file_name(path) {
+        Some(f) => f,
+        None => return false,
+    };
+    let mut new_path = path.to_string();
+    if should_use_file(&mut new_path, filter_rules, default_include) {
+        return new_path == file_name;
+    }
+    let source_dir = &path[0..(path.len() - file_name.len())];
+    included_dirs.iter().any(|(src, _)| {
+        src.len() > source_dir.len() && src.starts_with(source_dir)
+    })
+}
+
+/// match a single path segment against a gitignore style glob.
+/// supports `*`, `?`, `[...]` character classes, and `\` escapes
+pub fn glob_matches_segment(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_matches_chars(&pattern, &text)
+}
+
+fn glob_matches_chars(pattern: &[char], text: &[char]) -> bool {
+    if pattern.is_empty() {
+        return text.is_empty();
+    }
+    match pattern[0] {
+        '*' => {
+            for i in 0..=text.len() {
+                if glob_matches_chars(&pattern[1..], &text[i..]) {
+                    return true;
+                }
+            }
+            false
+        }
+        '?' => !text.is_empty() && glob_matches_chars(&pattern[1..], &text[1..]),
+        '[' => {
+            let close = match pattern.iter().skip(2).position(|c| *c == ']') {
+                Some(i) => i + 2,
+                // no closing bracket, so treat it literally
+                None => return !text.is_empty() && text[0] == '['
+                    && glob_matches_chars(&pattern[1..], &text[1..]),
+            };
+            if text.is_empty() {
+                return false;
+            }
+            let mut class = &pattern[1..close];
+            let negate = class[0] == '!' || class[0] == '^';
+            if negate {
+                class = &class[1..];
+            }
+            let mut in_class = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    if class[i] <= text[0] && text[0] <= class[i + 2] {
+                        in_class = true;
+                    }
+                    i += 3;
+                } else {
+                    if class[i] == text[0] {
+                        in_class = true;
+                    }
+                    i += 1;
+                }
+            }
+            in_class != negate && glob_matches_chars(&pattern[(close + 1)..], &text[1..])
+        }
+        '\\' if pattern.len() > 1 => {
+            !text.is_empty() && text[0] == pattern[1]
+                && glob_matches_chars(&pattern[2..], &text[1..])
+        }
+        c => !text.is_empty() && text[0] == c && glob_matches_chars(&pattern[1..], &text[1..]),
+    }
+}
+
+/// rewrite a single pattern that was found in a directory above `src_rel`
+/// (relative to the directory of the file the pattern came from) so that
+/// it applies to `dest`. returns None if the pattern has nothing to do with src_rel.
+pub fn rewrite_pattern(pattern: &str, src_rel: &str, dest: &str) -> Option<String> {
+    // a pattern without a slash (other than a trailing one)
+    // matches at any depth, so it applies to anything in src_rel
+    let is_anchored = pattern.trim_end_matches('/').contains('/');
+    if !is_anchored {
+        if dest.is_empty() {
+            return Some(pattern.to_string());
+        }
+        return Some(format!("/{}**/{}", dest, pattern));
+    }
+
+    // otherwise the pattern is relative to the directory it came from
+    // so it must match every segment of src_rel, and whatever is left
+    // of it is what applies within dest
+    let mut rest = pattern.trim_start_matches('/');
+    for src_segment in src_rel.split('/').filter(|s| !s.is_empty()) {
+        if rest == "**" || rest.starts_with("**/") {
+            return Some(format!("/{}{}", dest, rest));
+        }
+        let (segment, remaining) = match rest.find('/') {
+            Some(i) => (&rest[0..i], &rest[(i + 1)..]),
+            // the pattern matches src_rel itself, or something above it
+            None => return None,
+        };
+        if !glob_matches_segment(segment, src_segment) {
+            return None;
+        }
+        rest = remaining;
+    }
+    if rest.is_empty() {
+        return None;
+    }
+    Some(format!("/{}{}", dest, rest))
+}
+
+/// given the contents of a .gitignore or .gitattributes file that lives
+/// in source_dir, return only the lines that apply to the included
+/// directories beneath it, with their patterns rewritten to
+/// where those directories end up.
+pub fn rewrite_patterns(
+    contents: &str,
+    source_dir: &str,
+    included_dirs: &[(String, String)],
+    is_attributes: bool,
+) -> Vec<String> {
+    let mut out: Vec<String> = vec![];
+    for line in contents.lines() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // macro attributes are not patterns, and apply everywhere
+        if is_attributes && line.starts_with("[attr]") {
+            if !out.iter().any(|l| l == line) {
+                out.push(line.to_string());
+            }
+            continue;
+        }
+        // for attributes, only the first word is the pattern
+        let (pattern, rest) = if is_attributes {
+            match line.find(char::is_whitespace) {
+                Some(i) => line.split_at(i),
+                None => (line, ""),
+            }
+        } else {
+            (line, "")
+        };
+        let (negate, pattern) = if let Some(stripped) = pattern.strip_prefix('!') {
+            ("!", stripped)
+        } else {
+            ("", pattern)
+        };
+        for (src, dest) in included_dirs {
+            if src.len() <= source_dir.len() || !src.starts_with(source_dir) {
+                continue;
+            }
+            let src_rel = &src[source_dir.len()..];
+            if let Some(new_pattern) = rewrite_pattern(pattern, src_rel, dest) {
+                let new_line = format!("{}{}{}", negate, new_pattern, rest);
+                if !out.contains(&new_line) {
+                    out.push(new_line);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// track the .gitignore/.gitattributes files we synthesize from
+/// for this commit, based on what its parents had, and what this
+/// commit changes. this must be called with the fileops before they get filtered.
+/// returns true if we need to synthesize the files again
+pub fn update_synthesize_sources(
+    filter_state: &mut FilterState,
+    commit: &StructuredCommit,
+    filter_rules: &FilterRules,
+    default_include: bool,
+) -> bool {
+    let included_dirs = get_included_dirs(filter_rules);
+    let mut sources = HashMap::new();
+    for p in commit.merges.iter().rev() {
+        if let Some(parent_sources) = filter_state.synthesize_sources.get(p) {
+            for (path, dataref) in parent_sources {
+                sources.insert(path.clone(), dataref.clone());
+            }
+        }
+    }
+
+    // merges can combine sources from several parents, so
+    // its easier to just always synthesize for them
+    let mut changed = commit.merges.len() > 1;
+    for op in commit.fileops.iter() {
+        match op {
+            FileOpsOwned::FileModify(_, dataref, path)
+                if is_synthesize_source(path, filter_rules, default_include, &included_dirs) => {
+                sources.insert(path.clone(), dataref.clone());
+                changed = true;
+            }
+            FileOpsOwned::FileDelete(path)
+                if is_synthesize_source(path, filter_rules, default_include, &included_dirs) => {
+                sources.remove(path);
+                changed = true;
+            }
+            FileOpsOwned::FileDeleteAll => {
+                sources.clear();
+                changed = true;
+            }
+            _ => {}
+        }
+    }
+    filter_state.synthesize_sources.insert(commit.mark, sources);
+    changed
+}
+
+/// replace whatever .gitignore/.gitattributes the commit has at the
+/// root of the output with one synthesized from its sources.
+/// this must be called with the fileops after they were filtered, and before
+/// the commit merges get resolved.
+pub fn add_synthesized_files(
+    filter_state: &mut FilterState,
+    commit: &mut StructuredCommit,
+    filter_rules: &FilterRules,
+    default_include: bool,
+) {
+    let included_dirs = get_included_dirs(filter_rules);
+    let sources = match filter_state.synthesize_sources.get(&commit.mark) {
+        Some(s) => s.clone(),
+        None => return,
+    };
+    for file_name in SYNTHESIZED_FILES.iter() {
+        let mut ancestor_sources = vec![];
+        let mut own_source = None;
+        for (path, dataref) in sources.iter() {
+            if synthesized_file_name(path) != Some(file_name) {
+                continue;
+            }
+            let mut new_path = path.clone();
+            if should_use_file(&mut new_path, filter_rules, default_include) {
+                own_source = Some(dataref);
+            } else {
+                ancestor_sources.push((path, dataref));
+            }
+        }
+        // start with the least specific patterns so that
+        // deeper ones take precedence
+        ancestor_sources.sort_by_key(|(path, _)| (path.matches('/').count(), path.to_string()));
+
+        let mut contents = String::new();
+        for (path, dataref) in ancestor_sources {
+            let text = match get_blob_contents(filter_state, dataref) {
+                Some(t) => t,
+                None => continue,
+            };
+            let source_dir = &path[0..(path.len() - file_name.len())];
+            let is_attributes = *file_name == GITATTRIBUTES_PATH;
+            let lines = rewrite_patterns(&text, source_dir, &included_dirs, is_attributes);
+            if lines.is_empty() {
+                continue;
+            }
+            contents.push_str("# from ");
+            contents.push_str(path);
+            contents.push('\n');
+            for line in lines {
+                contents.push_str(&line);
+                contents.push('\n');
+            }
+        }
+        if let Some(dataref) = own_source {
+            if let Some(text) = get_blob_contents(filter_state, dataref) {
+                contents.push_str(&text);
+            }
+        }
+
+        commit.fileops.retain(|op| match op {
+            FileOpsOwned::FileModify(_, _, p) |
+            FileOpsOwned::FileDelete(p) => p != file_name,
+            _ => true,
+        });
+        if !contents.is_empty() {
+            let fileop = FileOpsOwned::FileModifyInline(
+                "100644".into(), contents.into_bytes(), file_name.to_string());
+            commit.fileops.push(fileop);
+        } else if parents_have_path(filter_state, &commit.merges, file_name) {
+            commit.fileops.push(FileOpsOwned::FileDelete(file_name.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rename_to_root() -> Vec<(String, String)> {
+        vec![("lib/cool-lib/".into(), "".into())]
+    }
+
+    #[test]
+    fn glob_segments_work() {
+        assert!(glob_matches_segment("*", "cool-lib"));
+        assert!(glob_matches_segment("cool-*", "cool-lib"));
+        assert!(glob_matches_segment("c?ol-lib", "cool-lib"));
+        assert!(glob_matches_segment("[a-c]ool-lib", "cool-lib"));
+        assert!(!glob_matches_segment("[!c]ool-lib", "cool-lib"));
+        assert!(!glob_matches_segment("other", "cool-lib"));
+    }
+
+    #[test]
+    fn unanchored_patterns_are_kept() {
+        let lines = rewrite_patterns("*.log\n# comment\n\nbuild/\n", "", &rename_to_root(), false);
+        assert_eq!(lines, vec!["*.log", "build/"]);
+
+        let included_dirs = vec![("lib/cool-lib/".into(), "vendor/cool/".into())];
+        let lines = rewrite_patterns("*.log\n", "lib/", &included_dirs, false);
+        assert_eq!(lines, vec!["/vendor/cool/**/*.log"]);
+    }
+
+    #[test]
+    fn anchored_patterns_are_rewritten_or_dropped() {
+        let contents = "/lib/cool-lib/target/\nlib/*/tmp\n/lib/other/x\n/lib/cool-lib\n!lib/cool-lib/keep.txt\n";
+        let lines = rewrite_patterns(contents, "", &rename_to_root(), false);
+        assert_eq!(lines, vec!["/target/", "/tmp", "!/keep.txt"]);
+    }
+
+    #[test]
+    fn attributes_keep_their_attributes() {
+        let contents = "[attr]binary -diff -merge -text\n*.png binary\n/lib/cool-lib/*.sh  text eol=lf\n/lib/other/*.sh text\n";
+        let lines = rewrite_patterns(contents, "", &rename_to_root(), true);
+        assert_eq!(lines, vec![
+            "[attr]binary -diff -merge -text",
+            "*.png binary",
+            "/*.sh  text eol=lf",
+        ]);
+    }
+
+    #[test]
+    fn sources_are_files_above_included_dirs() {
+        let filter_rules = vec![
+            FilterRule::FilterRulePathRename("lib/cool-lib/".into(), "".into()),
+        ];
+        let included_dirs = get_included_dirs(&filter_rules);
+        let is_source = |path: &str| is_synthesize_source(path, &filter_rules, false, &included_dirs);
+        assert!(is_source(".gitignore"));
+        assert!(is_source("lib/.gitattributes"));
+        assert!(is_source("lib/cool-lib/.gitignore"));
+        assert!(!is_source("lib/cool-lib/sub/.gitignore"));
+        assert!(!is_source("other/.gitignore"));
+        assert!(!is_source("lib/README.md"));
+    }
+
+    #[test]
+    fn synthesized_files_combine_sources() {
+        let mut filter_state = FilterState::default();
+        filter_state.blob_contents.insert("aaa".into(), b"*.log\n/other/\n".to_vec());
+        filter_state.blob_contents.insert("bbb".into(), b"/target/\n".to_vec());
+        let filter_rules = vec![
+            FilterRule::FilterRulePathRename("lib/cool-lib/".into(), "".into()),
+        ];
+        let mut commit = StructuredCommit::default();
+        commit.mark = 1;
+        commit.fileops = vec![
+            FileOpsOwned::FileModify("100644".into(), "aaa".into(), ".gitignore".into()),
+            FileOpsOwned::FileModify("100644".into(), "bbb".into(), "lib/cool-lib/.gitignore".into()),
+        ];
+        assert!(update_synthesize_sources(&mut filter_state, &commit, &filter_rules, false));
+        let mut commit = commit;
+        commit.fileops = vec![
+            FileOpsOwned::FileModify("100644".into(), "bbb".into(), ".gitignore".into()),
+        ];
+        add_synthesized_files(&mut filter_state, &mut commit, &filter_rules, false);
+
+        let expected = vec![FileOpsOwned::FileModifyInline(
+            "100644".into(),
+            b"# from .gitignore\n*.log\n/target/\n".to_vec(),
+            ".gitignore".into(),
+        )];
+        assert_eq!(commit.fileops, expected);
+    }
+}
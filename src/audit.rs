@@ -0,0 +1,213 @@
+use super::cli::MgtCommandAudit;
+use super::die;
+use super::git_helpers3;
+use super::repo_file;
+use super::repo_file::RepoFile;
+use super::verify;
+use super::workspace;
+use gitfilter::filter::FilterRules;
+use std::collections::BTreeMap;
+
+/// everything we need to know about a repo file to audit it
+pub struct AuditedRepoFile {
+    /// how we refer to this repo file in the report
+    pub name: String,
+    /// repo files that push to the same destination
+    /// have the same destination
+    pub destination: String,
+    pub filter_rules: FilterRules,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct AuditReport {
+    /// pairs of (path, names of the repo files that include that path)
+    pub claimed_by_several: Vec<(String, Vec<String>)>,
+    /// paths under a watched root that no repo file includes
+    pub unclaimed: Vec<String>,
+    /// (destination, destination path, [(repo file name, source path)])
+    /// for every destination path that more than one source path maps to
+    pub colliding_destinations: Vec<(String, String, Vec<(String, String)>)>,
+}
+
+impl AuditReport {
+    pub fn has_problems(&self) -> bool {
+        !self.claimed_by_several.is_empty()
+            || !self.unclaimed.is_empty()
+            || !self.colliding_destinations.is_empty()
+    }
+}
+
+/// the remote is where a repo file pushes to. if it doesnt have
+/// a remote, the repo name is the closest thing we have to that
+pub fn get_destination(repo_file: &RepoFile, repo_file_name: &str) -> String {
+    match (&repo_file.remote_repo, &repo_file.repo_name) {
+        (Some(remote), _) => remote.clone(),
+        (None, Some(name)) => name.clone(),
+        (None, None) => repo_file_name.to_string(),
+    }
+}
+
+pub fn audit_repo_files(
+    all_files: &Vec<String>,
+    gitlinks: &[String],
+    repo_files: &[AuditedRepoFile],
+    watched_roots: &[String],
+) -> AuditReport {
+    // for every source path, which repo files include it
+    let mut claimed_by: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+    // for every (destination, destination path), which (repo file, source path)
+    // ends up there
+    let mut destinations: BTreeMap<(&str, String), Vec<(String, String)>> = BTreeMap::new();
+    for audited in repo_files {
+        let remaining = verify::apply_expected_gitfilter(all_files, gitlinks, &audited.filter_rules);
+        for (index, dest_path) in remaining {
+            claimed_by.entry(index).or_default().push(&audited.name);
+            destinations.entry((&audited.destination, dest_path)).or_default()
+                .push((audited.name.clone(), all_files[index].clone()));
+        }
+    }
+
+    let mut report = AuditReport::default();
+    for (index, names) in claimed_by.iter() {
+        if names.len() > 1 {
+            let names = names.iter().map(|n| n.to_string()).collect();
+            report.claimed_by_several.push((all_files[*index].clone(), names));
+        }
+    }
+    for (index, path) in all_files.iter().enumerate() {
+        let is_watched = watched_roots.iter().any(|root| path.starts_with(root.as_str()));
+        if is_watched && !claimed_by.contains_key(&index) {
+            report.unclaimed.push(path.clone());
+        }
+    }
+    for ((destination, dest_path), sources) in destinations {
+        if sources.len() > 1 {
+            report.colliding_destinations.push((destination.to_string(), dest_path, sources));
+        }
+    }
+    report
+}
+
+pub fn print_audit_report(report: &AuditReport) {
+    if !report.has_problems() {
+        println!("No problems found");
+        return;
+    }
+    if !report.claimed_by_several.is_empty() {
+        println!("\nPaths included by more than one repo file:");
+        for (path, names) in report.claimed_by_several.iter() {
+            println!("{}: {}", path, names.join(", "));
+        }
+    }
+    if !report.unclaimed.is_empty() {
+        println!("\nPaths under a watched root that no repo file includes:");
+        for path in report.unclaimed.iter() {
+            println!("{}", path);
+        }
+    }
+    if !report.colliding_destinations.is_empty() {
+        println!("\nDestination paths that more than one path maps to:");
+        for (destination, dest_path, sources) in report.colliding_destinations.iter() {
+            println!("{} in {}:", dest_path, destination);
+            for (name, src_path) in sources {
+                println!("    {} ({})", src_path, name);
+            }
+        }
+    }
+}
+
+pub fn run_audit(cmd: &mut MgtCommandAudit) {
+    let workspace = match workspace::find_current_workspace() {
+        Ok(w) => w,
+        Err(e) => die!("{}", e),
+    };
+    let repo_file_paths = match workspace.get_repo_file_paths(cmd.group.as_deref()) {
+        Ok(p) => p,
+        Err(e) => die!("{}", e),
+    };
+
+    let mut audited = vec![];
+    for path in repo_file_paths {
+        let name = match path.strip_prefix(&workspace.root) {
+            Ok(p) => p.to_string_lossy().to_string(),
+            Err(_) => path.to_string_lossy().to_string(),
+        };
+        let repo_file = repo_file::parse_repo_file_from_toml_path(&path);
+        let mut file_ops = verify::get_vec_of_current_file_ops_with_order(&repo_file, true);
+        audited.push(AuditedRepoFile {
+            destination: get_destination(&repo_file, &name),
+            filter_rules: verify::make_filter_rules(&mut file_ops),
+            name,
+        });
+    }
+
+    let all_files: Vec<String> = match git_helpers3::get_all_files_in_repo() {
+        Ok(text) => text.split('\n').map(|line| line.to_string()).collect(),
+        Err(e) => die!("Failed to get all files in git repo:\n{}", e),
+    };
+    let gitlinks = match git_helpers3::get_all_gitlinks_in_repo() {
+        Ok(g) => g,
+        Err(e) => die!("Failed to get submodules in git repo:\n{}", e),
+    };
+
+    let report = audit_repo_files(&all_files, &gitlinks, &audited, &workspace.watch);
+    print_audit_report(&report);
+    if report.has_problems() {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gitfilter::filter::FilterRule;
+
+    #[test]
+    fn audit_finds_overlaps_unclaimed_and_collisions() {
+        let all_files: Vec<String> = vec![
+            "shared/utils/a.rs".into(),
+            "libs/foo/lib.rs".into(),
+            "libs/bar/lib.rs".into(),
+            "libs/baz/lib.rs".into(),
+        ];
+        let repo_files = vec![
+            AuditedRepoFile {
+                name: "foo.rf".into(),
+                destination: "foo.git".into(),
+                filter_rules: vec![
+                    FilterRule::FilterRulePathInclude("shared/utils/".into()),
+                    FilterRule::FilterRulePathRename("libs/foo/".into(), "".into()),
+                ],
+            },
+            AuditedRepoFile {
+                name: "bar.rf".into(),
+                destination: "bar.git".into(),
+                filter_rules: vec![
+                    FilterRule::FilterRulePathInclude("shared/utils/".into()),
+                    FilterRule::FilterRulePathRename("libs/bar/".into(), "".into()),
+                ],
+            },
+            AuditedRepoFile {
+                name: "bar2.rf".into(),
+                destination: "bar.git".into(),
+                filter_rules: vec![
+                    FilterRule::FilterRulePathRename("libs/baz/".into(), "".into()),
+                ],
+            },
+        ];
+        let report = audit_repo_files(&all_files, &[], &repo_files, &["libs/".into()]);
+        assert_eq!(report.claimed_by_several, vec![
+            ("shared/utils/a.rs".to_string(), vec!["foo.rf".to_string(), "bar.rf".to_string()]),
+        ]);
+        assert!(report.unclaimed.is_empty());
+        assert_eq!(report.colliding_destinations, vec![
+            ("bar.git".to_string(), "lib.rs".to_string(), vec![
+                ("bar.rf".to_string(), "libs/bar/lib.rs".to_string()),
+                ("bar2.rf".to_string(), "libs/baz/lib.rs".to_string()),
+            ]),
+        ]);
+
+        let report = audit_repo_files(&all_files, &[], &repo_files[0..1], &["libs/".into()]);
+        assert_eq!(report.unclaimed, vec!["libs/bar/lib.rs".to_string(), "libs/baz/lib.rs".to_string()]);
+    }
+}
@@ -270,7 +270,13 @@ impl From<RawBlobSummary> for RawBlobSummaryEndStateWithoutPath {
     }
 }
 
-impl RawBlobSummary {}
+impl RawBlobSummary {
+    /// true if either side of this blob is a gitlink (a submodule entry)
+    pub fn is_gitlink(&self) -> bool {
+        let (_, mode_src, mode_dest) = self.src_dest_mode_and_status.into();
+        mode_src == FileMode::GitLink || mode_dest == FileMode::GitLink
+    }
+}
 
 pub fn hex_char_to_u64(c: char) -> u64 {
     match c {
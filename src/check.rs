@@ -2,11 +2,15 @@ use std::path::PathBuf;
 
 use super::git_helpers3;
 use super::git_helpers3::Oid;
+use super::jobs;
+use super::ledger;
+use super::squash;
 use super::exec_helpers;
 use super::repo_file::RepoFile;
 use super::die;
 use super::topbase;
 use super::repo_file;
+use super::workspace;
 use super::cli::MgtCommandCheck;
 use super::core::get_all_repo_files;
 use git_helpers3::{RawBlobSummary, CommitWithBlobs};
@@ -16,6 +20,22 @@ pub struct Checker<'a> {
     current_branch: String,
     current_is_remote: bool,
     repo_file: &'a RepoFile,
+    /// what gets shown as Current: and Upstream:
+    pub current_display: String,
+    pub upstream_display: String,
+    /// the remote branch gets fetched from this url into `fetch_ref`
+    remote: String,
+    remote_branch: String,
+    fetch_ref: String,
+}
+
+/// everything that checking a repo file found out, so
+/// that it can be printed after the check is done
+pub struct CheckOutcome {
+    pub file: String,
+    /// None if the repo file could not be read
+    pub branches: Option<(String, String)>,
+    pub updates: Result<(Vec<Oid>, Vec<String>), String>,
 }
 
 impl<'a> Checker<'a> {
@@ -26,48 +46,74 @@ impl<'a> Checker<'a> {
         local_branch: Option<S>,
         remote_branch: Option<S>,
         repo_file: &'a RepoFile,
-    ) -> Checker {
-        create_checker(current_is_remote, local_branch, remote_branch, repo_file)
+        fetch_ref: &str,
+    ) -> Checker<'a> {
+        create_checker(current_is_remote, local_branch, remote_branch, repo_file, fetch_ref)
+    }
+
+    pub fn fetch(&self) -> Result<(), String> {
+        git_helpers3::fetch_branch_into(&self.remote, &self.remote_branch, &self.fetch_ref)
+            .map_err(|e| format!("Error fetching {} {}\n{}", self.remote, self.remote_branch, e))
     }
 
+    /// the ids and summaries of the commits that upstream can take from current
     pub fn check_for_updates(
         &self,
         repo_file_path: Option<&str>,
-        should_clean_fetch_head: bool,
         should_summarize: bool,
-    ) {
-        let (commits_to_take, commit_summaries) = check_for_updates(
+    ) -> (Vec<Oid>, Vec<String>) {
+        check_for_updates(
             self.repo_file,
+            repo_file_path,
             &self.upstream_branch,
             &self.current_branch,
             self.current_is_remote,
             should_summarize
-        );
+        )
+    }
+}
 
-        if should_summarize {
-            let command_to_take = match repo_file_path {
-                None => None,
-                Some(file_path) => {
-                    let split_mode = if self.current_is_remote {
-                        "split-in"
-                    } else {
-                        "split-out"
-                    };
-                    // TODO: calculate if it can be topbased/rebased/whatever...
-                    // here we just assume that it can be topbased...
-                    let command_string = "To perform this update you can run: ";
-                    let command_string = format!("\n{}\nmgt {} {} --topbase", command_string, split_mode, file_path);
-                    Some(command_string)
-                }
-            };
-            summarize_updates(command_to_take, commits_to_take, commit_summaries);
-        }
+/// fetch the remote of the repo file into `fetch_ref`, and find which
+/// commits upstream can take. this does not print anything, so it can
+/// run for several repo files at the same time
+pub fn check_repo_file(
+    file: String,
+    fetch_ref: String,
+    current_is_remote: bool,
+    local_branch: Option<String>,
+    remote_branch: Option<String>,
+) -> CheckOutcome {
+    let repo_file = match repo_file::parse_repo_file_from_toml_path_res(&file) {
+        Ok(r) => r,
+        Err(e) => return CheckOutcome { file, branches: None, updates: Err(e.to_string()) },
+    };
+    let checker = Checker::create(current_is_remote, local_branch, remote_branch, &repo_file, &fetch_ref);
+    let branches = Some((checker.current_display.clone(), checker.upstream_display.clone()));
+    let updates = checker.fetch().map(|_| checker.check_for_updates(Some(&file), true));
+    let _ = git_helpers3::delete_ref(&fetch_ref);
+    CheckOutcome { file, branches, updates }
+}
 
-        if should_clean_fetch_head {
-            // TODO: clean fetch head...
-            // hard to do because of gits auto gc?
-        }
+pub fn print_check_outcome(outcome: CheckOutcome, current_is_remote: bool) {
+    println!("---\nChecking {}", outcome.file);
+    if let Some((current, upstream)) = outcome.branches {
+        println!("Current: {}", current);
+        println!("Upstream: {}", upstream);
     }
+    let (commits_to_take, commit_summaries) = match outcome.updates {
+        Ok(updates) => updates,
+        Err(e) => die!("{}", e),
+    };
+    let split_mode = if current_is_remote {
+        "split-in"
+    } else {
+        "split-out"
+    };
+    // TODO: calculate if it can be topbased/rebased/whatever...
+    // here we just assume that it can be topbased...
+    let command_string = "To perform this update you can run: ";
+    let command_string = format!("\n{}\nmgt {} {} --topbase", command_string, split_mode, outcome.file);
+    summarize_updates(Some(command_string), commits_to_take, commit_summaries);
 }
 
 pub fn summarize_updates(
@@ -98,6 +144,16 @@ pub fn blob_path_applies_to_repo_file(
     repo_file: &RepoFile,
     is_remote: bool,
 ) -> bool {
+    // overlay files only exist in the remote repository,
+    // so they are never relevant when comparing to the local repository
+    if is_remote {
+        if let Some(ref overlay) = repo_file.overlay {
+            if overlay.iter().any(|(dest, _)| dest == blob_path) {
+                return false;
+            }
+        }
+    }
+
     let mut blob_matches_include = false;
     let empty_vec = vec![];
     let include_vec = match &repo_file.include {
@@ -118,6 +174,20 @@ pub fn blob_path_applies_to_repo_file(
     for p in include_as_vec.iter().skip(skip_by).step_by(2) {
         paths_to_include.push(p.clone());
     }
+    // versioned rules apply to some part of the history, and
+    // we dont know which part this blob is from, so consider all of them
+    if let Some(ref versioned) = repo_file.versioned {
+        for rules in versioned {
+            if let Some(ref include) = rules.include {
+                paths_to_include.extend(include.iter().cloned());
+            }
+            if let Some(ref include_as) = rules.include_as {
+                for p in include_as.iter().skip(skip_by).step_by(2) {
+                    paths_to_include.push(p.clone());
+                }
+            }
+        }
+    }
     // paths_to_include
     // try to see if it matches any of the include/include_as
     for i in paths_to_include.iter() {
@@ -141,6 +211,20 @@ pub fn blob_path_applies_to_repo_file(
     false
 }
 
+/// same as blob_path_applies_to_repo_file, but a gitlink (submodule)
+/// is evaluated as a directory, so that rules like `lib/sub/` apply to it
+pub fn blob_applies_to_repo_file(
+    blob: &RawBlobSummary,
+    repo_file: &RepoFile,
+    is_remote: bool,
+) -> bool {
+    if blob.is_gitlink() {
+        let dir_path = format!("{}/", blob.path_dest);
+        return blob_path_applies_to_repo_file(&dir_path, repo_file, is_remote);
+    }
+    blob_path_applies_to_repo_file(&blob.path_dest, repo_file, is_remote)
+}
+
 fn get_formatted_remote_or_branch_str(branch_and_remote: &str, is_remote: bool) -> String {
     match is_remote {
         false => branch_and_remote.to_string(),
@@ -169,12 +253,6 @@ fn get_branch_and_remote_from_str(branch_and_remote: &str) -> (&str, &str) {
     (remote.unwrap(), branch.unwrap())
 }
 
-pub fn fetch_branch(remote: &str, branch: &str) {
-    if let Err(e) = git_helpers3::fetch_branch(remote, branch) {
-        die!("Error fetching {} {}\n{}", remote, branch, e);
-    }
-}
-
 // delete FETCH_HEAD and gc
 pub fn _clean_fetch(path_to_repo_root: &PathBuf) -> std::io::Result<bool> {
     let mut fetch_head = PathBuf::from(path_to_repo_root);
@@ -199,14 +277,23 @@ pub fn run_check(cmd: &mut MgtCommandCheck) {
         cmd.remote = true;
     }
 
-    let repo_file_path = if cmd.repo_file.len() < 1 {
-        die!("Must provide repo file path");
+    let use_workspace = cmd.repo_file.len() < 1 || cmd.group.is_some();
+    if use_workspace && cmd.repo_file.len() > 0 {
+        die!("Cannot provide both a repo file path and a --group");
+    }
+    let repo_file_path = if use_workspace {
+        String::new()
     } else {
         cmd.repo_file[0].clone()
     };
 
     let repo_file_pathbuf: PathBuf = repo_file_path.clone().into();
-    let files_to_check = if repo_file_pathbuf.is_file() {
+    let files_to_check = if use_workspace {
+        match workspace::get_workspace_repo_files(cmd.group.as_deref()) {
+            Ok(files) => files.iter().map(|f| f.to_string_lossy().to_string()).collect(),
+            Err(e) => die!("{}", e),
+        }
+    } else if repo_file_pathbuf.is_file() {
         vec![repo_file_path.to_string()]
     } else {
         // iterate over that folder and find all repo files
@@ -221,32 +308,36 @@ pub fn run_check(cmd: &mut MgtCommandCheck) {
         }
     };
 
-    for file in files_to_check {
-        println!("---\nChecking {}", file);
-        let repo_file = repo_file::parse_repo_file_from_toml_path(&file);
-        let current_is_remote = cmd.remote;
-        let checker = Checker::create(
-            current_is_remote,
-            cmd.local_branch.clone(),
-            cmd.remote_branch.clone(),
-            &repo_file
-        );
-        checker.check_for_updates(
-            Some(&file),
-            true,
-            true
-        );
+    let current_is_remote = cmd.remote;
+    let local_branch = cmd.local_branch.clone();
+    let remote_branch = cmd.remote_branch.clone();
+    let check_file = move |(index, file): (usize, String)| {
+        let fetch_ref = format!("refs/mgt/fetch/{}", index);
+        check_repo_file(file, fetch_ref, current_is_remote, local_branch.clone(), remote_branch.clone())
+    };
+    let max_jobs = cmd.jobs.unwrap_or(1);
+    if max_jobs > 1 {
+        // report them together, in order, once they are all done
+        let files = files_to_check.into_iter().enumerate().collect();
+        for outcome in jobs::run_jobs(files, max_jobs, check_file) {
+            print_check_outcome(outcome, current_is_remote);
+        }
+    } else {
+        for file in files_to_check.into_iter().enumerate() {
+            print_check_outcome(check_file(file), current_is_remote);
+        }
     }
 }
 
 /// create the checker struct that is setup and ready
 /// to run the check operation
-pub fn create_checker<S: ToString>(
+pub fn create_checker<'a, S: ToString>(
     current_is_remote: bool,
     local_branch: Option<S>,
     remote_branch: Option<S>,
-    repo_file: &RepoFile,
-) -> Checker {
+    repo_file: &'a RepoFile,
+    fetch_ref: &str,
+) -> Checker<'a> {
     // 'current' is NOT the branch we are currently on
     // but rather its the branch that potentially
     // has the most recent updates
@@ -268,8 +359,8 @@ pub fn create_checker<S: ToString>(
     // whichever is the remote one will be in the format of <uri>?<ref>
     // so we need to know which to be able to split by :
     // checking if upstream should get updates from current
-    println!("Current: {}", get_formatted_remote_or_branch_str(&current, current_is_remote));
-    println!("Upstream: {}", get_formatted_remote_or_branch_str(&upstream, upstream_is_remote));
+    let current_display = get_formatted_remote_or_branch_str(&current, current_is_remote);
+    let upstream_display = get_formatted_remote_or_branch_str(&upstream, upstream_is_remote);
 
     // probably want to have two modes eventually:
     // default is to fetch entire remote branch and then run the git diff-tree, and rev-list
@@ -282,19 +373,28 @@ pub fn create_checker<S: ToString>(
         false => get_branch_and_remote_from_str(current.as_str()),
     };
 
-    // println!("REMOTE AND BRANCH: {}, {}", remote, branch);
-    fetch_branch(remote, branch);
+    let (remote, remote_branch) = (remote.to_string(), branch.to_string());
 
     let upstream_branch = match upstream_is_remote {
-        true => "FETCH_HEAD".to_string(),
+        true => fetch_ref.to_string(),
         false => upstream,
     };
     let current_branch = match current_is_remote {
-        true => "FETCH_HEAD".to_string(),
+        true => fetch_ref.to_string(),
         false => current,
     };
 
-    Checker { upstream_branch, current_branch, current_is_remote, repo_file }
+    Checker {
+        upstream_branch,
+        current_branch,
+        current_is_remote,
+        repo_file,
+        current_display,
+        upstream_display,
+        remote,
+        remote_branch,
+        fetch_ref: fetch_ref.to_string(),
+    }
 }
 
 fn get_current_branch_name<S: ToString>(
@@ -351,6 +451,7 @@ fn get_remote_branch2<S: ToString>(
 /// check if upstream branch needs to get updates from current
 fn check_for_updates(
     repo_file: &RepoFile,
+    repo_file_path: Option<&str>,
     upstream_branch: &str,
     current_branch: &str,
     current_is_remote: bool,
@@ -383,11 +484,28 @@ fn check_for_updates(
         } else {
             b == upstream_branch
         };
-        blob_path_applies_to_repo_file(&c.path_dest, repo_file, this_is_a_remote_blob)
+        blob_applies_to_repo_file(c, repo_file, this_is_a_remote_blob)
+    };
+    // start from the last sync of this repo file if there is one we can use
+    let (local_branch, remote_branch) = if current_is_remote {
+        (upstream_branch, current_branch)
+    } else {
+        (current_branch, upstream_branch)
+    };
+    let last_sync = repo_file_path.and_then(|path| {
+        let path = std::path::Path::new(path);
+        let entries = ledger::read_ledger().ok()?;
+        let ledger_name = ledger::get_ledger_name(path).ok()?;
+        let repo_file_hash = ledger::get_repo_file_hash(path).ok()?;
+        ledger::find_last_sync(&entries, &ledger_name, &repo_file_hash, local_branch, remote_branch)
+    }).or_else(|| squash::find_squashed_fork(local_branch, remote_branch));
+    let topbase_result = match last_sync {
+        Some(entry) => ledger::topbase_from_last_sync(
+            &entry, a_branch, b_branch, current_is_remote, should_use_blob_cb).map(Some),
+        None => topbase::find_a_b_difference2::<CommitWithBlobs, _>(
+            a_branch, b_branch, Some(traverse_at_a_time), hashing_mode, should_rewind, Some(should_use_blob_cb)),
     };
-    let successful_topbase = match topbase::find_a_b_difference2::<CommitWithBlobs, _>(
-        a_branch, b_branch, Some(traverse_at_a_time), hashing_mode, should_rewind, Some(should_use_blob_cb))
-    {
+    let successful_topbase = match topbase_result {
         Ok(s) => if let Some(t) = s { t } else { return (out_ids, out_str) },
         Err(_) => return (out_ids, out_str),
     };
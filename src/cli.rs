@@ -1,7 +1,14 @@
 use gumdrop::Options;
 
+use super::audit::run_audit;
 use super::check::run_check;
 use super::difflog::run_difflog;
+use super::format::run_format;
+use super::init::run_init;
+use super::ledger::run_history;
+use super::lint::run_lint;
+use super::lock;
+use super::lock::RepoLock;
 use super::split_in::run_split_in;
 use super::split_in::run_split_in_as;
 use super::split_out::run_split_out;
@@ -45,6 +52,16 @@ pub struct MgtCommandCheck {
         help = "check updates to/from a specific remote branch instead of what's in the repo file"
     )]
     pub remote_branch: Option<String>,
+    #[options(
+        meta = "NAME",
+        help = "check the repo files of this group of the mgt.toml workspace. If no <repo-file> and no --group is provided, every repo file of the workspace is checked"
+    )]
+    pub group: Option<String>,
+    #[options(
+        meta = "N",
+        help = "fetch and check up to N repo files at the same time, and report them together once all of them are done. Default is 1"
+    )]
+    pub jobs: Option<usize>,
 
     // positional arg: repo_file
     // (its a vec to appease gumdrop cli parser
@@ -135,6 +152,12 @@ pub struct MgtCommandSplit {
     )]
     pub as_subdir: Option<String>,
 
+    #[options(
+        meta = "NAME",
+        help = "split out the repo files of this group of the mgt.toml workspace. If no <repo-file> and no --group is provided, every repo file of the workspace is split out"
+    )]
+    pub group: Option<String>,
+
     // for program use, not by user
     #[options(skip)]
     pub direction: Option<Direction>,
@@ -175,6 +198,111 @@ pub struct MgtCommandVerify {
         help = "provide a list of files to verify from stdin, one file per line. By default we get this list of files for you via:\ngit ls-tree -r HEAD --name-only --full-tree\n You can achieve the default behavior by doing:\n git ls-tree -r HEAD --name-only --full-tree | mgt verify-rf --stdin <PATH/TO/REPOFILE>"
     )]
     pub stdin: bool,
+
+    #[options(
+        help = "print the repo file after resolving its `extends` and `[vars]`, and exit"
+    )]
+    pub show_resolved: bool,
+
+    #[options(
+        help = "for every file, show every rule that matched it, which rule decided whether the file is included, and the result"
+    )]
+    pub explain: bool,
+
+    #[options(
+        meta = "PATH",
+        help = "only explain this path. implies --explain"
+    )]
+    pub path: Option<String>,
+
+    #[options(
+        meta = "COMMITTISH",
+        help = "verify against the files of this commit, branch, or tag instead of HEAD"
+    )]
+    pub rev: Option<String>,
+
+    #[options(help = "also list the files that the repo file does not include")]
+    pub show_excluded: bool,
+    #[options(
+        help = "compare two repo files: mgt verify-rf --diff <old> <new>. Shows which files are newly included, newly excluded, or end up at a different destination"
+    )]
+    pub diff: bool,
+
+    #[options(
+        meta = "COMMITTISH",
+        help = "like --diff, but compare the repo file against the version of it at this commit"
+    )]
+    pub diff_rev: Option<String>,
+
+    #[options(
+        meta = "in|out",
+        help = "out (the default) shows how local files map to the split repository. in shows where the files of the remote_repo/remote_branch (or of --rev) land in the local repository when using split-in, and warns about local files outside of the mapping that would get overwritten"
+    )]
+    pub direction: Option<String>,
+}
+
+#[derive(Debug, Options)]
+pub struct MgtCommandAudit {
+    #[options(short = "h")]
+    pub help: bool,
+
+    #[options(
+        meta = "NAME",
+        help = "only audit the repo files of this group of the mgt.toml workspace"
+    )]
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Options)]
+pub struct MgtCommandLint {
+    #[options(short = "h")]
+    pub help: bool,
+
+    #[options(
+        free,
+        help = "path to your repo file(s). if not provided, lints every repo file of the mgt.toml workspace"
+    )]
+    pub repo_files: Vec<String>,
+}
+
+#[derive(Debug, Options)]
+pub struct MgtCommandFormat {
+    #[options(short = "h")]
+    pub help: bool,
+
+    #[options(
+        free,
+        help = "path to your repo file(s). if not provided, formats every repo file of the mgt.toml workspace"
+    )]
+    pub repo_files: Vec<String>,
+
+    #[options(
+        help = "dont modify any repo file. instead, list the ones that are not formatted, and exit with an error if there are any"
+    )]
+    pub check: bool,
+}
+
+#[derive(Debug, Options)]
+pub struct MgtCommandInit {
+    #[options(short = "h")]
+    pub help: bool,
+
+    #[options(
+        help = "specify a maximum number of attempts when being asked an interactive question. Default is infinite. This is useful only for scripts to prevent them from hanging"
+    )]
+    pub max_interactive_attempts: Option<usize>,
+}
+
+#[derive(Debug, Options)]
+pub struct MgtCommandHistory {
+    #[options(short = "h")]
+    pub help: bool,
+
+    #[options(
+        free,
+        help = "path to your repo file(s). if not provided, lists the syncs of every repo file"
+    )]
+    pub repo_files: Vec<String>,
 }
 
 #[derive(Debug, Options)]
@@ -205,6 +333,23 @@ pub struct MgtCommandSync {
         help = "only show the summary of the potential sync, but do not perform any filter, pull, or push operations"
     )]
     pub summary_only: bool,
+
+    #[options(
+        meta = "NAME",
+        help = "sync the repo files of this group of the mgt.toml workspace. If no repo files and no --group is provided, every repo file of the workspace is synced"
+    )]
+    pub group: Option<String>,
+
+    #[options(
+        help = "choose which commits to push or pull, and in which order, in a full screen terminal UI instead of answering numbered questions"
+    )]
+    pub tui: bool,
+
+    #[options(
+        meta = "N",
+        help = "fetch and analyze up to N repo files at the same time before syncing them. You are still asked about each repo file one at a time, in order. Default is 1"
+    )]
+    pub jobs: Option<usize>,
 }
 
 #[derive(Debug, Options)]
@@ -254,6 +399,29 @@ pub enum MgtSubcommands {
 
     #[options(help = "alias for verify-repo-file")]
     VerifyRf(MgtCommandVerify),
+
+    #[options(
+        help = "find paths that several repo files of the workspace include, paths that none of them include, and destination paths that collide"
+    )]
+    Audit(MgtCommandAudit),
+
+    #[options(
+        help = "check your repo file(s) for mistakes, ie: directories without a trailing slash, or keys that get ignored"
+    )]
+    LintRf(MgtCommandLint),
+
+    #[options(
+        help = "rewrite your repo file(s) into a canonical form: sorted sections, trailing slashes on directories, no duplicate entries"
+    )]
+    FmtRf(MgtCommandFormat),
+
+    #[options(
+        help = "interactively create a new repo file by picking a remote repository and the directories to split out"
+    )]
+    InitRf(MgtCommandInit),
+
+    #[options(help = "list every successful sync that was recorded in refs/mgt/ledger")]
+    History(MgtCommandHistory),
 }
 
 pub fn get_version_str() -> String {
@@ -289,6 +457,7 @@ pub fn print_usage<A: AsRef<impl Options>>(
                 "num-commits",
                 "--rebase",
                 "--topbase",
+                "group",
             ]),
         )
     } else if cmd_name.contains("split-out") {
@@ -302,11 +471,11 @@ pub fn print_usage<A: AsRef<impl Options>>(
     } else if cmd_name.contains("split-in-as") {
         let p_desc = Some("    <git-repo-uri>    a valid git url of the repository to split in");
         let desc = "fetch and rewrite a remote repository's history onto a new branch and into the --as <subdirectory>";
-        (p_desc, desc, Some(vec!["input-branch"]))
+        (p_desc, desc, Some(vec!["input-branch", "group"]))
     } else if cmd_name.contains("split-in") {
         let p_desc = Some(repo_file_desc);
         let desc = "fetch and rewrite a remote repository's history onto a new branch according to the repo file rules";
-        (p_desc, desc, Some(vec!["gen-repo-file", "--as"]))
+        (p_desc, desc, Some(vec!["gen-repo-file", "--as", "group"]))
     } else if cmd_name.contains("topbase") {
         let p_desc = Some("    <base>    the branch to rebase onto.\n    [top]     the branch that will be rebased. defaults to current branch");
         let desc = "rebase top branch onto bottom branch but stop the rebase after the first shared commit";
@@ -315,6 +484,24 @@ pub fn print_usage<A: AsRef<impl Options>>(
         let p_desc = Some(repo_file_desc);
         let desc = "check if there are changes ready to be pushed or pulled";
         (p_desc, desc, None)
+    } else if cmd_name.contains("lint-rf") {
+        let p_desc = Some(repo_file_desc);
+        let desc = "check your repo file(s) for mistakes, ie: directories without a trailing slash, or keys that get ignored";
+        (p_desc, desc, None)
+    } else if cmd_name.contains("fmt-rf") {
+        let p_desc = Some(repo_file_desc);
+        let desc = "rewrite your repo file(s) into a canonical form: sorted sections, trailing slashes on directories, no duplicate entries";
+        (p_desc, desc, None)
+    } else if cmd_name.contains("init-rf") {
+        let desc = "interactively create a new repo file by picking a remote repository and the directories to split out";
+        (None, desc, None)
+    } else if cmd_name.contains("history") {
+        let p_desc = Some(repo_file_desc);
+        let desc = "list every successful sync that was recorded in refs/mgt/ledger, most recent first";
+        (p_desc, desc, None)
+    } else if cmd_name.contains("audit") {
+        let desc = "find paths that several repo files of the workspace include, paths that none of them include, and destination paths that collide";
+        (None, desc, None)
     } else {
         (None, "", None)
     };
@@ -392,6 +579,31 @@ impl AsRef<MgtCommandSplit> for MgtCommandSplit {
         self
     }
 }
+impl AsRef<MgtCommandAudit> for MgtCommandAudit {
+    fn as_ref(&self) -> &MgtCommandAudit {
+        self
+    }
+}
+impl AsRef<MgtCommandLint> for MgtCommandLint {
+    fn as_ref(&self) -> &MgtCommandLint {
+        self
+    }
+}
+impl AsRef<MgtCommandFormat> for MgtCommandFormat {
+    fn as_ref(&self) -> &MgtCommandFormat {
+        self
+    }
+}
+impl AsRef<MgtCommandInit> for MgtCommandInit {
+    fn as_ref(&self) -> &MgtCommandInit {
+        self
+    }
+}
+impl AsRef<MgtCommandHistory> for MgtCommandHistory {
+    fn as_ref(&self) -> &MgtCommandHistory {
+        self
+    }
+}
 impl AsRef<MgtCommandVerify> for MgtCommandVerify {
     fn as_ref(&self) -> &MgtCommandVerify {
         self
@@ -508,6 +720,46 @@ pub fn get_cli_input() -> Mgt {
                     false
                 }
             }
+            MgtSubcommands::Audit(a) => {
+                if cli.help || a.help {
+                    print_usage(&a, Some("mgt audit"), Some("[OPTIONS]"));
+                    true
+                } else {
+                    false
+                }
+            }
+            MgtSubcommands::LintRf(l) => {
+                if cli.help || l.help {
+                    print_usage(&l, Some("mgt lint-rf"), Some("[<repo-file>...]"));
+                    true
+                } else {
+                    false
+                }
+            }
+            MgtSubcommands::FmtRf(f) => {
+                if cli.help || f.help {
+                    print_usage(&f, Some("mgt fmt-rf"), Some("[FLAGS] [<repo-file>...]"));
+                    true
+                } else {
+                    false
+                }
+            }
+            MgtSubcommands::InitRf(i) => {
+                if cli.help || i.help {
+                    print_usage(&i, Some("mgt init-rf"), Some("[OPTIONS]"));
+                    true
+                } else {
+                    false
+                }
+            }
+            MgtSubcommands::History(h) => {
+                if cli.help || h.help {
+                    print_usage(&h, Some("mgt history"), Some("[<repo-file>...]"));
+                    true
+                } else {
+                    false
+                }
+            }
         },
     };
 
@@ -525,12 +777,45 @@ pub fn get_cli_input() -> Mgt {
     cli
 }
 
+impl MgtSubcommands {
+    /// true for the commands that make and delete branches, or that
+    /// fetch into FETCH_HEAD, unless they are only a dry run
+    pub fn takes_lock(&self, dry_run: bool) -> bool {
+        match self {
+            MgtSubcommands::Sync(_) | MgtSubcommands::Check(_) => true,
+            MgtSubcommands::Topbase(cmd) => !(dry_run || cmd.dry_run),
+            MgtSubcommands::SplitIn(cmd)
+            | MgtSubcommands::SplitInAs(cmd)
+            | MgtSubcommands::SplitOut(cmd)
+            | MgtSubcommands::SplitOutAs(cmd) => !(dry_run || cmd.dry_run),
+            _ => false,
+        }
+    }
+}
+
+/// outside of a git repository there is nothing to lock, and
+/// the command itself reports that it needs a repository
+fn take_repo_lock() -> Option<RepoLock> {
+    let path = lock::get_lock_path().ok()?;
+    let command_line = std::env::args().collect::<Vec<String>>().join(" ");
+    match lock::take_lock(&path, &command_line) {
+        Ok(lock) => Some(lock),
+        Err(e) => die!("{}", e),
+    }
+}
+
 /// validate the input options, and adjust as needed
 /// print an error message and exit if invalid.
 /// otherwise, call each commands run function
 pub fn validate_input_and_run(mgt_opts: Mgt) {
     let mut mgt_opts = mgt_opts;
-    match mgt_opts.command.take() {
+    let command = mgt_opts.command.take();
+    // held until the command is done
+    let _lock = match command {
+        Some(ref c) if c.takes_lock(mgt_opts.dry_run) => take_repo_lock(),
+        _ => None,
+    };
+    match command {
         None => (),
         Some(mut command) => match command {
             MgtSubcommands::Help(_) => {
@@ -542,6 +827,9 @@ pub fn validate_input_and_run(mgt_opts: Mgt) {
                 if cmd.remote && cmd.local {
                     die!("--remote cannot be used with --local");
                 }
+                if cmd.jobs == Some(0) {
+                    die!("--jobs must be at least 1");
+                }
                 run_check(&mut cmd);
             }
             MgtSubcommands::DiffLog(mut cmd) => {
@@ -602,8 +890,26 @@ pub fn validate_input_and_run(mgt_opts: Mgt) {
                 run_verify(cmd);
             }
             MgtSubcommands::Sync(ref mut cmd) => {
+                if cmd.jobs == Some(0) {
+                    die!("--jobs must be at least 1");
+                }
                 run_sync(cmd);
             }
+            MgtSubcommands::Audit(ref mut cmd) => {
+                run_audit(cmd);
+            }
+            MgtSubcommands::LintRf(ref mut cmd) => {
+                run_lint(cmd);
+            }
+            MgtSubcommands::FmtRf(ref mut cmd) => {
+                run_format(cmd);
+            }
+            MgtSubcommands::InitRf(ref mut cmd) => {
+                run_init(cmd);
+            }
+            MgtSubcommands::History(ref mut cmd) => {
+                run_history(cmd);
+            }
         },
     }
 }
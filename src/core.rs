@@ -6,11 +6,15 @@ use std::io::{self, sink};
 
 use git_url_parse::GitUrl;
 use gitfilter::filter::FilterOptions;
+use gitfilter::filter::OverlayContents;
 use gitfilter::filter::FilterRules;
+use gitfilter::filter::VersionedFilterRules;
 
 use super::exec_helpers;
 use super::git_helpers3;
 use super::repo_file::RepoFile;
+use super::repo_file::OverlaySource;
+use super::verify;
 use super::ioerre;
 
 pub const VALID_REPO_FILE_EXTENSION: &str = "rf";
@@ -51,21 +55,131 @@ pub fn go_to_repo_root() {
     }
 }
 
+/// what gitfilter needs to know about every overlay file of the repo file.
+/// local files are relative to the repository root, and the overlay file
+/// follows their history. inline contents get added in the commit of
+/// `repo_file_path` that they were added or last changed in
+pub fn get_overlay_contents(
+    repo_file: &RepoFile,
+    repo_file_path: Option<&Path>,
+) -> io::Result<Vec<(String, OverlayContents)>> {
+    let mut out = vec![];
+    let overlay = match repo_file.overlay {
+        Some(ref o) => o,
+        None => return Ok(out),
+    };
+    for (dest, source) in overlay {
+        let contents = match source {
+            OverlaySource::Inline(content) => {
+                let since = match repo_file_path {
+                    Some(path) => find_inline_overlay_commit(path, dest, content)?,
+                    None => None,
+                };
+                OverlayContents::Inline(content.as_bytes().to_vec(), since)
+            }
+            OverlaySource::LocalFile(path) => {
+                if !Path::new(path).is_file() {
+                    return ioerre!("Failed to find overlay file {}", path);
+                }
+                OverlayContents::InputPath(path.trim_start_matches("./").to_string())
+            }
+        };
+        out.push((dest.clone(), contents));
+    }
+    Ok(out)
+}
+
+/// go back through the commits that changed the repo file for as long as
+/// it has these inline contents for `dest`, and return the oldest one.
+/// None if the current contents are not committed yet
+pub fn find_inline_overlay_commit(
+    repo_file_path: &Path,
+    dest: &str,
+    content: &str,
+) -> io::Result<Option<String>> {
+    let path_str = repo_file_path.to_string_lossy();
+    let commits = git_helpers3::git_output(&["log", "--format=%H", "HEAD", "--", &path_str])?;
+    let mut found = None;
+    for commit in commits.lines() {
+        let has_content = match verify::get_repo_file_at_rev(&path_str, commit) {
+            Ok(old) => old.overlay.unwrap_or_default().iter()
+                .any(|(d, s)| d == dest && *s == OverlaySource::Inline(content.to_string())),
+            Err(_) => false,
+        };
+        if !has_content {
+            break;
+        }
+        found = Some(commit.to_string());
+    }
+    Ok(found)
+}
+
+/// convert the versioned rules of the repo file into what gitfilter expects.
+/// the since/until commits get resolved to full oids because
+/// that is what gitfilter compares against
+pub fn get_versioned_filter_rules(repo_file: &RepoFile) -> io::Result<Vec<VersionedFilterRules>> {
+    let mut out = vec![];
+    let versioned = match repo_file.versioned {
+        Some(ref v) => v,
+        None => return Ok(out),
+    };
+    let resolve = |committish: &Option<String>| -> io::Result<Option<String>> {
+        match committish {
+            None => Ok(None),
+            Some(c) => match git_helpers3::get_full_commit_oid(c) {
+                Ok(oid) => Ok(Some(oid)),
+                Err(e) => ioerre!("Failed to resolve versioned rule commit: {}", e),
+            },
+        }
+    };
+    for rules in versioned {
+        let mut file_ops = verify::get_vec_of_file_ops_from_rules(
+            &rules.include_as, &rules.include, &rules.exclude, true);
+        out.push(VersionedFilterRules {
+            since: resolve(&rules.since)?,
+            until: resolve(&rules.until)?,
+            rules: verify::make_filter_rules(&mut file_ops),
+        });
+    }
+    Ok(out)
+}
+
+/// if `split_out_repo_file` is provided, then the options
+/// of that repo file that only apply when splitting out
+/// (synthesize_gitfiles, overlay, versioned, allow_destination_collisions) are used.
 pub fn perform_gitfilter_res(
     filter_rules: FilterRules,
     output_branch: String,
+    split_out_repo_file: Option<&RepoFile>,
+    repo_file_path: Option<&Path>,
     dry_run: bool,
     verbose: bool,
 ) -> io::Result<()> {
+    let (synthesize_gitfiles, overlay, versioned_rules, detect_destination_collisions) = match split_out_repo_file {
+        Some(repo_file) => (
+            repo_file.synthesize_gitfiles,
+            get_overlay_contents(repo_file, repo_file_path)?,
+            get_versioned_filter_rules(repo_file)?,
+            !repo_file.allow_destination_collisions,
+        ),
+        None => (false, vec![], vec![], false),
+    };
     let filter_options = FilterOptions {
         stream: sink(),
         branch: Some(output_branch),
         default_include: false,
         with_blobs: false,
+        synthesize_gitfiles,
+        overlay,
+        versioned_rules,
+        detect_destination_collisions,
     };
 
     if dry_run || verbose {
         println!("Running with filter rules:\n{:#?}", filter_rules);
+        if !filter_options.versioned_rules.is_empty() {
+            println!("And versioned filter rules:\n{:#?}", filter_options.versioned_rules);
+        }
     }
     if dry_run { return Ok(()); }
 
@@ -86,10 +200,12 @@ pub fn perform_gitfilter_res(
 pub fn perform_gitfilter(
     filter_rules: FilterRules,
     output_branch: String,
+    split_out_repo_file: Option<&RepoFile>,
+    repo_file_path: Option<&Path>,
     dry_run: bool,
     verbose: bool,
 ) {
-    if let Err(e) = perform_gitfilter_res(filter_rules, output_branch, dry_run, verbose) {
+    if let Err(e) = perform_gitfilter_res(filter_rules, output_branch, split_out_repo_file, repo_file_path, dry_run, verbose) {
         die!("{}", e);
     }
 }
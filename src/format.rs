@@ -0,0 +1,415 @@
+use super::cli::MgtCommandFormat;
+use super::die;
+use super::git_helpers3;
+use super::lint;
+use super::repo_file;
+use super::repo_file::RepoFile;
+use super::workspace;
+use std::io;
+use toml::Value;
+
+/// the order of the sections of a formatted repo file
+const SECTION_ORDER: [&str; 9] = [
+    "extends", "vars", "repo", "include_as", "include", "exclude", "overlay", "tags", "versioned",
+];
+
+/// comments of a repo file, grouped by what they are next to
+#[derive(Debug, Default, PartialEq)]
+pub struct RepoFileComments {
+    /// comments at the top of the file that are separated
+    /// from the first section by an empty line
+    pub header: Vec<String>,
+    /// pairs of (section name, comments before or inside that section)
+    pub sections: Vec<(String, Vec<String>)>,
+    /// comments after the last section
+    pub footer: Vec<String>,
+}
+
+impl RepoFileComments {
+    fn add(&mut self, section: &str, comments: &mut Vec<String>) {
+        if comments.is_empty() {
+            return;
+        }
+        match self.sections.iter_mut().find(|(s, _)| s == section) {
+            Some((_, existing)) => existing.append(comments),
+            None => self.sections.push((section.to_string(), std::mem::take(comments))),
+        }
+    }
+}
+
+/// split a line into the toml part and the comment at the end
+/// of it, if any. a # inside of a string is not a comment
+fn split_inline_comment(line: &str) -> (&str, Option<&str>) {
+    let mut in_basic_string = false;
+    let mut in_literal_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' if in_basic_string => { escaped = !escaped; continue; }
+            '"' if !in_literal_string && !escaped => in_basic_string = !in_basic_string,
+            '\'' if !in_basic_string => in_literal_string = !in_literal_string,
+            '#' if !in_basic_string && !in_literal_string => return (&line[..i], Some(&line[i..])),
+            _ => (),
+        }
+        escaped = false;
+    }
+    (line, None)
+}
+
+fn bracket_depth_change(code: &str) -> i32 {
+    let mut change = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in code.chars() {
+        match (c, quote) {
+            ('\\', Some('"')) => { escaped = !escaped; continue; }
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (_, Some(q)) if c == q && !escaped => quote = None,
+            ('[', None) | ('{', None) => change += 1,
+            (']', None) | ('}', None) => change -= 1,
+            _ => (),
+        }
+        escaped = false;
+    }
+    change
+}
+
+/// the name of the top level section that a table header or key belongs to
+fn section_name(code: &str) -> String {
+    let code = code.trim();
+    let name = if code.starts_with('[') {
+        code.trim_start_matches('[').split([']', '.']).next().unwrap_or("")
+    } else {
+        code.split('=').next().unwrap_or("")
+    };
+    name.trim().trim_matches('"').to_string()
+}
+
+/// find every comment of the repo file, and which section it belongs to.
+/// a comment belongs to the section that comes after it,
+/// or the section it is inside of
+pub fn collect_comments(lines: &[String]) -> RepoFileComments {
+    let segment_starts: Vec<usize> = repo_file::split_repo_file_into_segments(lines)
+        .into_iter().map(|(start, _)| start).collect();
+    let mut comments = RepoFileComments::default();
+    let mut pending = vec![];
+    let mut depth = 0;
+    let mut current_table: Option<String> = None;
+    let mut last_section: Option<String> = None;
+    for (i, line) in lines.iter().enumerate() {
+        if segment_starts.contains(&i) {
+            current_table = None;
+        }
+        let (code, comment) = split_inline_comment(line);
+        let code_is_empty = code.trim().is_empty();
+        if depth > 0 || code_is_empty {
+            if let Some(comment) = comment {
+                pending.push(comment.trim().to_string());
+            }
+            if depth > 0 {
+                if let Some(ref section) = last_section {
+                    comments.add(section, &mut pending);
+                }
+            } else if line.trim().is_empty() && last_section.is_none() {
+                comments.header.append(&mut pending);
+            }
+            depth += bracket_depth_change(code);
+            continue;
+        }
+
+        let section = if code.trim_start().starts_with('[') {
+            let name = section_name(code);
+            current_table = Some(name.clone());
+            name
+        } else {
+            current_table.clone().unwrap_or_else(|| section_name(code))
+        };
+        if let Some(comment) = comment {
+            pending.push(comment.trim().to_string());
+        }
+        comments.add(&section, &mut pending);
+        last_section = Some(section);
+        depth += bracket_depth_change(code);
+    }
+    comments.footer = pending;
+    comments
+}
+
+/// remove a leading ./ and repeated slashes, and add the
+/// trailing slash to paths that are directories at HEAD
+pub fn normalize_path(path: &str, files_at_head: &[String]) -> String {
+    let mut out = String::with_capacity(path.len() + 1);
+    for c in path.trim().trim_start_matches("./").chars() {
+        if c == '/' && out.ends_with('/') {
+            continue;
+        }
+        out.push(c);
+    }
+    let is_dir_at_head = !out.is_empty() && !out.ends_with('/') && files_at_head.iter()
+        .any(|f| f.len() > out.len() && f.starts_with(&out) && f[out.len()..].starts_with('/'));
+    if is_dir_at_head {
+        out.push('/');
+    }
+    out
+}
+
+fn normalize_list(list: &Option<Vec<String>>, files_at_head: &[String]) -> Option<Vec<String>> {
+    let list = list.as_ref()?;
+    let mut out: Vec<String> = vec![];
+    for path in list {
+        let path = normalize_path(path, files_at_head);
+        if !out.contains(&path) {
+            out.push(path);
+        }
+    }
+    out.sort();
+    Some(out)
+}
+
+fn normalize_include_as(include_as: &Option<Vec<String>>, files_at_head: &[String]) -> Option<Vec<String>> {
+    let include_as = include_as.as_ref()?;
+    let mut pairs: Vec<(String, String)> = vec![];
+    for pair in include_as.chunks(2) {
+        let src = normalize_path(&pair[0], files_at_head);
+        let mut dest = normalize_path(pair.get(1).map(|s| s.as_str()).unwrap_or(""), &[]);
+        if dest.is_empty() {
+            // the root of the repository is written as a single space
+            dest = " ".into();
+        } else if src.ends_with('/') && !dest.ends_with('/') {
+            dest.push('/');
+        }
+        // the same source twice: the last destination wins
+        pairs.retain(|(s, _)| *s != src);
+        pairs.push((src, dest));
+    }
+    pairs.sort();
+    Some(pairs.into_iter().flat_map(|(src, dest)| vec![src, dest]).collect())
+}
+
+/// sort and deduplicate every list of paths of the repo file,
+/// and normalize the paths themselves
+pub fn normalize_repo_file(repo_file: &mut RepoFile, files_at_head: &[String]) {
+    repo_file.include = normalize_list(&repo_file.include, files_at_head);
+    repo_file.exclude = normalize_list(&repo_file.exclude, files_at_head);
+    repo_file.include_as = normalize_include_as(&repo_file.include_as, files_at_head);
+    if let Some(ref mut overlay) = repo_file.overlay {
+        overlay.sort_by(|a, b| a.0.cmp(&b.0));
+        overlay.dedup_by(|a, b| a.0 == b.0);
+    }
+    if let Some(ref mut versioned) = repo_file.versioned {
+        for rules in versioned.iter_mut() {
+            rules.include = normalize_list(&rules.include, files_at_head);
+            rules.exclude = normalize_list(&rules.exclude, files_at_head);
+            rules.include_as = normalize_include_as(&rules.include_as, files_at_head);
+        }
+    }
+}
+
+/// a list with one entry per line, so that diffs of it are easy to read
+fn format_list(key: &str, list: &Option<Vec<String>>) -> Option<String> {
+    let list = list.as_ref()?;
+    if list.len() == 1 {
+        return Some(format!("{} = {}\n", key, Value::String(list[0].clone())));
+    }
+    let mut out = format!("{} = [\n", key);
+    for path in list {
+        out.push_str(&format!("    {},\n", Value::String(path.clone())));
+    }
+    out.push_str("]\n");
+    Some(out)
+}
+
+fn format_table(key: &str, value: Option<Value>) -> Option<String> {
+    let mut toml_map = toml::map::Map::new();
+    toml_map.insert(key.into(), value?);
+    Some(Value::Table(toml_map).to_string())
+}
+
+fn format_section(section: &str, repo_file: &RepoFile) -> Option<String> {
+    match section {
+        "extends" => format_table("extends", repo_file.extends.clone().map(Value::String)),
+        "vars" => format_table("vars", repo_file::generate_repo_file_section_vars(repo_file)),
+        "repo" => format_table("repo", repo_file::generate_repo_file_section_repo(repo_file)),
+        "include_as" => format_table("include_as", repo_file::generate_repo_file_section_include_as(repo_file)),
+        "include" => format_list("include", &repo_file.include),
+        "exclude" => format_list("exclude", &repo_file.exclude),
+        "overlay" => format_table("overlay", repo_file::generate_repo_file_section_overlay(repo_file)),
+        "tags" => format_table("tags", repo_file::generate_repo_file_section_tags(repo_file)),
+        "versioned" => {
+            let versioned: Vec<String> = repo_file::generate_repo_file_section_versioned(repo_file)
+                .iter().map(|v| v.to_string()).collect();
+            if versioned.is_empty() { None } else { Some(versioned.join("\n\n")) }
+        }
+        _ => None,
+    }
+}
+
+/// get the canonical form of a repo file. every section is separated
+/// by 2 empty lines, so that it always gets parsed on its own
+pub fn format_repo_file(lines: &[String], files_at_head: &[String]) -> io::Result<String> {
+    let mut repo_file = repo_file::parse_repo_file_from_toml_lines(lines.to_vec())?;
+    normalize_repo_file(&mut repo_file, files_at_head);
+    let mut comments = collect_comments(lines);
+
+    let mut sections = vec![];
+    for section in SECTION_ORDER.iter() {
+        let text = match format_section(section, &repo_file) {
+            Some(t) => t,
+            None => continue,
+        };
+        let mut out = String::new();
+        if let Some(index) = comments.sections.iter().position(|(s, _)| s == section) {
+            for comment in comments.sections.remove(index).1 {
+                out.push_str(&comment);
+                out.push('\n');
+            }
+        }
+        out.push_str(&text);
+        sections.push(out);
+    }
+    // a comment should never get lost, even if the section
+    // it was next to no longer exists
+    let mut footer: Vec<String> = comments.sections.drain(..).flat_map(|(_, c)| c).collect();
+    footer.extend(comments.footer);
+
+    let mut out = String::new();
+    if !comments.header.is_empty() {
+        out.push_str(&comments.header.join("\n"));
+        out.push_str("\n\n");
+    }
+    out.push_str(&sections.join("\n\n"));
+    if !footer.is_empty() {
+        out.push_str("\n\n");
+        out.push_str(&footer.join("\n"));
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+pub fn run_format(cmd: &mut MgtCommandFormat) {
+    let repo_file_paths: Vec<String> = if cmd.repo_files.is_empty() {
+        match workspace::get_workspace_repo_files(None) {
+            Ok(paths) => paths.iter().map(|p| p.display().to_string()).collect(),
+            Err(e) => die!("{}", e),
+        }
+    } else {
+        cmd.repo_files.clone()
+    };
+    // only used to know which paths are directories. a repo
+    // file can still be formatted outside of a git repository
+    let files_at_head: Vec<String> = match git_helpers3::get_all_files_in_repo() {
+        Ok(text) => text.split('\n').map(|line| line.to_string()).collect(),
+        Err(_) => vec![],
+    };
+
+    let mut unformatted = vec![];
+    for path in repo_file_paths.iter() {
+        let lines = match repo_file::read_file_into_lines(path) {
+            Ok(l) => l,
+            Err(e) => die!("{}", e),
+        };
+        // formatting only keeps what the parser understands,
+        // so anything it would ignore has to be fixed by hand first
+        let problems = lint::lint_repo_file_structure(&lines);
+        if !problems.is_empty() {
+            lint::print_lint_messages(path, &problems);
+            die!("Refusing to format {}, because the above would get lost", path);
+        }
+        let formatted = match format_repo_file(&lines, &files_at_head) {
+            Ok(f) => f,
+            Err(e) => die!("Failed to format {}: {}", path, e),
+        };
+        let original = match std::fs::read_to_string(path) {
+            Ok(o) => o,
+            Err(e) => die!("Failed to read {}: {}", path, e),
+        };
+        if original == formatted {
+            continue;
+        }
+        if cmd.check {
+            println!("{} is not formatted", path);
+            unformatted.push(path);
+        } else if let Err(e) = std::fs::write(path, formatted) {
+            die!("Failed to write {}: {}", path, e);
+        }
+    }
+    if !unformatted.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format_sorts_normalizes_and_keeps_comments() {
+        let repo_file_str = r#"# splits out the lib
+
+include = ["scripts", "./docs//", "docs/", "scripts/"] # the docs too
+
+
+# where it goes
+[repo]
+remote = "https://github.com/a/b"
+
+
+include_as = { "lib/" = "" }
+"#;
+        let lines: Vec<String> = repo_file_str.split('\n').map(|s| s.to_string()).collect();
+        let files_at_head: Vec<String> = vec!["scripts/a.sh".into(), "docs/b.md".into(), "lib/c.rs".into()];
+        let formatted = format_repo_file(&lines, &files_at_head).unwrap();
+        let expected = r#"# splits out the lib
+
+# where it goes
+[repo]
+remote = "https://github.com/a/b"
+
+
+[include_as]
+"lib/" = " "
+
+
+# the docs too
+include = [
+    "docs/",
+    "scripts/",
+]
+"#;
+        assert_eq!(formatted, expected);
+
+        // formatting is stable
+        let lines: Vec<String> = formatted.split('\n').map(|s| s.to_string()).collect();
+        assert_eq!(format_repo_file(&lines, &files_at_head).unwrap(), expected);
+    }
+
+    #[test]
+    fn format_keeps_tag_rules_in_order() {
+        let repo_file_str = r#"
+[tags]
+"lib-v*" = "v*"
+"*" = "*"
+
+[repo]
+remote = "https://github.com/a/b"
+"#;
+        let lines: Vec<String> = repo_file_str.split('\n').map(|s| s.to_string()).collect();
+        let formatted = format_repo_file(&lines, &[]).unwrap();
+        let expected = r#"[repo]
+remote = "https://github.com/a/b"
+
+
+[tags]
+"lib-v*" = "v*"
+"*" = "*"
+"#;
+        assert_eq!(formatted, expected);
+
+        // the first rule that matches wins, so the order has to survive a round trip
+        let lines: Vec<String> = formatted.split('\n').map(|s| s.to_string()).collect();
+        let parsed = repo_file::parse_repo_file_from_toml_lines(lines.clone()).unwrap();
+        let tags = vec![("lib-v*".to_string(), "v*".to_string()), ("*".to_string(), "*".to_string())];
+        assert_eq!(parsed.tags, Some(tags));
+        assert_eq!(format_repo_file(&lines, &[]).unwrap(), expected);
+    }
+}
@@ -4,6 +4,7 @@ use super::exec_helpers;
 pub use crate::blob_log_parser::*;
 use crate::{ioerr, ioerre};
 use std::{
+    collections::HashMap,
     io::BufRead,
     io::{self, BufReader},
     process::Stdio,
@@ -385,6 +386,118 @@ pub fn get_repo_root() -> Result<String, String> {
     }
 }
 
+/// get the contents of the file at `path` as it was at `rev`.
+/// `path` is relative to the root of the repository
+pub fn get_file_contents_at_rev(rev: &str, path: &str) -> Result<String, String> {
+    let object = format!("{}:{}", rev, path);
+    match exec_helpers::execute(&["git", "show", &object]) {
+        Ok(out) => {
+            if out.status == 0 {
+                Ok(out.stdout)
+            } else {
+                Err(out.stderr)
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// resolve something like a short sha, or a branch name
+/// into the full oid of the commit it points to
+pub fn get_full_commit_oid(committish: &str) -> Result<String, String> {
+    let commit_str = format!("{}^{{commit}}", committish);
+    let exec_args = ["git", "rev-parse", "--verify", "--quiet", &commit_str];
+    match exec_helpers::execute(&exec_args) {
+        Ok(out) => {
+            if out.status == 0 {
+                Ok(out.stdout.trim_end().into())
+            } else {
+                Err(format!("{} is not a valid commit", committish))
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// every (path, blob hash) in the tree of `rev`. submodules
+/// are not blobs, so they are not part of the output
+pub fn get_blobs_at_rev(rev: &str) -> Result<Vec<(String, String)>, String> {
+    let exec_args = ["git", "ls-tree", "-r", "--full-tree", rev];
+    let out_str = match exec_helpers::execute(&exec_args) {
+        Err(e) => return Err(e.to_string()),
+        Ok(out) => match out.status {
+            0 => out.stdout,
+            _ => return Err(out.stderr),
+        },
+    };
+    let mut blobs = vec![];
+    for line in out_str.lines() {
+        // <mode> SP <type> SP <object> TAB <path>
+        let (info, path) = match line.find('\t') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => continue,
+        };
+        let mut info_split = info.split(' ');
+        if info_split.nth(1) == Some("blob") {
+            if let Some(hash) = info_split.next() {
+                blobs.push((path.to_string(), hash.to_string()));
+            }
+        }
+    }
+    Ok(blobs)
+}
+
+/// the (path, old blob hash, new blob hash) of every file that `commit`
+/// changed compared to its first parent. a hash is empty if the
+/// file does not exist on that side. submodules are not part of the output
+pub fn get_commit_changes(commit: &str) -> Result<Vec<(String, String, String)>, String> {
+    let exec_args = ["git", "diff-tree", "-r", "--no-renames", "--root", "--no-commit-id", commit];
+    let out_str = match exec_helpers::execute(&exec_args) {
+        Err(e) => return Err(e.to_string()),
+        Ok(out) => match out.status {
+            0 => out.stdout,
+            _ => return Err(out.stderr),
+        },
+    };
+    let mut changes = vec![];
+    for line in out_str.lines() {
+        // :<old mode> SP <new mode> SP <old hash> SP <new hash> SP <status> TAB <path>
+        let (info, path) = match line.find('\t') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => continue,
+        };
+        let info: Vec<&str> = info.trim_start_matches(':').split(' ').collect();
+        if info.len() < 4 || info[0] == "160000" || info[1] == "160000" {
+            continue;
+        }
+        let hash_or_empty = |hash: &str| if hash.chars().all(|c| c == '0') { String::new() } else { hash.to_string() };
+        changes.push((path.to_string(), hash_or_empty(info[2]), hash_or_empty(info[3])));
+    }
+    Ok(changes)
+}
+
+/// run a git command and get its trimmed output,
+/// or its stderr as the error if it failed
+pub fn git_output(args: &[&str]) -> io::Result<String> {
+    let mut exec_args = vec!["git"];
+    exec_args.extend_from_slice(args);
+    let out = exec_helpers::execute(&exec_args)?;
+    if out.status != 0 {
+        return ioerre!("git {} failed:\n{}", args.join(" "), out.stderr);
+    }
+    Ok(out.stdout.trim_end().to_string())
+}
+
+/// true if `ancestor` is reachable from `descendant`. this is
+/// also true if they are the same commit
+pub fn is_ancestor(ancestor: &str, descendant: &str) -> bool {
+    let exec_args = ["git", "merge-base", "--is-ancestor", ancestor, descendant];
+    match exec_helpers::execute(&exec_args) {
+        Ok(out) => out.status == 0,
+        Err(_) => false,
+    }
+}
+
 pub fn fetch_branch(remote: &str, branch: &str) -> Result<(), String> {
     let err_msg = match exec_helpers::execute(&["git", "fetch", remote, branch, "--no-tags"]) {
         Err(e) => Some(format!("{}", e)),
@@ -399,8 +512,35 @@ pub fn fetch_branch(remote: &str, branch: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// like fetch_branch, but into `dest_ref` instead of only FETCH_HEAD,
+/// so that fetching another remote does not overwrite it
+pub fn fetch_branch_into(remote: &str, branch: &str, dest_ref: &str) -> Result<(), String> {
+    let refspec = format!("+{}:{}", branch, dest_ref);
+    let err_msg = match exec_helpers::execute(&["git", "fetch", remote, &refspec, "--no-tags"]) {
+        Err(e) => Some(format!("{}", e)),
+        Ok(o) => match o.status {
+            0 => None,
+            _ => Some(o.stderr),
+        },
+    };
+    if let Some(err) = err_msg {
+        return Err(err);
+    }
+    Ok(())
+}
+
+pub fn delete_ref(ref_name: &str) -> io::Result<()> {
+    git_output(&["update-ref", "-d", ref_name])?;
+    Ok(())
+}
+
 pub fn get_all_files_in_repo() -> Result<String, String> {
-    let exec_args = ["git", "ls-tree", "-r", "HEAD", "--name-only", "--full-tree"];
+    get_all_files_at_rev("HEAD")
+}
+
+/// like get_all_files_in_repo, but for the tree of any committish
+pub fn get_all_files_at_rev(rev: &str) -> Result<String, String> {
+    let exec_args = ["git", "ls-tree", "-r", rev, "--name-only", "--full-tree"];
     match exec_helpers::execute(&exec_args) {
         Ok(out) => {
             if out.status == 0 {
@@ -413,6 +553,89 @@ pub fn get_all_files_in_repo() -> Result<String, String> {
     }
 }
 
+/// like get_all_files_in_repo, but only returns
+/// the paths of gitlinks (submodules) in HEAD
+pub fn get_all_gitlinks_in_repo() -> Result<Vec<String>, String> {
+    get_all_gitlinks_at_rev("HEAD")
+}
+
+pub fn get_all_gitlinks_at_rev(rev: &str) -> Result<Vec<String>, String> {
+    let exec_args = ["git", "ls-tree", "-r", rev, "--full-tree"];
+    match exec_helpers::execute(&exec_args) {
+        Ok(out) => {
+            if out.status != 0 {
+                return Err(out.stderr);
+            }
+            let mut gitlinks = vec![];
+            // each line is: <mode> SP <type> SP <object> TAB <file>
+            for line in out.stdout.lines() {
+                let (info, path) = match line.split_once('\t') {
+                    Some(s) => s,
+                    None => continue,
+                };
+                let mode = info.split_whitespace().next().unwrap_or("");
+                if let Ok(FileMode::GitLink) = mode.parse::<FileMode>() {
+                    gitlinks.push(path.to_string());
+                }
+            }
+            Ok(gitlinks)
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// get the path of every submodule listed in the .gitmodules file of HEAD.
+/// returns an empty vec if there is no .gitmodules file
+pub fn get_gitmodules_paths() -> Vec<String> {
+    get_gitmodules_paths_at_rev("HEAD")
+}
+
+pub fn get_gitmodules_paths_at_rev(rev: &str) -> Vec<String> {
+    let blob = format!("{}:.gitmodules", rev);
+    let exec_args = [
+        "git", "config", "-z", "--blob", &blob, "--get-regexp", r"^submodule\..*\.path$",
+    ];
+    match exec_helpers::execute(&exec_args) {
+        Ok(out) if out.status == 0 => parse_gitmodules_paths(&out.stdout),
+        _ => vec![],
+    }
+}
+
+/// with -z, each entry is: submodule.<name>.path LF <path> NUL.
+/// the name can contain spaces, so we cant split on those
+pub fn parse_gitmodules_paths(output: &str) -> Vec<String> {
+    output.split('\0').filter_map(|entry| {
+        entry.split_once('\n').map(|(_, path)| path.to_string())
+    }).collect()
+}
+
+/// get the size in bytes of every blob in the tree of `rev`, by path.
+/// gitlinks dont have a size, so they are not included
+pub fn get_file_sizes_at_rev(rev: &str) -> Result<HashMap<String, u64>, String> {
+    let exec_args = ["git", "ls-tree", "-r", "-l", rev, "--full-tree"];
+    match exec_helpers::execute(&exec_args) {
+        Ok(out) => {
+            if out.status != 0 {
+                return Err(out.stderr);
+            }
+            let mut sizes = HashMap::new();
+            // each line is: <mode> SP <type> SP <object> SP+ <size> TAB <file>
+            for line in out.stdout.lines() {
+                let (info, path) = match line.split_once('\t') {
+                    Some(s) => s,
+                    None => continue,
+                };
+                let size = info.split_whitespace().nth(3).and_then(|s| s.parse::<u64>().ok());
+                if let Some(size) = size {
+                    sizes.insert(path.to_string(), size);
+                }
+            }
+            Ok(sizes)
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 pub fn reset_stage() -> Result<String, String> {
     // git reset --hard
     let exec_args = ["git", "reset", "--hard"];
@@ -452,10 +675,12 @@ pub fn rebase_interactively_with_commits(
     // eprintln!("{}", rebase_data_str);
     // eprintln!("{:?}", args);
 
+    // GIT_EDITOR so that a squash keeps the combined
+    // commit message instead of waiting for the user
     let err_msg = match exec_helpers::execute_with_env(
         &args,
-        &["GIT_SEQUENCE_EDITOR"],
-        &[rebase_data_str.as_str()],
+        &["GIT_SEQUENCE_EDITOR", "GIT_EDITOR"],
+        &[rebase_data_str.as_str(), "true"],
     ) {
         Err(e) => Some(format!("{}", e)),
         Ok(o) => match o.status {
@@ -496,4 +721,10 @@ mod test {
         // a git repository with more than 1 commit...
         assert!(data.len() > 1);
     }
+
+    #[test]
+    fn gitmodules_paths_can_have_spaces_in_names() {
+        let output = "submodule.my lib.path\nlibs/my lib\0submodule.other.path\nother\0";
+        assert_eq!(parse_gitmodules_paths(output), vec!["libs/my lib".to_string(), "other".to_string()]);
+    }
 }
@@ -0,0 +1,178 @@
+use super::cli::MgtCommandSync;
+use super::git_helpers3;
+use super::git_helpers3::Commit;
+use super::interact;
+use super::ioerr;
+use super::ledger;
+use super::ledger::LedgerEntry;
+use super::repo_file::RepoFile;
+use super::split_in;
+use super::split_out;
+use gitfilter::filter::FilterRules;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// how many of the most recent commits of each side we compare by content
+const MAX_GRAFT_CANDIDATES: usize = 100;
+/// how many of the most recent commits of each side we
+/// offer when the user picks the fork point themselves
+const MAX_GRAFT_CHOICES: usize = 15;
+
+/// the sorted (path, blob hash) pairs of a commit that are part of
+/// the split repository. a local commit and a remote commit with the same
+/// content key have the same contents after filtering. if `map_paths` is
+/// false, the paths are filtered but not renamed
+pub fn get_content_key(
+    blobs: &[(String, String)],
+    filter_rules: &FilterRules,
+    map_paths: bool,
+) -> Vec<(String, String)> {
+    let mut key = vec![];
+    for (path, hash) in blobs {
+        let mut new_path = path.clone();
+        if gitfilter::filter::should_use_file(&mut new_path, filter_rules, false) {
+            let path = if map_paths { new_path } else { path.clone() };
+            key.push((path, hash.clone()));
+        }
+    }
+    key.sort();
+    key
+}
+
+/// the index of the most recent local commit that has the same content
+/// key as a remote commit, and the index of the most recent such remote commit.
+/// commits without any content never match
+pub fn find_matching_pair(
+    local_keys: &[Vec<(String, String)>],
+    remote_keys: &[Vec<(String, String)>],
+) -> Option<(usize, usize)> {
+    let mut remote_by_key = HashMap::new();
+    for (i, key) in remote_keys.iter().enumerate() {
+        if !key.is_empty() {
+            remote_by_key.entry(key).or_insert(i);
+        }
+    }
+    local_keys.iter().enumerate().find_map(|(i, key)| {
+        remote_by_key.get(key).map(|remote_i| (i, *remote_i))
+    })
+}
+
+pub fn get_content_keys(
+    commits: &[Commit],
+    filter_rules: &FilterRules,
+    map_paths: bool,
+) -> io::Result<Vec<Vec<(String, String)>>> {
+    let mut keys = vec![];
+    for commit in commits {
+        let blobs = git_helpers3::get_blobs_at_rev(&commit.id.hash).map_err(|e| ioerr!("{}", e))?;
+        keys.push(get_content_key(&blobs, filter_rules, map_paths));
+    }
+    Ok(keys)
+}
+
+fn ask_commit(cmd: &MgtCommandSync, description: String, branch: &str) -> io::Result<Commit> {
+    let commits = git_helpers3::get_all_commits_from_ref(branch, Some(MAX_GRAFT_CHOICES))
+        .map_err(|e| ioerr!("{}", e))?;
+    let choices: Vec<String> = commits.iter()
+        .map(|c| format!("{} {}", c.id.short(), c.summary))
+        .collect();
+    let mut commit_choice: interact::InteractChoices = (&choices[..]).into();
+    commit_choice.description = Some(description);
+    commit_choice.max_loop = cmd.max_interactive_attempts;
+    let selection = interact::interact_number(commit_choice)?;
+    Ok(commits[selection - 1].clone())
+}
+
+/// find or ask for a (local, remote) pair of commits that have the same
+/// contents. returns None if the user does not want to graft
+pub fn ask_graft_pair(
+    cmd: &MgtCommandSync,
+    repo_file: &RepoFile,
+    local_branch: &str,
+    remote_branch: &str,
+) -> io::Result<Option<(Commit, Commit)>> {
+    println!("- Searching for a remote commit with the same contents as a local commit");
+    let local_commits = git_helpers3::get_all_commits_from_ref(local_branch, Some(MAX_GRAFT_CANDIDATES))
+        .map_err(|e| ioerr!("{}", e))?;
+    let remote_commits = git_helpers3::get_all_commits_from_ref(remote_branch, Some(MAX_GRAFT_CANDIDATES))
+        .map_err(|e| ioerr!("{}", e))?;
+    // local paths get renamed to where they end up in the split repository.
+    // remote paths are already there, we only drop the ones that split-in would drop
+    let local_keys = get_content_keys(
+        &local_commits, &split_out::generate_gitfilter_filterrules(repo_file, false), true)?;
+    let remote_keys = get_content_keys(
+        &remote_commits, &split_in::generate_gitfilter_filterrules(repo_file, false), false)?;
+
+    let found = find_matching_pair(&local_keys, &remote_keys)
+        .map(|(l, r)| (local_commits[l].clone(), remote_commits[r].clone()));
+    let mut choices = vec![];
+    if let Some((ref local, ref remote)) = found {
+        println!(
+            "\nLocal commit {} {}\nhas the same contents as remote commit {} {}",
+            local.id.short(), local.summary, remote.id.short(), remote.summary,
+        );
+        choices.push("Use these two commits as the fork point");
+    } else {
+        println!("\nNo remote commit has the same contents as one of the last {} local commits", MAX_GRAFT_CANDIDATES);
+    }
+    choices.push("Choose the fork point myself");
+    choices.push("Skip");
+    let mut graft_choice: interact::InteractChoices = (&choices[..]).into();
+    graft_choice.description = Some("These branches do not share any history. How would you like to connect them?".into());
+    graft_choice.max_loop = cmd.max_interactive_attempts;
+    let selection = interact::interact_number(graft_choice)?;
+    match choices[selection - 1] {
+        "Skip" => Ok(None),
+        "Choose the fork point myself" => {
+            let local = ask_commit(cmd, "Which local commit has the same contents as the remote?".into(), local_branch)?;
+            let remote = ask_commit(cmd, format!("Which remote commit has the same contents as {}?", local.id.short()), remote_branch)?;
+            Ok(Some((local, remote)))
+        }
+        _ => Ok(found),
+    }
+}
+
+/// connect two branches that do not share any history by recording
+/// a pair of commits as if they had been synced. later syncs start from there
+pub fn try_guided_graft(
+    cmd: &MgtCommandSync,
+    repo_file: &RepoFile,
+    repo_file_path: &Path,
+    remote_url: &str,
+    local_branch: &str,
+    remote_branch: &str,
+) -> io::Result<Option<LedgerEntry>> {
+    let (local, remote) = match ask_graft_pair(cmd, repo_file, local_branch, remote_branch)? {
+        Some(pair) => pair,
+        None => return Ok(None),
+    };
+    let entry = ledger::make_entry("graft", repo_file_path, remote_url, &local.id.hash, &remote.id.hash)?;
+    ledger::record_sync(&entry)?;
+    println!("- Recorded {} and {} as the fork point in {}", local.id.short(), remote.id.short(), ledger::LEDGER_REF);
+    Ok(Some(entry))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gitfilter::filter::FilterRule;
+
+    #[test]
+    fn graft_matches_commits_by_filtered_contents() {
+        let rules = vec![FilterRule::FilterRulePathRename("lib/".into(), "".into())];
+        let local = vec![
+            ("lib/a.rs".to_string(), "111".to_string()),
+            ("other.rs".to_string(), "222".to_string()),
+        ];
+        let remote = vec![("a.rs".to_string(), "111".to_string())];
+        let local_key = get_content_key(&local, &rules, true);
+        assert_eq!(local_key, vec![("a.rs".to_string(), "111".to_string())]);
+
+        let changed = vec![("a.rs".to_string(), "333".to_string())];
+        let local_keys = vec![vec![], local_key.clone(), local_key];
+        let remote_keys = vec![changed.clone(), remote.clone(), remote];
+        assert_eq!(find_matching_pair(&local_keys, &remote_keys), Some((1, 1)));
+        assert_eq!(find_matching_pair(&[vec![]], &[vec![]]), None);
+    }
+}
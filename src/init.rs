@@ -0,0 +1,195 @@
+use super::cli::MgtCommandInit;
+use super::core;
+use super::die;
+use super::git_helpers3;
+use super::interact;
+use super::ioerre;
+use super::lint;
+use super::repo_file::RepoFile;
+use super::split_in;
+use super::verify;
+use std::io;
+
+/// how deep into the repository we look for directories to offer
+const MAX_DIRECTORY_DEPTH: usize = 2;
+
+/// every directory of `files` up to `max_depth` levels deep,
+/// with a trailing slash, sorted
+pub fn get_directories(files: &[String], max_depth: usize) -> Vec<String> {
+    let mut directories = vec![];
+    for file in files {
+        let components: Vec<&str> = file.split('/').collect();
+        // the last component is the file itself
+        let depth = std::cmp::min(components.len() - 1, max_depth);
+        for i in 1..=depth {
+            let dir = format!("{}/", components[..i].join("/"));
+            if !directories.contains(&dir) {
+                directories.push(dir);
+            }
+        }
+    }
+    directories.sort();
+    directories
+}
+
+/// parse a selection like "1, 3 5-7" into the 0-based
+/// indices of the choices. every number must be between 1 and `num_choices`,
+/// and a range must not be reversed
+pub fn parse_selection(input: &str, num_choices: usize) -> Result<Vec<usize>, String> {
+    let mut out = vec![];
+    for part in input.split(|c: char| c == ',' || c.is_whitespace()).filter(|p| !p.is_empty()) {
+        let parse_number = |s: &str| -> Result<usize, String> {
+            match s.trim().parse::<usize>() {
+                Ok(n) if n >= 1 && n <= num_choices => Ok(n),
+                _ => Err(format!("'{}' is not a number between 1 and {}", s, num_choices)),
+            }
+        };
+        let (start, end) = match part.find('-') {
+            Some(i) => (parse_number(&part[..i])?, parse_number(&part[i + 1..])?),
+            None => {
+                let n = parse_number(part)?;
+                (n, n)
+            }
+        };
+        if start > end {
+            return Err(format!("'{}' is not a range, {} comes after {}", part, start, end));
+        }
+        for n in start..=end {
+            if !out.contains(&(n - 1)) {
+                out.push(n - 1);
+            }
+        }
+    }
+    if out.is_empty() {
+        return Err("Must select at least one directory".into());
+    }
+    Ok(out)
+}
+
+fn ask_word(question: &str, max_attempts: Option<usize>) -> io::Result<String> {
+    let mut choice = interact::InteractChoices::choose_word(question);
+    choice.max_loop = max_attempts;
+    let answer = interact::interact_word(choice)?;
+    Ok(answer.trim().to_string())
+}
+
+fn ask_remote_repo(max_attempts: Option<usize>) -> io::Result<String> {
+    let mut attempts = 0;
+    loop {
+        let remote_repo = ask_word("Enter the url of the remote repository to split into", max_attempts)?;
+        if core::is_valid_remote_repo(&remote_repo) {
+            return Ok(remote_repo);
+        }
+        println!("'{}' is not a valid git url", remote_repo);
+        attempts += 1;
+        if let Some(max) = max_attempts {
+            if attempts >= max {
+                return ioerre!("Too many invalid remote urls");
+            }
+        }
+    }
+}
+
+fn ask_directories(directories: &[String], max_attempts: Option<usize>) -> io::Result<Vec<String>> {
+    println!();
+    for (i, dir) in directories.iter().enumerate() {
+        println!("{}: {}", i + 1, dir);
+    }
+    let mut attempts = 0;
+    loop {
+        let selection = ask_word(
+            "Which directories should be split out? (ie: 1, 3 5-7)", max_attempts)?;
+        match parse_selection(&selection, directories.len()) {
+            Ok(indices) => return Ok(indices.iter().map(|i| directories[*i].clone()).collect()),
+            Err(e) => println!("{}", e),
+        }
+        attempts += 1;
+        if let Some(max) = max_attempts {
+            if attempts >= max {
+                return ioerre!("Too many invalid selections");
+            }
+        }
+    }
+}
+
+/// ask the user everything we need to know to write their first repo file
+pub fn ask_for_repo_file(files: &[String], max_attempts: Option<usize>) -> io::Result<RepoFile> {
+    let remote_repo = ask_remote_repo(max_attempts)?;
+    let suggested_name = core::try_get_repo_name_from_remote_repo(remote_repo.clone());
+    let repo_name = ask_word(&format!(
+        "Enter a name for this repo file (hit Enter to use {})", suggested_name), max_attempts)?;
+    let repo_name = if repo_name.is_empty() { suggested_name } else { repo_name };
+
+    let directories = get_directories(files, MAX_DIRECTORY_DEPTH);
+    if directories.is_empty() {
+        return ioerre!("This repository has no directories to split out");
+    }
+    let selected = ask_directories(&directories, max_attempts)?;
+
+    let mut repo_file = RepoFile::new();
+    repo_file.repo_name = Some(repo_name);
+    repo_file.remote_repo = Some(remote_repo);
+    // a single directory usually becomes the root of the split repository
+    let at_root = if selected.len() == 1 {
+        let choices = [
+            format!("At the root of {}", repo_file.repo_name.as_ref().unwrap()),
+            format!("At {}", selected[0]),
+        ];
+        let mut root_choice: interact::InteractChoices = (&choices[..]).into();
+        root_choice.description = Some(format!("Where should {} end up in the split repository?", selected[0]));
+        root_choice.max_loop = max_attempts;
+        interact::interact_number(root_choice)? == 1
+    } else {
+        false
+    };
+    if at_root {
+        repo_file.include_as = Some(vec![selected[0].clone(), " ".into()]);
+    } else {
+        repo_file.include = Some(selected);
+    }
+    Ok(repo_file)
+}
+
+pub fn run_init(cmd: &mut MgtCommandInit) {
+    let files: Vec<String> = match git_helpers3::get_all_files_in_repo() {
+        Ok(text) => text.split('\n').map(|line| line.to_string()).collect(),
+        Err(e) => die!("Failed to get all files in git repo:\n{}", e),
+    };
+    let repo_file = match ask_for_repo_file(&files, cmd.max_interactive_attempts) {
+        Ok(r) => r,
+        Err(e) => die!("Failed to create a repo file: {}", e),
+    };
+    let repo_name = repo_file.repo_name.clone().unwrap();
+    if let Err(e) = split_in::generate_repo_file(&repo_name, &repo_file) {
+        die!("Failed to write repo file: {}", e);
+    }
+    let repo_file_path = format!("{}.rf", repo_name);
+    println!("\nWrote {}", repo_file_path);
+
+    // verify what we just wrote, so that the user knows what it does
+    let mut file_ops = verify::get_vec_of_current_file_ops_with_order(&repo_file, true);
+    let filter_rules = verify::make_filter_rules(&mut file_ops);
+    let remaining_files = verify::apply_expected_gitfilter(&files, &[], &filter_rules);
+    let summary = verify::summarize_verify(&files, &remaining_files, &None);
+    verify::print_verify_summary(&summary);
+    lint::print_lint_messages(&repo_file_path, &lint::lint_repo_file(&repo_file_path, &files));
+    println!("Run `mgt verify-rf --verbose {}` to see where every file ends up", repo_file_path);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn init_offers_directories_and_parses_selections() {
+        let files: Vec<String> = vec![
+            "README.md".into(), "lib/a/b/c.rs".into(), "lib/d.rs".into(), "docs/e.md".into(),
+        ];
+        assert_eq!(get_directories(&files, 2), vec!["docs/", "lib/", "lib/a/"]);
+        assert_eq!(parse_selection("1, 3", 3), Ok(vec![0, 2]));
+        assert_eq!(parse_selection("2-3 1", 3), Ok(vec![1, 2, 0]));
+        assert!(parse_selection("4", 3).is_err());
+        assert!(parse_selection("3-2", 3).is_err());
+        assert!(parse_selection("", 3).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use std::panic;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// run `job` on every item, with at most `max_jobs` of them running at
+/// the same time. the results are in the same order as the items
+pub fn run_jobs<T, R, F>(items: Vec<T>, max_jobs: usize, job: F) -> Vec<R>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(T) -> R + Send + Sync + 'static,
+{
+    let num_items = items.len();
+    let num_threads = max_jobs.max(1).min(num_items);
+    let queue = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let job = Arc::new(job);
+    let (tx, rx) = mpsc::channel();
+    let mut spawned_threads = vec![];
+    for _ in 0..num_threads {
+        let queue = queue.clone();
+        let job = job.clone();
+        let tx = tx.clone();
+        spawned_threads.push(thread::spawn(move || loop {
+            // the queue is only locked while taking the next item
+            let next = queue.lock().ok().and_then(|mut q| q.next());
+            let (index, item) = match next {
+                Some(n) => n,
+                None => break,
+            };
+            if tx.send((index, job(item))).is_err() {
+                break;
+            }
+        }));
+    }
+    // only the clones are sending, so this one has to be
+    // dropped, otherwise we would wait for it forever below
+    drop(tx);
+
+    let mut results: Vec<Option<R>> = (0..num_items).map(|_| None).collect();
+    for (index, result) in rx {
+        results[index] = Some(result);
+    }
+    // a job that panicked did not send its result, so its panic
+    // is passed on instead, eg: a `die!` in a debug build
+    for spawned in spawned_threads {
+        if let Err(payload) = spawned.join() {
+            panic::resume_unwind(payload);
+        }
+    }
+    results.into_iter()
+        .map(|result| result.expect("every job that did not panic sent its result"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn jobs_results_keep_the_order_of_the_items() {
+        // the first items take the longest, so they finish last
+        let items: Vec<u64> = (0..8).collect();
+        let results = run_jobs(items, 3, |n| {
+            thread::sleep(Duration::from_millis(40 - n * 5));
+            n * 10
+        });
+        assert_eq!(results, vec![0, 10, 20, 30, 40, 50, 60, 70]);
+        assert_eq!(run_jobs(Vec::<u64>::new(), 4, |n| n), Vec::<u64>::new());
+        assert_eq!(run_jobs(vec![1], 0, |n| n + 1), vec![2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "failed on 3")]
+    fn jobs_pass_on_the_panic_of_a_job() {
+        run_jobs((0..6).collect::<Vec<u32>>(), 2, |n| {
+            if n == 3 {
+                panic!("failed on {}", n);
+            }
+            n
+        });
+    }
+}
@@ -0,0 +1,296 @@
+use super::cli::MgtCommandHistory;
+use super::die;
+use super::git_helpers3;
+use super::git_helpers3::{Commit, CommitWithBlobs, RawBlobSummary};
+use super::ioerr;
+use super::topbase::SuccessfulTopbaseResult;
+use std::io;
+use std::path::Path;
+
+/// every successful sync is a commit on this ref. the first parent
+/// of each entry is the previous entry, and the other parents are the local and
+/// remote commits that were synced, so that they never get garbage collected
+pub const LEDGER_REF: &str = "refs/mgt/ledger";
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LedgerEntry {
+    /// push, pull, or graft for a fork point that the user chose
+    pub direction: String,
+    /// path of the repo file, relative to the root of the repository
+    pub repo_file: String,
+    /// the git blob hash of the contents of the repo file at the time of the sync
+    pub repo_file_hash: String,
+    /// after the sync, the local commit that corresponds to `remote_commit`
+    pub local_commit: String,
+    pub remote_commit: String,
+    pub remote_url: String,
+    /// when the sync happened. this is the committer date of the entry
+    pub time: String,
+}
+
+impl LedgerEntry {
+    pub fn to_message(&self) -> String {
+        format!(
+            "mgt sync {} {}\n\nrepo_file: {}\nrepo_file_hash: {}\nlocal: {}\nremote: {}\nremote_url: {}\n",
+            self.direction, self.repo_file, self.repo_file, self.repo_file_hash,
+            self.local_commit, self.remote_commit, self.remote_url,
+        )
+    }
+
+    pub fn from_message(message: &str) -> Option<LedgerEntry> {
+        let mut lines = message.lines();
+        let subject = lines.next()?;
+        let mut entry = LedgerEntry {
+            direction: subject.strip_prefix("mgt sync ")?.split(' ').next()?.to_string(),
+            ..Default::default()
+        };
+        for line in lines {
+            let (key, value) = match line.find(": ") {
+                Some(i) => (&line[..i], line[i + 2..].to_string()),
+                None => continue,
+            };
+            match key {
+                "repo_file" => entry.repo_file = value,
+                "repo_file_hash" => entry.repo_file_hash = value,
+                "local" => entry.local_commit = value,
+                "remote" => entry.remote_commit = value,
+                "remote_url" => entry.remote_url = value,
+                _ => (),
+            }
+        }
+        if entry.local_commit.is_empty() || entry.remote_commit.is_empty() {
+            return None;
+        }
+        Some(entry)
+    }
+}
+
+/// how a repo file is referred to in the ledger: its path
+/// relative to the root of the repository
+pub fn get_ledger_name(repo_file_path: &Path) -> io::Result<String> {
+    let repo_root = git_helpers3::get_repo_root().map_err(|e| ioerr!("{}", e))?;
+    let repo_root = Path::new(&repo_root).canonicalize()?;
+    let repo_file_path = repo_file_path.canonicalize()
+        .map_err(|e| ioerr!("Failed to find repo file {:?}: {}", repo_file_path, e))?;
+    match repo_file_path.strip_prefix(&repo_root) {
+        Ok(p) => Ok(p.to_string_lossy().replace('\\', "/")),
+        Err(_) => Ok(repo_file_path.to_string_lossy().to_string()),
+    }
+}
+
+pub fn get_repo_file_hash(repo_file_path: &Path) -> io::Result<String> {
+    git_helpers3::git_output(&["hash-object", &repo_file_path.to_string_lossy()])
+}
+
+/// get every entry of the ledger, most recent first
+pub fn read_ledger() -> io::Result<Vec<LedgerEntry>> {
+    if git_helpers3::get_full_commit_oid(LEDGER_REF).is_err() {
+        return Ok(vec![]);
+    }
+    // %x1e separates entries, %x1f separates the date from the message
+    let log = git_helpers3::git_output(&[
+        "log", "--first-parent", "--date=iso", "--format=%cd%x1f%B%x1e", LEDGER_REF,
+    ])?;
+    let mut entries = vec![];
+    for record in log.split('\x1e') {
+        let mut parts = record.trim_start().splitn(2, '\x1f');
+        let time = parts.next().unwrap_or("").to_string();
+        if let Some(mut entry) = parts.next().and_then(LedgerEntry::from_message) {
+            entry.time = time;
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// add an entry on top of the ledger
+pub fn record_sync(entry: &LedgerEntry) -> io::Result<()> {
+    let previous = git_helpers3::get_full_commit_oid(LEDGER_REF).ok();
+    // the entries dont have any files, so they all use the empty tree
+    let empty_tree = git_helpers3::git_output(&["mktree"])?;
+    let message = entry.to_message();
+    let mut args = vec!["commit-tree", &empty_tree, "-m", &message];
+    if let Some(ref previous) = previous {
+        args.push("-p");
+        args.push(previous);
+    }
+    args.extend_from_slice(&["-p", &entry.local_commit, "-p", &entry.remote_commit]);
+    let new_entry = git_helpers3::git_output(&args)?;
+    // passing the previous value makes this fail if someone
+    // else updated the ledger in the meantime
+    let previous = previous.unwrap_or_default();
+    git_helpers3::git_output(&["update-ref", "-m", "mgt sync", LEDGER_REF, &new_entry, &previous])?;
+    Ok(())
+}
+
+/// find the most recent sync of this repo file that we can start from.
+/// it can only be used if the repo file did not change since then, and
+/// if both sides still contain the commits of that sync, ie: neither
+/// side rewrote its history
+pub fn find_last_sync(
+    entries: &[LedgerEntry],
+    ledger_name: &str,
+    repo_file_hash: &str,
+    local_branch: &str,
+    remote_branch: &str,
+) -> Option<LedgerEntry> {
+    let entry = entries.iter().find(|e| e.repo_file == ledger_name)?;
+    if entry.repo_file_hash != repo_file_hash {
+        return None;
+    }
+    let is_usable = git_helpers3::is_ancestor(&entry.local_commit, local_branch)
+        && git_helpers3::is_ancestor(&entry.remote_commit, remote_branch);
+    if is_usable {
+        Some(entry.clone())
+    } else {
+        None
+    }
+}
+
+/// every commit of `fork..branch` that the callback keeps at least one
+/// blob of, and every merge commit. this is what the topbase
+/// loader would have found above the fork point
+fn get_commits_above<B>(fork: &str, branch: &str, should_use_blob: &mut B) -> io::Result<Vec<CommitWithBlobs>>
+where
+    B: FnMut(&mut RawBlobSummary, &str) -> bool,
+{
+    let mut commits: Vec<CommitWithBlobs> = vec![];
+    let range = format!("{}..{}", fork, branch);
+    git_helpers3::iterate_blob_log(&range, None, |c| {
+        // with -m, merge commits show up once per parent
+        if commits.iter().any(|existing| existing.commit.id.hash == c.commit.id.hash) {
+            return false;
+        }
+        let mut uses_a_blob = false;
+        for mut blob in c.blobs.iter().cloned() {
+            if should_use_blob(&mut blob, branch) {
+                uses_a_blob = true;
+            }
+        }
+        if c.commit.is_merge || uses_a_blob {
+            commits.push(c);
+        }
+        false
+    })?;
+    Ok(commits)
+}
+
+/// the same as what `topbase::find_a_b_difference2` returns, but
+/// starting from a previous sync instead of searching for the fork point
+pub fn topbase_from_last_sync<B>(
+    entry: &LedgerEntry,
+    a_branch: &str,
+    b_branch: &str,
+    a_is_remote: bool,
+    should_use_blob: B,
+) -> io::Result<SuccessfulTopbaseResult<CommitWithBlobs>>
+where
+    B: FnMut(&mut RawBlobSummary, &str) -> bool,
+{
+    let mut should_use_blob = should_use_blob;
+    let (a_fork, b_fork) = if a_is_remote {
+        (&entry.remote_commit, &entry.local_commit)
+    } else {
+        (&entry.local_commit, &entry.remote_commit)
+    };
+    let fork_commit = |hash: &str| CommitWithBlobs {
+        commit: Commit::new(hash, format!("synced on {}", entry.time), false),
+        blobs: vec![],
+    };
+    Ok(SuccessfulTopbaseResult {
+        top_commits: get_commits_above(a_fork, a_branch, &mut should_use_blob)?,
+        fork_point: (fork_commit(a_fork), fork_commit(b_fork)),
+        top_right_commits: get_commits_above(b_fork, b_branch, &mut should_use_blob)?,
+    })
+}
+
+/// an entry for the current contents of the repo file
+pub fn make_entry(
+    direction: &str,
+    repo_file_path: &Path,
+    remote_url: &str,
+    local_committish: &str,
+    remote_committish: &str,
+) -> io::Result<LedgerEntry> {
+    Ok(LedgerEntry {
+        direction: direction.to_string(),
+        repo_file: get_ledger_name(repo_file_path)?,
+        repo_file_hash: get_repo_file_hash(repo_file_path)?,
+        local_commit: git_helpers3::get_full_commit_oid(local_committish).map_err(|e| ioerr!("{}", e))?,
+        remote_commit: git_helpers3::get_full_commit_oid(remote_committish).map_err(|e| ioerr!("{}", e))?,
+        remote_url: remote_url.to_string(),
+        time: String::new(),
+    })
+}
+
+/// record a sync that just succeeded. failing to record it does not
+/// undo the sync, so this only prints a warning
+pub fn try_record_sync(
+    direction: &str,
+    repo_file_path: &Path,
+    remote_url: &str,
+    local_committish: &str,
+    remote_committish: &str,
+) {
+    let entry = make_entry(direction, repo_file_path, remote_url, local_committish, remote_committish);
+    if let Err(e) = entry.and_then(|entry| record_sync(&entry)) {
+        eprintln!("- Warning: failed to record this sync in {}:\n{}", LEDGER_REF, e);
+    }
+}
+
+pub fn print_ledger_entry(entry: &LedgerEntry) {
+    println!(
+        "{}  {:<5}  {}  local {}  remote {}  {}",
+        entry.time,
+        entry.direction,
+        entry.repo_file,
+        entry.local_commit.get(0..7).unwrap_or(&entry.local_commit),
+        entry.remote_commit.get(0..7).unwrap_or(&entry.remote_commit),
+        entry.remote_url,
+    );
+}
+
+pub fn run_history(cmd: &mut MgtCommandHistory) {
+    let entries = match read_ledger() {
+        Ok(e) => e,
+        Err(e) => die!("Failed to read {}:\n{}", LEDGER_REF, e),
+    };
+    let ledger_names = cmd.repo_files.iter().map(|path| {
+        match get_ledger_name(Path::new(path)) {
+            Ok(name) => name,
+            Err(e) => die!("{}", e),
+        }
+    }).collect::<Vec<String>>();
+    let mut found_any = false;
+    for entry in entries.iter() {
+        if ledger_names.is_empty() || ledger_names.contains(&entry.repo_file) {
+            found_any = true;
+            print_ledger_entry(entry);
+        }
+    }
+    if !found_any {
+        println!("No syncs recorded yet");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ledger_entry_message_round_trips() {
+        let entry = LedgerEntry {
+            direction: "push".into(),
+            repo_file: "repos/lib.rf".into(),
+            repo_file_hash: "0123abc".into(),
+            local_commit: "a".repeat(40),
+            remote_commit: "b".repeat(40),
+            remote_url: "https://github.com/a/b".into(),
+            time: String::new(),
+        };
+        let message = entry.to_message();
+        assert!(message.starts_with("mgt sync push repos/lib.rf\n\n"));
+        assert_eq!(LedgerEntry::from_message(&message), Some(entry));
+        assert_eq!(LedgerEntry::from_message("some other commit"), None);
+    }
+}
@@ -0,0 +1,372 @@
+use super::cli::MgtCommandLint;
+use super::core;
+use super::die;
+use super::git_helpers3;
+use super::repo_file;
+use super::repo_file::RepoFile;
+use super::tags;
+use super::verify;
+use super::workspace;
+use gitfilter::filter::FilterRule;
+use gitfilter::filter::FilterRules;
+use toml::Value;
+
+const KNOWN_KEYS: [&str; 9] = [
+    "repo", "include_as", "include", "exclude", "overlay", "versioned", "vars", "extends", "tags",
+];
+const KNOWN_REPO_KEYS: [&str; 7] = [
+    "remote", "name", "branch", "synthesize_gitfiles", "allow_destination_collisions", "squash",
+    "push_mode",
+];
+const KNOWN_VERSIONED_KEYS: [&str; 5] = ["since", "until", "include_as", "include", "exclude"];
+/// if these appear more than once, the last one replaces the others
+const LAST_ONE_WINS_KEYS: [&str; 3] = ["include_as", "include", "exclude"];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LintLevel {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LintMessage {
+    pub level: LintLevel,
+    /// 1-based line of the repo file that the message is about.
+    /// None if we dont know, ie: the rule came from a repo file that
+    /// this one extends
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl LintMessage {
+    pub fn new(level: LintLevel, line: Option<usize>, message: String) -> LintMessage {
+        LintMessage { level, line, message }
+    }
+}
+
+/// find the first line that contains `needle`. returns a 1-based line number
+fn find_line(lines: &[String], needle: &str) -> Option<usize> {
+    lines.iter().position(|l| l.contains(needle)).map(|i| i + 1)
+}
+
+/// find the first line at or after `start` that defines `key`,
+/// either as `key = ...` or as part of a table header
+fn find_key_line(lines: &[String], start: usize, key: &str) -> Option<usize> {
+    let quoted = format!("\"{}\"", key);
+    lines.iter().enumerate().skip(start).find(|(_, line)| {
+        let line = line.trim_start();
+        if line.starts_with('[') {
+            return line.trim_matches(|c| c == '[' || c == ']').split('.').any(|k| k.trim() == key);
+        }
+        let name = line.split('=').next().unwrap_or("").trim();
+        name == key || name == quoted
+    }).map(|(i, _)| i + 1)
+}
+
+fn lint_unknown_keys(
+    table: &toml::map::Map<String, Value>,
+    known_keys: &[&str],
+    section: &str,
+    lines: &[String],
+    start: usize,
+    messages: &mut Vec<LintMessage>,
+) {
+    for k in table.keys() {
+        if !known_keys.contains(&k.as_str()) {
+            messages.push(LintMessage::new(
+                LintLevel::Warning,
+                find_key_line(lines, start, k),
+                format!("unknown key '{}'{} is ignored", k, section),
+            ));
+        }
+    }
+}
+
+/// check that every segment of the repo file parses, and that
+/// it does not contain keys that the parser would silently ignore
+pub fn lint_repo_file_structure(lines: &[String]) -> Vec<LintMessage> {
+    let mut messages = vec![];
+    let mut seen_keys = vec![];
+    for (start, segment) in repo_file::split_repo_file_into_segments(lines) {
+        let t = match segment.parse::<Value>() {
+            Ok(Value::Table(t)) => t,
+            Ok(_) => continue,
+            Err(e) => {
+                let line = e.line_col().map(|(line, _)| start + line + 1);
+                messages.push(LintMessage::new(LintLevel::Error, line, format!("failed to parse: {}", e)));
+                continue;
+            }
+        };
+        lint_unknown_keys(&t, &KNOWN_KEYS, "", lines, start, &mut messages);
+        for key in LAST_ONE_WINS_KEYS.iter().filter(|k| t.contains_key(**k)) {
+            if seen_keys.contains(key) {
+                messages.push(LintMessage::new(
+                    LintLevel::Error,
+                    find_key_line(lines, start, key),
+                    format!("'{}' appears more than once, only the last one is used", key),
+                ));
+            }
+            seen_keys.push(*key);
+        }
+        if let Some(Value::Table(repo)) = t.get("repo") {
+            lint_unknown_keys(repo, &KNOWN_REPO_KEYS, " in [repo]", lines, start, &mut messages);
+        }
+        match t.get("versioned") {
+            Some(Value::Array(versioned)) => for v in versioned {
+                if let Value::Table(v) = v {
+                    lint_unknown_keys(v, &KNOWN_VERSIONED_KEYS, " in [[versioned]]", lines, start, &mut messages);
+                }
+            },
+            Some(Value::Table(v)) => {
+                lint_unknown_keys(v, &KNOWN_VERSIONED_KEYS, " in [[versioned]]", lines, start, &mut messages);
+            }
+            _ => (),
+        }
+    }
+    messages
+}
+
+/// check the rules of an already parsed repo file against the
+/// files that exist at HEAD. `lines` are only used to find where
+/// in the repo file each problem is
+pub fn lint_repo_file_rules(
+    lines: &[String],
+    repo_file: &RepoFile,
+    files_at_head: &[String],
+) -> Vec<LintMessage> {
+    let mut messages = vec![];
+    let line_of = |path: &str| find_line(lines, &format!("\"{}\"", path));
+
+    if let Some(ref remote) = repo_file.remote_repo {
+        if !core::is_valid_remote_repo(remote) {
+            messages.push(LintMessage::new(
+                LintLevel::Error,
+                line_of(remote),
+                format!("remote '{}' is not a valid git url", remote),
+            ));
+        }
+    }
+    if let Some(ref push_mode) = repo_file.push_mode {
+        if !repo_file::PUSH_MODES.contains(&push_mode.as_str()) {
+            messages.push(LintMessage::new(
+                LintLevel::Error,
+                line_of(push_mode),
+                format!("push_mode '{}' must be one of {:?}", push_mode, repo_file::PUSH_MODES),
+            ));
+        }
+    }
+
+    for (local, remote) in repo_file.tags.iter().flatten() {
+        if let Err(e) = tags::validate_tag_rule(local, remote) {
+            messages.push(LintMessage::new(LintLevel::Error, line_of(local), e));
+        }
+    }
+
+    let mut file_ops = verify::get_vec_of_current_file_ops_with_order(repo_file, true);
+    for file_op in file_ops.iter() {
+        let (kind, src, dest) = match file_op {
+            verify::FileOpType::IncludeAs(src, dest) => ("include_as", src.trim_start(), Some(dest.trim())),
+            verify::FileOpType::Include(src) => ("include", src.trim_start(), None),
+            verify::FileOpType::Exclude(src) => ("exclude", src.trim_start(), None),
+        };
+        if src.is_empty() {
+            continue;
+        }
+        let is_dir_at_head = files_at_head.iter()
+            .any(|f| f.len() > src.len() && f.starts_with(src) && f[src.len()..].starts_with('/'));
+        if !src.ends_with('/') && is_dir_at_head {
+            messages.push(LintMessage::new(
+                LintLevel::Error,
+                line_of(src),
+                format!("{} '{}' is a directory but does not end with a slash, so it also matches every other path that starts with '{}'", kind, src, src),
+            ));
+        }
+        if let Some(dest) = dest {
+            if src.ends_with('/') && !dest.is_empty() && !dest.ends_with('/') {
+                messages.push(LintMessage::new(
+                    LintLevel::Error,
+                    line_of(src),
+                    format!("include_as '{}' is a directory, so its destination '{}' must also end with a slash", src, dest),
+                ));
+            }
+            if !files_at_head.iter().any(|f| f.starts_with(src)) {
+                messages.push(LintMessage::new(
+                    LintLevel::Error,
+                    line_of(src),
+                    format!("include_as source '{}' does not exist at HEAD", src),
+                ));
+            }
+        }
+    }
+
+    let filter_rules = verify::make_filter_rules(&mut file_ops);
+    lint_excludes(lines, &filter_rules, files_at_head, &mut messages);
+    messages
+}
+
+/// an exclude that matches some files, but never is the rule that
+/// decides whether to keep them, is shadowed by whatever comes after it
+fn lint_excludes(
+    lines: &[String],
+    filter_rules: &FilterRules,
+    files_at_head: &[String],
+    messages: &mut Vec<LintMessage>,
+) {
+    let mut matched = vec![0; filter_rules.len()];
+    let mut decided = vec![0; filter_rules.len()];
+    // for every rule, the rule that decided the first file it matched
+    let mut decided_instead: Vec<Option<usize>> = vec![None; filter_rules.len()];
+    for path in files_at_head {
+        let mut path = path.clone();
+        let mut matching = vec![];
+        let (_, decided_by) = gitfilter::filter::should_use_file_ex(
+            &mut path, filter_rules, false, |i| matching.push(i));
+        for i in matching {
+            matched[i] += 1;
+            if decided_instead[i].is_none() {
+                decided_instead[i] = decided_by;
+            }
+        }
+        if let Some(i) = decided_by {
+            decided[i] += 1;
+        }
+    }
+
+    for (i, filter_rule) in filter_rules.iter().enumerate() {
+        let exclude = match filter_rule {
+            FilterRule::FilterRulePathExclude(exclude) => exclude,
+            _ => continue,
+        };
+        let line = find_line(lines, &format!("\"{}\"", exclude));
+        if matched[i] == 0 {
+            messages.push(LintMessage::new(
+                LintLevel::Warning,
+                line,
+                format!("exclude '{}' does not match any file at HEAD", exclude),
+            ));
+        } else if decided[i] == 0 {
+            let shadowed_by = match decided_instead[i] {
+                Some(j) => verify::describe_filter_rule(&filter_rules[j]),
+                None => "a later rule".into(),
+            };
+            messages.push(LintMessage::new(
+                LintLevel::Warning,
+                line,
+                format!("exclude '{}' has no effect, it is shadowed by {}", exclude, shadowed_by),
+            ));
+        }
+    }
+}
+
+/// lint the repo file at `path`. the structure is checked first, and
+/// the rules are only checked if the repo file can be parsed
+pub fn lint_repo_file(path: &str, files_at_head: &[String]) -> Vec<LintMessage> {
+    let lines = match repo_file::read_file_into_lines(path) {
+        Ok(l) => l,
+        Err(e) => return vec![LintMessage::new(LintLevel::Error, None, e.to_string())],
+    };
+    let mut messages = lint_repo_file_structure(&lines);
+    if messages.iter().any(|m| m.level == LintLevel::Error) {
+        return messages;
+    }
+    match repo_file::parse_repo_file_from_toml_path_res(path) {
+        Ok(repo_file) => messages.extend(lint_repo_file_rules(&lines, &repo_file, files_at_head)),
+        Err(e) => messages.push(LintMessage::new(LintLevel::Error, None, e.to_string())),
+    }
+    messages
+}
+
+pub fn print_lint_messages(path: &str, messages: &[LintMessage]) {
+    for m in messages {
+        let level = match m.level {
+            LintLevel::Warning => "warning",
+            LintLevel::Error => "error",
+        };
+        match m.line {
+            Some(line) => println!("{}:{}: {}: {}", path, line, level, m.message),
+            None => println!("{}: {}: {}", path, level, m.message),
+        }
+    }
+}
+
+pub fn run_lint(cmd: &mut MgtCommandLint) {
+    let repo_file_paths: Vec<String> = if cmd.repo_files.is_empty() {
+        match workspace::get_workspace_repo_files(None) {
+            Ok(paths) => paths.iter().map(|p| p.display().to_string()).collect(),
+            Err(e) => die!("{}", e),
+        }
+    } else {
+        cmd.repo_files.clone()
+    };
+    let files_at_head: Vec<String> = match git_helpers3::get_all_files_in_repo() {
+        Ok(text) => text.split('\n').map(|line| line.to_string()).collect(),
+        Err(e) => die!("Failed to get all files in git repo:\n{}", e),
+    };
+
+    let mut num_errors = 0;
+    let mut num_warnings = 0;
+    for path in repo_file_paths.iter() {
+        let messages = lint_repo_file(path, &files_at_head);
+        print_lint_messages(path, &messages);
+        for m in messages {
+            match m.level {
+                LintLevel::Warning => num_warnings += 1,
+                LintLevel::Error => num_errors += 1,
+            }
+        }
+    }
+    println!("{} error(s), {} warning(s)", num_errors, num_warnings);
+    if num_errors > 0 {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lint_finds_structure_and_rule_problems() {
+        let repo_file_str = "[repo]\nremote = \"https://github.com/a/b\"\nbrnach = \"main\"\n\n\ninclude_as = { \"lib\" = \"src\", \"docs/\" = \"doc\", \"gone/\" = \" \" }\n\n\nexclude = [\"lib/a.txt\", \"nothing/\"]\n";
+        let lines: Vec<String> = repo_file_str.split('\n').map(|s| s.to_string()).collect();
+        let structure = lint_repo_file_structure(&lines);
+        assert_eq!(structure, vec![
+            LintMessage::new(LintLevel::Warning, Some(3), "unknown key 'brnach' in [repo] is ignored".into()),
+        ]);
+
+        let repo_file = repo_file::parse_repo_file_from_toml_lines(lines.clone()).unwrap();
+        let files_at_head: Vec<String> = vec!["lib/a.txt".into(), "docs/b.md".into()];
+        let rules = lint_repo_file_rules(&lines, &repo_file, &files_at_head);
+        let rule_messages: Vec<(LintLevel, Option<usize>)> = rules.iter().map(|m| (m.level, m.line)).collect();
+        assert_eq!(rule_messages, vec![
+            // lib without a slash
+            (LintLevel::Error, Some(6)),
+            // docs/ -> doc
+            (LintLevel::Error, Some(6)),
+            // gone/ does not exist
+            (LintLevel::Error, Some(6)),
+            // nothing/ matches nothing
+            (LintLevel::Warning, Some(9)),
+        ]);
+        assert!(rules[0].message.contains("'lib'"));
+        assert!(rules[1].message.contains("'doc'"));
+        assert!(rules[2].message.contains("'gone/'"));
+        assert!(rules[3].message.contains("'nothing/'"));
+
+        let filter_rules = vec![
+            FilterRule::FilterRulePathExclude("lib/".into()),
+            FilterRule::FilterRulePathInclude("lib/".into()),
+        ];
+        let mut messages = vec![];
+        lint_excludes(&lines, &filter_rules, &files_at_head, &mut messages);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].message, "exclude 'lib/' has no effect, it is shadowed by include lib/");
+
+        // the parser only keeps the last include, so the first one is lost
+        let repo_file_str = "include = [\"docs/\"]\n\n\n[repo]\nname = \"a\"\n\n\ninclude = \"scripts/\"\n";
+        let lines: Vec<String> = repo_file_str.split('\n').map(|s| s.to_string()).collect();
+        assert_eq!(lint_repo_file_structure(&lines), vec![
+            LintMessage::new(LintLevel::Error, Some(8), "'include' appears more than once, only the last one is used".into()),
+        ]);
+    }
+}
@@ -0,0 +1,142 @@
+use super::exec_helpers;
+use super::git_helpers3;
+use super::ioerre;
+use std::fs;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// commands that make and delete temporary branches take this lock,
+/// so that two of them never run in the same repository at the same time.
+/// it is relative to the git common dir, so that worktrees share it
+pub const LOCK_FILE: &str = "mgt/lock";
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LockInfo {
+    pub pid: u32,
+    pub host: String,
+    pub command: String,
+}
+
+impl LockInfo {
+    pub fn to_contents(&self) -> String {
+        format!("pid: {}\nhost: {}\ncommand: {}\n", self.pid, self.host, self.command)
+    }
+
+    pub fn from_contents(contents: &str) -> Option<LockInfo> {
+        let mut pid = None;
+        let mut host = None;
+        let mut command = None;
+        for line in contents.lines() {
+            if let Some(value) = line.strip_prefix("pid: ") {
+                pid = value.trim().parse::<u32>().ok();
+            } else if let Some(value) = line.strip_prefix("host: ") {
+                host = Some(value.to_string());
+            } else if let Some(value) = line.strip_prefix("command: ") {
+                command = Some(value.to_string());
+            }
+        }
+        Some(LockInfo { pid: pid?, host: host?, command: command.unwrap_or_default() })
+    }
+}
+
+/// the lock is released when this is dropped, or by the OS when mgt
+/// exits without dropping it, eg: from `die!` or `process::exit`.
+/// the file is kept so that a waiting command never locks a removed file
+pub struct RepoLock {
+    file: File,
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        // the contents are only a diagnostic for whoever fails to take it
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+pub fn get_lock_path() -> io::Result<PathBuf> {
+    let git_dir = git_helpers3::git_output(&["rev-parse", "--git-common-dir"])?;
+    // commands can change directory after taking the lock
+    let git_dir = Path::new(&git_dir).canonicalize()?;
+    Ok(git_dir.join(LOCK_FILE))
+}
+
+pub fn get_host_name() -> String {
+    if let Ok(name) = fs::read_to_string("/etc/hostname") {
+        if !name.trim().is_empty() {
+            return name.trim().to_string();
+        }
+    }
+    if let Ok(out) = exec_helpers::execute(&["hostname"]) {
+        if out.status == 0 && !out.stdout.trim().is_empty() {
+            return out.stdout.trim().to_string();
+        }
+    }
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "unknown".into())
+}
+
+/// take the lock at `path`, and write who holds it into the file.
+/// fails if another command holds it
+pub fn take_lock(path: &Path, command: &str) -> io::Result<RepoLock> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // not truncated, the contents belong to whoever holds the lock
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+    match file.try_lock() {
+        Ok(()) => (),
+        Err(TryLockError::WouldBlock) => {
+            let mut contents = String::new();
+            let _ = file.read_to_string(&mut contents);
+            return match LockInfo::from_contents(&contents) {
+                Some(other) => ioerre!(
+                    "Another mgt command is running in this repository: `{}` (pid {} on {})",
+                    other.command, other.pid, other.host
+                ),
+                None => ioerre!("Another mgt command is running in this repository, it holds {}", path.display()),
+            };
+        }
+        Err(TryLockError::Error(e)) => return Err(e),
+    }
+    let info = LockInfo {
+        pid: std::process::id(),
+        host: get_host_name(),
+        command: command.to_string(),
+    };
+    // a command that exited without dropping its lock leaves its contents behind
+    file.set_len(0)?;
+    file.write_all(info.to_contents().as_bytes())?;
+    file.flush()?;
+    Ok(RepoLock { file })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lock_is_exclusive_until_dropped_or_exited() {
+        let dir = std::env::temp_dir().join(format!("mgt-lock-test-{}", std::process::id()));
+        let path = dir.join(LOCK_FILE);
+        let lock = take_lock(&path, "mgt sync a.rf").unwrap();
+        let info = LockInfo::from_contents(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(info.pid, std::process::id());
+        assert_eq!(info.command, "mgt sync a.rf");
+        let err = take_lock(&path, "mgt check").err().unwrap();
+        assert!(err.to_string().contains("`mgt sync a.rf`"));
+        drop(lock);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+        // a command that exited without dropping the lock leaves
+        // its contents, but the OS released the lock itself
+        let gone = LockInfo { pid: 7, host: "here".into(), command: "mgt sync".into() };
+        fs::write(&path, gone.to_contents()).unwrap();
+        let lock = take_lock(&path, "mgt check").unwrap();
+        let info = LockInfo::from_contents(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(info.command, "mgt check");
+        drop(lock);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
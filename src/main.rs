@@ -2,18 +2,31 @@ use die::*;
 use exechelper as exec_helpers;
 use simple_interaction as interact;
 
+mod audit;
 mod blob_log_parser;
 mod check;
 mod cli;
 mod core;
 mod difflog;
+mod format;
 mod git_helpers3;
+mod graft;
+mod init;
+mod jobs;
+mod ledger;
+mod lint;
+mod lock;
+mod preview;
 mod repo_file;
 mod split_in;
 mod split_out;
+mod squash;
 mod sync;
+mod tags;
 mod topbase;
+mod tui;
 mod verify;
+mod workspace;
 
 fn main() {
     cli::validate_input_and_run(cli::get_cli_input());
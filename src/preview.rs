@@ -0,0 +1,181 @@
+use super::exec_helpers;
+use super::git_helpers3;
+use super::git_helpers3::CommitWithBlobs;
+use super::ioerr;
+use super::repo_file::RepoFile;
+use super::split_in;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+/// a file that a remote commit changed, at the path it has
+/// locally. a hash is empty if the file does not exist on that side
+#[derive(Debug, PartialEq, Clone)]
+pub struct FileChange {
+    pub path: String,
+    pub old_hash: String,
+    pub new_hash: String,
+}
+
+/// apply the changes of one commit onto `files`, the (path, blob hash) of
+/// every local file. `merge` does a 3-way merge of (base, ours, theirs)
+/// blob hashes and returns the hash of the result, or None if it conflicts.
+/// returns the paths that conflict, those are left as they were
+pub fn apply_changes<M>(
+    files: &mut HashMap<String, String>,
+    changes: &[FileChange],
+    merge: &mut M,
+) -> Vec<String>
+where
+    M: FnMut(&str, &str, &str) -> Option<String>,
+{
+    let mut conflicts = vec![];
+    for change in changes {
+        let ours = files.get(&change.path).cloned().unwrap_or_default();
+        let result = if ours == change.old_hash {
+            Some(change.new_hash.clone())
+        } else if ours == change.new_hash {
+            Some(ours)
+        } else if ours.is_empty() || change.old_hash.is_empty() || change.new_hash.is_empty() {
+            // one side added, deleted, or modified what the other side deleted
+            None
+        } else {
+            merge(&change.old_hash, &ours, &change.new_hash)
+        };
+        match result {
+            Some(hash) if hash.is_empty() => {
+                files.remove(&change.path);
+            }
+            Some(hash) => {
+                files.insert(change.path.clone(), hash);
+            }
+            None => conflicts.push(change.path.clone()),
+        }
+    }
+    conflicts
+}
+
+fn write_blob_to(hash: &str, path: &Path) -> io::Result<()> {
+    let file = File::create(path)?;
+    let exec_args = ["git", "cat-file", "blob", hash];
+    let status = exec_helpers::spawn_with_env_ex(
+        &exec_args, &[], &[], Some(Stdio::null()), Some(Stdio::null()), Some(Stdio::from(file)),
+    )?.wait()?;
+    if !status.success() {
+        return Err(ioerr!("Failed to read blob {}", hash));
+    }
+    Ok(())
+}
+
+/// a content merge of 3 blobs, the same way that a rebase would
+/// merge them. it writes the merged blob into the object database
+/// but never touches the index or the worktree
+fn merge_blobs(temp_dir: &Path, base: &str, ours: &str, theirs: &str) -> io::Result<Option<String>> {
+    let [base_path, ours_path, theirs_path, merged_path]: [PathBuf; 4] =
+        ["base", "ours", "theirs", "merged"].map(|name| temp_dir.join(name));
+    write_blob_to(base, &base_path)?;
+    write_blob_to(ours, &ours_path)?;
+    write_blob_to(theirs, &theirs_path)?;
+    let paths = [&ours_path, &base_path, &theirs_path].map(|p| p.to_string_lossy().to_string());
+    let exec_args = ["git", "merge-file", "-p", "-q", &paths[0], &paths[1], &paths[2]];
+    let status = exec_helpers::spawn_with_env_ex(
+        &exec_args, &[], &[], Some(Stdio::null()), Some(Stdio::null()), Some(Stdio::from(File::create(&merged_path)?)),
+    )?.wait()?;
+    // the exit code is the number of conflicts
+    if !status.success() {
+        return Ok(None);
+    }
+    let merged = git_helpers3::git_output(&["hash-object", "-w", &merged_path.to_string_lossy()])?;
+    Ok(Some(merged))
+}
+
+/// for every commit that a pull would take, oldest first, the local paths
+/// that would conflict if the commits before it were already applied.
+/// nothing here touches the index or the worktree
+pub fn preview_pull(
+    repo_file: &RepoFile,
+    local_branch: &str,
+    commits_to_pull: &[CommitWithBlobs],
+) -> io::Result<Vec<(String, Vec<String>)>> {
+    let filter_rules = split_in::generate_gitfilter_filterrules(repo_file, false);
+    let mut files: HashMap<String, String> = git_helpers3::get_blobs_at_rev(local_branch)
+        .map_err(|e| ioerr!("{}", e))?
+        .into_iter()
+        .collect();
+    let temp_dir = std::env::temp_dir().join(format!("mgt-preview-{}", std::process::id()));
+    std::fs::create_dir_all(&temp_dir)?;
+    let mut merge_error = None;
+    let mut merge = |base: &str, ours: &str, theirs: &str| {
+        match merge_blobs(&temp_dir, base, ours, theirs) {
+            Ok(merged) => merged,
+            Err(e) => {
+                merge_error = Some(e);
+                None
+            }
+        }
+    };
+
+    let mut previews = vec![];
+    for commit in commits_to_pull.iter().rev().filter(|c| !c.commit.is_merge) {
+        let hash = &commit.commit.id.hash;
+        let changes: Vec<FileChange> = git_helpers3::get_commit_changes(hash)
+            .map_err(|e| ioerr!("{}", e))?
+            .into_iter()
+            .filter_map(|(path, old_hash, new_hash)| {
+                let mut local_path = path;
+                if gitfilter::filter::should_use_file(&mut local_path, &filter_rules, false) {
+                    Some(FileChange { path: local_path, old_hash, new_hash })
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let conflicts = apply_changes(&mut files, &changes, &mut merge);
+        previews.push((hash.clone(), conflicts));
+    }
+    let _ = std::fs::remove_dir_all(&temp_dir);
+    if let Some(e) = merge_error {
+        return Err(e);
+    }
+    Ok(previews)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn change(path: &str, old_hash: &str, new_hash: &str) -> FileChange {
+        FileChange { path: path.into(), old_hash: old_hash.into(), new_hash: new_hash.into() }
+    }
+
+    #[test]
+    fn preview_applies_changes_and_finds_conflicts() {
+        let mut files: HashMap<String, String> = vec![
+            ("a.txt".to_string(), "a1".to_string()),
+            ("b.txt".to_string(), "b2".to_string()),
+        ].into_iter().collect();
+        // b.txt changed on both sides but it can be merged
+        let mut merge = |base: &str, _: &str, _: &str| {
+            if base == "b1" { Some("merged".to_string()) } else { None }
+        };
+        let conflicts = apply_changes(&mut files, &[
+            change("a.txt", "a1", "a2"),
+            change("b.txt", "b1", "b3"),
+            change("d.txt", "", "d1"),
+        ], &mut merge);
+        assert!(conflicts.is_empty());
+        assert_eq!(files["a.txt"], "a2");
+        assert_eq!(files["b.txt"], "merged");
+        assert_eq!(files["d.txt"], "d1");
+
+        // the next commit is applied on top of the first one
+        let conflicts = apply_changes(&mut files, &[
+            change("a.txt", "a1", "a3"),
+            change("d.txt", "d1", ""),
+        ], &mut merge);
+        assert_eq!(conflicts, vec!["a.txt".to_string()]);
+        assert!(!files.contains_key("d.txt"));
+    }
+}
@@ -1,12 +1,37 @@
 use super::die;
 use super::ioerr;
 use super::ioerre;
+use super::workspace;
 use std::fs::File;
 use std::io;
 use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use toml::Value;
 
+/// where the contents of an overlay file come from
+#[derive(Debug, PartialEq, Clone)]
+pub enum OverlaySource {
+    /// path to a file in the local repository
+    LocalFile(String),
+    /// the contents themselves
+    Inline(String),
+}
+
+/// include/include_as/exclude rules that only apply to part of the history.
+/// `since` is the first commit these rules apply to, and `until`
+/// is the first commit they no longer apply to
+#[derive(Debug, PartialEq, Default, Clone)]
+pub struct VersionedRules {
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub include_as: Option<Vec<String>>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+}
+
+/// the values that push_mode can have
+pub const PUSH_MODES: [&str; 2] = ["direct", "branch"];
+
 #[derive(Debug, PartialEq, Default)]
 pub struct RepoFile {
     pub repo_name: Option<String>,
@@ -15,6 +40,31 @@ pub struct RepoFile {
     pub include_as: Option<Vec<String>>,
     pub include: Option<Vec<String>>,
     pub exclude: Option<Vec<String>>,
+    /// when splitting out, synthesize a .gitignore and .gitattributes
+    /// at the root from the ones above the included directories
+    pub synthesize_gitfiles: bool,
+    /// by default it is an error for include_as to map two different
+    /// source paths to the same destination path. this allows it
+    pub allow_destination_collisions: bool,
+    /// when pushing out, squash every commit that is
+    /// ahead of the remote into a single commit
+    pub squash: bool,
+    /// one of PUSH_MODES. "direct" (the default) pushes to the remote
+    /// branch, "branch" pushes to a new branch that can be reviewed
+    pub push_mode: Option<String>,
+    /// files that only exist in the split repository,
+    /// as pairs of (destination path, source)
+    pub overlay: Option<Vec<(String, OverlaySource)>>,
+    pub versioned: Option<Vec<VersionedRules>>,
+    /// path to another repo file that this one builds on,
+    /// relative to the directory of this repo file
+    pub extends: Option<String>,
+    /// (name, value) pairs that get substituted for `${name}`
+    /// in the values of this repo file
+    pub vars: Option<Vec<(String, String)>>,
+    /// (local pattern, remote pattern) pairs of tag names that sync
+    /// creates on the other side. the `*` of a pattern is kept as is
+    pub tags: Option<Vec<(String, String)>>,
 }
 
 impl RepoFile {
@@ -50,9 +100,197 @@ pub fn line_is_break(line: &String) -> bool {
     true
 }
 
+/// parses the repo file, and resolves its `extends` and `[vars]`.
+/// if the repo file is part of a workspace, it is applied
+/// on top of the defaults of that workspace
 pub fn parse_repo_file_from_toml(filename: &str) -> io::Result<RepoFile> {
     let lines = read_file_into_lines(filename)?;
-    parse_repo_file_from_toml_lines(lines)
+    parse_repo_file_from_toml_lines_resolved(lines, Path::new(filename))
+}
+
+/// same as `parse_repo_file_from_toml`, but for lines that
+/// did not necessarily come from `path`, ie: the contents of the repo
+/// file at an older commit. `path` is used to find what it extends,
+/// and which workspace it is in
+pub fn parse_repo_file_from_toml_lines_resolved(
+    lines: Vec<String>,
+    path: &Path,
+) -> io::Result<RepoFile> {
+    let mut extends_chain = vec![];
+    let repo_file = parse_repo_file_from_toml_lines(lines)?;
+    let mut repo_file = resolve_repo_file_extends(repo_file, path, &mut extends_chain)?;
+    if let Some(defaults) = workspace::get_workspace_defaults(path)? {
+        repo_file = merge_repo_files(defaults, repo_file);
+    }
+    substitute_repo_file_vars(&mut repo_file)?;
+    repo_file.extends = None;
+    repo_file.vars = None;
+    Ok(repo_file)
+}
+
+/// parse the repo file at `path`, and recursively merge it
+/// on top of whatever repo file it extends. `extends_chain` is
+/// the list of repo files we are currently in the middle of parsing,
+/// which is used to detect cycles
+pub fn parse_repo_file_with_extends(
+    path: &Path,
+    extends_chain: &mut Vec<PathBuf>,
+) -> io::Result<RepoFile> {
+    let filename = match path.to_str() {
+        Some(s) => s,
+        None => return ioerre!("Failed to find repo file: {:?}", path),
+    };
+    let lines = read_file_into_lines(filename)?;
+    let repo_file = parse_repo_file_from_toml_lines(lines)?;
+    resolve_repo_file_extends(repo_file, path, extends_chain)
+}
+
+/// if `repo_file` extends another repo file, merge it on top of that one.
+/// `path` is where `repo_file` lives
+pub fn resolve_repo_file_extends(
+    repo_file: RepoFile,
+    path: &Path,
+    extends_chain: &mut Vec<PathBuf>,
+) -> io::Result<RepoFile> {
+    let filename = path.display().to_string();
+    let extends = match repo_file.extends {
+        Some(ref e) => e.clone(),
+        None => return Ok(repo_file),
+    };
+
+    let canonical_path = path.canonicalize()
+        .map_err(|e| ioerr!("Failed to find repo file: {}, {}", filename, e))?;
+    extends_chain.push(canonical_path);
+    let extends_path = match path.parent() {
+        Some(dir) => dir.join(&extends),
+        None => PathBuf::from(&extends),
+    };
+    if let Ok(canonical_extends) = extends_path.canonicalize() {
+        if extends_chain.contains(&canonical_extends) {
+            let mut cycle: Vec<String> = extends_chain.iter()
+                .map(|p| p.display().to_string()).collect();
+            cycle.push(canonical_extends.display().to_string());
+            return ioerre!("Repo file extends cycle detected: {}", cycle.join(" -> "));
+        }
+    }
+    let base = parse_repo_file_with_extends(&extends_path, extends_chain)
+        .map_err(|e| ioerr!("{}\nwhile resolving extends = \"{}\" of {}", e, extends, filename))?;
+    extends_chain.pop();
+    Ok(merge_repo_files(base, repo_file))
+}
+
+fn merge_lists<T>(base: Option<Vec<T>>, top: Option<Vec<T>>) -> Option<Vec<T>> {
+    match (base, top) {
+        (Some(mut b), Some(t)) => {
+            b.extend(t);
+            Some(b)
+        }
+        (b, None) => b,
+        (None, t) => t,
+    }
+}
+
+/// single values of `top` override the ones of `base`, and
+/// lists (ie: include, exclude) of `top` get added after the ones of `base`
+pub fn merge_repo_files(base: RepoFile, top: RepoFile) -> RepoFile {
+    let mut vars = base.vars.unwrap_or_default();
+    for (name, value) in top.vars.unwrap_or_default() {
+        vars.retain(|(n, _)| *n != name);
+        vars.push((name, value));
+    }
+    RepoFile {
+        repo_name: top.repo_name.or(base.repo_name),
+        remote_repo: top.remote_repo.or(base.remote_repo),
+        remote_branch: top.remote_branch.or(base.remote_branch),
+        include_as: merge_lists(base.include_as, top.include_as),
+        include: merge_lists(base.include, top.include),
+        exclude: merge_lists(base.exclude, top.exclude),
+        synthesize_gitfiles: top.synthesize_gitfiles || base.synthesize_gitfiles,
+        allow_destination_collisions: top.allow_destination_collisions || base.allow_destination_collisions,
+        squash: top.squash || base.squash,
+        push_mode: top.push_mode.or(base.push_mode),
+        overlay: merge_lists(base.overlay, top.overlay),
+        versioned: merge_lists(base.versioned, top.versioned),
+        extends: top.extends,
+        vars: if vars.is_empty() { None } else { Some(vars) },
+        tags: merge_lists(base.tags, top.tags),
+    }
+}
+
+/// replace every `${name}` in `value` with the value of that var.
+/// errors if a var is not defined
+pub fn substitute_vars(value: &str, vars: &[(String, String)]) -> io::Result<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut remaining = value;
+    while let Some(start) = remaining.find("${") {
+        out.push_str(&remaining[0..start]);
+        let after_start = &remaining[(start + 2)..];
+        let end = match after_start.find('}') {
+            Some(e) => e,
+            None => return ioerre!("Unterminated variable in repo file value: {}", value),
+        };
+        let name = &after_start[0..end];
+        match vars.iter().find(|(n, _)| n == name) {
+            Some((_, var_value)) => out.push_str(var_value),
+            None => return ioerre!("Undefined variable '{}' in repo file value: {}", name, value),
+        }
+        remaining = &after_start[(end + 1)..];
+    }
+    out.push_str(remaining);
+    Ok(out)
+}
+
+fn substitute_opt(value: &mut Option<String>, vars: &[(String, String)]) -> io::Result<()> {
+    if let Some(v) = value {
+        *v = substitute_vars(v, vars)?;
+    }
+    Ok(())
+}
+
+fn substitute_list(list: &mut Option<Vec<String>>, vars: &[(String, String)]) -> io::Result<()> {
+    if let Some(l) = list {
+        for v in l.iter_mut() {
+            *v = substitute_vars(v, vars)?;
+        }
+    }
+    Ok(())
+}
+
+/// apply the `[vars]` of the repo file to all of its values
+pub fn substitute_repo_file_vars(repo_file: &mut RepoFile) -> io::Result<()> {
+    let vars = repo_file.vars.clone().unwrap_or_default();
+    substitute_opt(&mut repo_file.repo_name, &vars)?;
+    substitute_opt(&mut repo_file.remote_repo, &vars)?;
+    substitute_opt(&mut repo_file.remote_branch, &vars)?;
+    substitute_list(&mut repo_file.include_as, &vars)?;
+    substitute_list(&mut repo_file.include, &vars)?;
+    substitute_list(&mut repo_file.exclude, &vars)?;
+    if let Some(ref mut tags) = repo_file.tags {
+        for (local, remote) in tags.iter_mut() {
+            *local = substitute_vars(local, &vars)?;
+            *remote = substitute_vars(remote, &vars)?;
+        }
+    }
+    if let Some(ref mut overlay) = repo_file.overlay {
+        for (dest, source) in overlay.iter_mut() {
+            *dest = substitute_vars(dest, &vars)?;
+            match source {
+                OverlaySource::LocalFile(s) | OverlaySource::Inline(s) => {
+                    *s = substitute_vars(s, &vars)?;
+                }
+            }
+        }
+    }
+    if let Some(ref mut versioned) = repo_file.versioned {
+        for rules in versioned.iter_mut() {
+            substitute_opt(&mut rules.since, &vars)?;
+            substitute_opt(&mut rules.until, &vars)?;
+            substitute_list(&mut rules.include_as, &vars)?;
+            substitute_list(&mut rules.include, &vars)?;
+            substitute_list(&mut rules.exclude, &vars)?;
+        }
+    }
+    Ok(())
 }
 
 /// this function will exit on error. if you do not want to exit on error,
@@ -77,6 +315,13 @@ pub fn parse_repo_file_from_toml_path_res<P: AsRef<Path>>(filename: P) -> io::Re
 }
 
 pub fn parse_repo_file_from_toml_lines(lines: Vec<String>) -> io::Result<RepoFile> {
+    let toml_segments = split_repo_file_into_segments(&lines)
+        .into_iter().map(|(_, segment)| segment).collect();
+    parse_repo_file_from_toml_segments(toml_segments)
+}
+
+/// returns pairs of (index of the first line of the segment, segment)
+pub fn split_repo_file_into_segments(lines: &[String]) -> Vec<(usize, String)> {
     // even though this is a toml file, and we have a toml parser
     // we still want to split by lines, and then parse specific sections
     // this is because if a user has:
@@ -130,11 +375,11 @@ pub fn parse_repo_file_from_toml_lines(lines: Vec<String>) -> io::Result<RepoFil
             toml_segment.push_str(string_vec[j]);
         }
 
-        toml_segments.push(toml_segment);
+        toml_segments.push((current_index, toml_segment));
         current_index = i;
     }
 
-    parse_repo_file_from_toml_segments(toml_segments)
+    toml_segments
 }
 
 pub fn toml_value_to_string_opt(toml_value: &Value) -> Option<String> {
@@ -151,6 +396,10 @@ pub fn parse_repo_section(toml_value: &Value, repofile: &mut RepoFile) {
                 "remote" => repofile.remote_repo = toml_value_to_string_opt(v),
                 "name" => repofile.repo_name = toml_value_to_string_opt(v),
                 "branch" => repofile.remote_branch = toml_value_to_string_opt(v),
+                "synthesize_gitfiles" => repofile.synthesize_gitfiles = v.as_bool().unwrap_or(false),
+                "allow_destination_collisions" => repofile.allow_destination_collisions = v.as_bool().unwrap_or(false),
+                "squash" => repofile.squash = v.as_bool().unwrap_or(false),
+                "push_mode" => repofile.push_mode = toml_value_to_string_opt(v),
                 _ => (),
             }
         }
@@ -170,6 +419,107 @@ pub fn parse_include_as_section(toml_value: &Value, repofile: &mut RepoFile) {
     }
 }
 
+pub fn parse_overlay_section(toml_value: &Value, repofile: &mut RepoFile) {
+    if let Value::Table(ref t) = toml_value {
+        // inline content might be generated as its own [overlay."dest"]
+        // table, which can end up in a different segment, so we add to
+        // whatever overlay we already parsed instead of replacing it
+        let mut overlay = repofile.overlay.take().unwrap_or_default();
+        for (k, v) in t {
+            // either "dest" = "local/path"
+            // or "dest" = { content = "..." }
+            if let Some(s) = v.as_str() {
+                overlay.push((k.to_owned(), OverlaySource::LocalFile(s.to_string())));
+            } else if let Some(content) = v.get("content").and_then(|c| c.as_str()) {
+                overlay.push((k.to_owned(), OverlaySource::Inline(content.to_string())));
+            }
+        }
+        repofile.overlay = Some(overlay);
+    }
+}
+
+pub fn parse_vars_section(toml_value: &Value, repofile: &mut RepoFile) {
+    if let Value::Table(ref t) = toml_value {
+        let mut vars = repofile.vars.take().unwrap_or_default();
+        for (k, v) in t {
+            if let Some(s) = v.as_str() {
+                vars.push((k.to_owned(), s.to_string()));
+            }
+        }
+        repofile.vars = Some(vars);
+    }
+}
+
+pub fn parse_tags_section(toml_value: &Value, repofile: &mut RepoFile) {
+    if let Value::Table(ref t) = toml_value {
+        let mut tags = repofile.tags.take().unwrap_or_default();
+        for (k, v) in t {
+            if let Some(s) = v.as_str() {
+                tags.push((k.to_owned(), s.to_string()));
+            }
+        }
+        repofile.tags = Some(tags);
+    }
+}
+
+/// parse the rules of a single [[versioned]] table into
+/// `versioned`, which is either a new set of rules, or
+/// a continuation of the last set of rules
+pub fn parse_versioned_table(toml_value: &Value, versioned: &mut Vec<VersionedRules>) {
+    let t = match toml_value {
+        Value::Table(ref t) => t,
+        _ => return,
+    };
+    // the rules are parsed the same way as the top level rules
+    let mut rules = RepoFile::default();
+    let mut since = None;
+    let mut until = None;
+    for (k, v) in t {
+        match k.as_str() {
+            "since" => since = toml_value_to_string_opt(v),
+            "until" => until = toml_value_to_string_opt(v),
+            "include_as" => parse_include_as_section(v, &mut rules),
+            "include" => parse_include_section(v, &mut rules),
+            "exclude" => parse_exclude_section(v, &mut rules),
+            _ => (),
+        }
+    }
+
+    // a [versioned.include_as] table can end up in a different
+    // segment than the [[versioned]] it belongs to, so if
+    // there is no since/until, it belongs to the last one
+    let is_continuation = since.is_none() && until.is_none();
+    match versioned.last_mut() {
+        Some(last) if is_continuation => {
+            if rules.include_as.is_some() { last.include_as = rules.include_as; }
+            if rules.include.is_some() { last.include = rules.include; }
+            if rules.exclude.is_some() { last.exclude = rules.exclude; }
+        }
+        _ => versioned.push(VersionedRules {
+            since,
+            until,
+            include_as: rules.include_as,
+            include: rules.include,
+            exclude: rules.exclude,
+        }),
+    }
+}
+
+pub fn parse_versioned_section(toml_value: &Value, repofile: &mut RepoFile) {
+    let mut versioned = repofile.versioned.take().unwrap_or_default();
+    match toml_value {
+        Value::Array(ref a) => {
+            for v in a {
+                parse_versioned_table(v, &mut versioned);
+            }
+        }
+        _ => parse_versioned_table(toml_value, &mut versioned),
+    }
+    if versioned.len() > 0 {
+        repofile.versioned = Some(versioned);
+    }
+}
+
 pub fn toml_value_to_vec(toml_value: &Value) -> Vec<String> {
     let mut toml_vec = vec![];
     if let Value::Array(ref a) = toml_value {
@@ -210,21 +560,30 @@ pub fn parse_repo_file_from_toml_segments(toml_segments: Vec<String>) -> io::Res
         let tomlvalue = tomlvalue.unwrap();
 
         if let Value::Table(ref t) = tomlvalue {
-            for (k, v) in t {
-                match k.as_str() {
-                    "repo" => parse_repo_section(v, &mut repo_file),
-                    "include_as" => parse_include_as_section(v, &mut repo_file),
-                    "include" => parse_include_section(v, &mut repo_file),
-                    "exclude" => parse_exclude_section(v, &mut repo_file),
-                    _ => (),
-                }
-            }
+            parse_repo_file_from_toml_table(t, &mut repo_file);
         }
     }
 
     Ok(repo_file)
 }
 
+pub fn parse_repo_file_from_toml_table(t: &toml::map::Map<String, Value>, repo_file: &mut RepoFile) {
+    for (k, v) in t {
+        match k.as_str() {
+            "repo" => parse_repo_section(v, repo_file),
+            "include_as" => parse_include_as_section(v, repo_file),
+            "include" => parse_include_section(v, repo_file),
+            "exclude" => parse_exclude_section(v, repo_file),
+            "overlay" => parse_overlay_section(v, repo_file),
+            "versioned" => parse_versioned_section(v, repo_file),
+            "vars" => parse_vars_section(v, repo_file),
+            "tags" => parse_tags_section(v, repo_file),
+            "extends" => repo_file.extends = toml_value_to_string_opt(v),
+            _ => (),
+        }
+    }
+}
+
 pub fn generate_repo_file_section_from_list(list: &Option<Vec<String>>) -> Option<toml::Value> {
     match list {
         None => None,
@@ -265,6 +624,19 @@ pub fn generate_repo_file_section_repo(repofile: &RepoFile) -> Option<toml::Valu
         }
     }
 
+    if repofile.synthesize_gitfiles {
+        toml_map.insert("synthesize_gitfiles".into(), toml::Value::Boolean(true));
+    }
+    if repofile.allow_destination_collisions {
+        toml_map.insert("allow_destination_collisions".into(), toml::Value::Boolean(true));
+    }
+    if repofile.squash {
+        toml_map.insert("squash".into(), toml::Value::Boolean(true));
+    }
+    if let Some(ref push_mode) = repofile.push_mode {
+        toml_map.insert("push_mode".into(), toml::Value::String(push_mode.clone()));
+    }
+
     if toml_map.len() > 0 {
         Some(toml::Value::Table(toml_map))
     } else {
@@ -296,6 +668,98 @@ pub fn generate_repo_file_section_include_as(repofile: &RepoFile) -> Option<toml
     }
 }
 
+pub fn generate_repo_file_section_overlay(repofile: &RepoFile) -> Option<toml::Value> {
+    let mut toml_map = toml::map::Map::new();
+
+    if let Some(ref overlay) = repofile.overlay {
+        for (dest, source) in overlay {
+            let value = match source {
+                OverlaySource::LocalFile(path) => toml::Value::String(path.clone()),
+                OverlaySource::Inline(content) => {
+                    let mut inline_map = toml::map::Map::new();
+                    inline_map.insert("content".into(), toml::Value::String(content.clone()));
+                    toml::Value::Table(inline_map)
+                }
+            };
+            toml_map.insert(dest.clone(), value);
+        }
+    }
+
+    if toml_map.len() > 0 {
+        Some(toml::Value::Table(toml_map))
+    } else {
+        None
+    }
+}
+
+pub fn generate_repo_file_section_vars(repofile: &RepoFile) -> Option<toml::Value> {
+    match repofile.vars {
+        Some(ref vars) if vars.len() > 0 => {
+            let mut toml_map = toml::map::Map::new();
+            for (name, value) in vars {
+                toml_map.insert(name.clone(), toml::Value::String(value.clone()));
+            }
+            Some(toml::Value::Table(toml_map))
+        }
+        _ => None,
+    }
+}
+
+pub fn generate_repo_file_section_tags(repofile: &RepoFile) -> Option<toml::Value> {
+    match repofile.tags {
+        Some(ref tags) if !tags.is_empty() => {
+            let mut toml_map = toml::map::Map::new();
+            for (local, remote) in tags {
+                toml_map.insert(local.clone(), toml::Value::String(remote.clone()));
+            }
+            Some(toml::Value::Table(toml_map))
+        }
+        _ => None,
+    }
+}
+
+/// returns one toml value per set of versioned rules. they are
+/// kept separate so that each one can be put in its own segment.
+/// otherwise a [versioned.include_as] table could end up in
+/// the same segment as the next [[versioned]], which toml rejects
+pub fn generate_repo_file_section_versioned(repofile: &RepoFile) -> Vec<toml::Value> {
+    let versioned = match repofile.versioned {
+        Some(ref v) => v,
+        None => return vec![],
+    };
+    let mut toml_vec = vec![];
+    for rules in versioned {
+        let mut toml_map = toml::map::Map::new();
+        if let Some(ref since) = rules.since {
+            toml_map.insert("since".into(), toml::Value::String(since.clone()));
+        }
+        if let Some(ref until) = rules.until {
+            toml_map.insert("until".into(), toml::Value::String(until.clone()));
+        }
+        // reuse the top level section generation for the rules themselves
+        let as_repo_file = RepoFile {
+            include_as: rules.include_as.clone(),
+            include: rules.include.clone(),
+            exclude: rules.exclude.clone(),
+            ..RepoFile::default()
+        };
+        if let Some(toml_value) = generate_repo_file_section_include(&as_repo_file) {
+            toml_map.insert("include".into(), toml_value);
+        }
+        if let Some(toml_value) = generate_repo_file_section_exclude(&as_repo_file) {
+            toml_map.insert("exclude".into(), toml_value);
+        }
+        if let Some(toml_value) = generate_repo_file_section_include_as(&as_repo_file) {
+            toml_map.insert("include_as".into(), toml_value);
+        }
+        let mut versioned_map = toml::map::Map::new();
+        let versioned_value = toml::Value::Array(vec![toml::Value::Table(toml_map)]);
+        versioned_map.insert("versioned".into(), versioned_value);
+        toml_vec.push(toml::Value::Table(versioned_map));
+    }
+    toml_vec
+}
+
 pub fn generate_repo_file_toml(repofile: &RepoFile) -> String {
     // the include and exclude
     // sections need to be done
@@ -306,9 +770,20 @@ pub fn generate_repo_file_toml(repofile: &RepoFile) -> String {
     let exclude_section = generate_repo_file_section_exclude(repofile);
     let repo_section = generate_repo_file_section_repo(repofile);
     let include_as_section = generate_repo_file_section_include_as(repofile);
+    let overlay_section = generate_repo_file_section_overlay(repofile);
+    let versioned_section = generate_repo_file_section_versioned(repofile);
+    let vars_section = generate_repo_file_section_vars(repofile);
+    let tags_section = generate_repo_file_section_tags(repofile);
 
     let mut toml_map = toml::map::Map::new();
 
+    if let Some(ref extends) = repofile.extends {
+        toml_map.insert("extends".into(), toml::Value::String(extends.clone()));
+    }
+    if let Some(toml_value) = vars_section {
+        toml_map.insert("vars".into(), toml_value);
+    }
+
     if let Some(toml_value) = repo_section {
         toml_map.insert("repo".into(), toml_value);
     }
@@ -321,9 +796,21 @@ pub fn generate_repo_file_toml(repofile: &RepoFile) -> String {
     if let Some(toml_value) = include_as_section {
         toml_map.insert("include_as".into(), toml_value);
     }
+    if let Some(toml_value) = overlay_section {
+        toml_map.insert("overlay".into(), toml_value);
+    }
+    if let Some(toml_value) = tags_section {
+        toml_map.insert("tags".into(), toml_value);
+    }
 
     let toml_table = toml::Value::Table(toml_map);
-    toml_table.to_string()
+    let mut out = toml_table.to_string();
+    // 2 empty lines always start a new segment
+    for toml_value in versioned_section {
+        out.push_str("\n\n");
+        out.push_str(&toml_value.to_string());
+    }
+    out
 }
 
 #[cfg(test)]
@@ -331,6 +818,11 @@ mod test {
     use super::generate_repo_file_toml;
     use super::parse_repo_file_from_toml_lines;
     use super::RepoFile;
+    use super::OverlaySource;
+    use super::VersionedRules;
+    use super::parse_repo_file_from_toml;
+    use super::substitute_repo_file_vars;
+    use std::fs;
 
     fn parse_from_lines(toml_str: &str) -> RepoFile {
         let lines: Vec<String> = toml_str.split('\n').map(|s| s.to_string()).collect();
@@ -423,6 +915,113 @@ mod test {
         assert_eq!(repofile.remote_branch.unwrap(), "somebranch");
     }
 
+    #[test]
+    fn toml_parse_synthesize_gitfiles() {
+        let toml_str = r#"
+            [repo]
+            name = "somename"
+            synthesize_gitfiles = true
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert!(repofile.synthesize_gitfiles);
+
+        let toml_str = r#"
+            [repo]
+            name = "somename"
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert!(!repofile.synthesize_gitfiles);
+    }
+
+    #[test]
+    fn toml_parse_squash() {
+        let toml_str = r#"
+            [repo]
+            name = "somename"
+            squash = true
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert!(repofile.squash);
+    }
+
+    #[test]
+    fn toml_parse_push_mode() {
+        let toml_str = r#"
+            [repo]
+            name = "somename"
+            push_mode = "branch"
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(repofile.push_mode, Some("branch".to_string()));
+    }
+
+    #[test]
+    fn toml_parse_tags() {
+        let toml_str = r#"
+            [tags]
+            "foo/v*" = "v*"
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(repofile.tags, Some(vec![("foo/v*".to_string(), "v*".to_string())]));
+    }
+
+    #[test]
+    fn toml_parse_allow_destination_collisions() {
+        let toml_str = r#"
+            [repo]
+            name = "somename"
+            allow_destination_collisions = true
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert!(repofile.allow_destination_collisions);
+
+        let toml_str = r#"
+            [repo]
+            name = "somename"
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert!(!repofile.allow_destination_collisions);
+    }
+
+    #[test]
+    fn toml_parse_overlay() {
+        let toml_str = r#"
+            [overlay]
+            "LICENSE" = "legal/LICENSE-MIT"
+            ".ci.yml" = { content = "build: true" }
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        let overlay = repofile.overlay.unwrap();
+        assert_eq!(overlay.len(), 2);
+        assert!(overlay.contains(&("LICENSE".into(), OverlaySource::LocalFile("legal/LICENSE-MIT".into()))));
+        assert!(overlay.contains(&(".ci.yml".into(), OverlaySource::Inline("build: true".into()))));
+    }
+
+    #[test]
+    fn toml_parse_versioned() {
+        let toml_str = r#"
+            [[versioned]]
+            until = "abc"
+            include_as = { "libs/foo/" = " " }
+
+
+            [[versioned]]
+            since = "abc"
+            exclude = "platform/foo/secret.txt"
+            [versioned.include_as]
+            "platform/foo/" = " "
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        let versioned = repofile.versioned.unwrap();
+        assert_eq!(versioned.len(), 2);
+        assert_eq!(versioned[0].until, Some("abc".into()));
+        assert_eq!(versioned[0].since, None);
+        assert_eq!(versioned[0].include_as, Some(vec!["libs/foo/".into(), " ".into()]));
+        assert_eq!(versioned[1].since, Some("abc".into()));
+        assert_eq!(versioned[1].include_as, Some(vec!["platform/foo/".into(), " ".into()]));
+        assert_eq!(versioned[1].exclude, Some(vec!["platform/foo/secret.txt".into()]));
+    }
+
     #[test]
     fn generate_repo_file_works() {
         let mut repofile = RepoFile::default();
@@ -447,6 +1046,44 @@ mod test {
         assert!(toml_str.contains("\"something.txt\" = \"else.txt\""));
     }
 
+    #[test]
+    fn toml_parse_vars_get_substituted() {
+        let toml_str = r#"
+            [vars]
+            host = "git@example.com:org"
+            [repo]
+            remote = "${host}/foo.git"
+        "#;
+        let mut repofile = parse_from_lines(toml_str);
+        substitute_repo_file_vars(&mut repofile).unwrap();
+        assert_eq!(repofile.remote_repo, Some("git@example.com:org/foo.git".into()));
+
+        repofile.remote_repo = Some("${nothost}/foo.git".into());
+        assert!(substitute_repo_file_vars(&mut repofile).is_err());
+    }
+
+    #[test]
+    fn extends_merges_and_detects_cycles() {
+        let dir = std::env::temp_dir().join(format!("mgt_extends_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("common.rf"), "[vars]\nhost = \"example.com\"\nname = \"common\"\n\n\nexclude = [\"target/\"]\n").unwrap();
+        fs::write(dir.join("foo.rf"), "extends = \"common.rf\"\n[vars]\nname = \"foo\"\n[repo]\nremote = \"${host}/${name}.git\"\n\n\nexclude = [\"foo/secret/\"]\n").unwrap();
+        fs::write(dir.join("a.rf"), "extends = \"b.rf\"\n").unwrap();
+        fs::write(dir.join("b.rf"), "extends = \"a.rf\"\n").unwrap();
+
+        let foo = parse_repo_file_from_toml(dir.join("foo.rf").to_str().unwrap());
+        let cycle = parse_repo_file_from_toml(dir.join("a.rf").to_str().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let foo = foo.unwrap();
+        assert_eq!(foo.remote_repo, Some("example.com/foo.git".into()));
+        assert_eq!(foo.exclude, Some(vec!["target/".into(), "foo/secret/".into()]));
+        assert_eq!(foo.extends, None);
+        assert_eq!(foo.vars, None);
+        let err = cycle.unwrap_err().to_string();
+        assert!(err.contains("cycle"));
+    }
+
     #[test]
     fn generated_repo_file_can_be_read() {
         let mut repofile = RepoFile::default();
@@ -454,6 +1091,29 @@ mod test {
         repofile.exclude = Some(vec!["abc".into(), "xyz".into()]);
         repofile.repo_name = Some("reponame".into());
         repofile.remote_branch = Some("mybranch".into());
+        repofile.synthesize_gitfiles = true;
+        repofile.allow_destination_collisions = true;
+        repofile.squash = true;
+        repofile.push_mode = Some("branch".into());
+        repofile.extends = Some("common.rf".into());
+        repofile.vars = Some(vec![("host".into(), "example.com".into())]);
+        repofile.tags = Some(vec![("foo/v*".into(), "v*".into())]);
+        repofile.overlay = Some(vec![
+            ("LICENSE".into(), OverlaySource::LocalFile("legal/LICENSE".into())),
+            ("README.md".into(), OverlaySource::Inline("# hello\n".into())),
+        ]);
+        repofile.versioned = Some(vec![
+            VersionedRules {
+                until: Some("abc".into()),
+                include_as: Some(vec!["libs/foo/".into(), " ".into()]),
+                ..VersionedRules::default()
+            },
+            VersionedRules {
+                since: Some("abc".into()),
+                include_as: Some(vec!["platform/foo/".into(), " ".into()]),
+                ..VersionedRules::default()
+            },
+        ]);
         repofile.include_as = Some(vec![
             "lib/".into(),
             " ".into(),
@@ -10,6 +10,7 @@ use super::repo_file::RepoFile;
 use super::split_out;
 use super::topbase;
 use super::verify;
+use gitfilter::filter::FilterRule;
 
 pub fn run_split_in(cmd: &mut MgtCommandSplit) {
     let repo_file_path = if cmd.repo_file.len() < 1 {
@@ -92,6 +93,8 @@ pub fn run_split_in_from_repo_file(
     core::perform_gitfilter(
         filter_rules,
         orphan_branch_name.clone(),
+        None,
+        None,
         cmd.dry_run,
         cmd.verbose,
     );
@@ -136,8 +139,17 @@ pub fn generate_gitfilter_filterrules(
     repo_file: &RepoFile,
     _verbose: bool,
 ) -> gitfilter::filter::FilterRules {
-    let mut file_ops = verify::get_vec_of_file_ops_with_order(&repo_file, false);
-    let filter_rules = verify::make_filter_rules(&mut file_ops);
+    // the versioned rules refer to local commits, so when
+    // filtering the remote we can only use the ones that apply now
+    let mut file_ops = verify::get_vec_of_current_file_ops_with_order(&repo_file, false);
+    let mut filter_rules = verify::make_filter_rules(&mut file_ops);
+    // overlay files only exist in the split repository,
+    // so they should never make it back into the local repository
+    if let Some(ref overlay) = repo_file.overlay {
+        for (dest, _) in overlay {
+            filter_rules.push(FilterRule::FilterRulePathExclude(dest.clone()));
+        }
+    }
     filter_rules
 }
 
@@ -1,21 +1,48 @@
 use super::cli::MgtCommandSplit;
 use super::core;
 use super::die;
+use super::git_helpers3;
 use super::repo_file;
 use super::repo_file::RepoFile;
+use super::squash;
 use super::verify;
-use crate::ioerre;
+use super::workspace;
+use crate::{ioerr, ioerre};
 use std::io;
+use std::path::Path;
 
 pub fn run_split_out(cmd: &mut MgtCommandSplit) {
-    let repo_file_path = if cmd.repo_file.len() < 1 {
-        die!("Must provide repo path argument");
-    } else {
-        cmd.repo_file[0].clone()
-    };
+    if cmd.repo_file.len() < 1 || cmd.group.is_some() {
+        return run_split_out_workspace(cmd);
+    }
+    let repo_file_path = cmd.repo_file[0].clone();
 
     let repo_file = repo_file::parse_repo_file_from_toml_path(&repo_file_path);
-    run_split_out_from_repo_file(cmd, repo_file)
+    run_split_out_from_repo_file(cmd, repo_file, Some(Path::new(&repo_file_path)))
+}
+
+/// split out every repo file of the workspace (or of the --group).
+/// each one gets split out from the branch we started on
+pub fn run_split_out_workspace(cmd: &mut MgtCommandSplit) {
+    if cmd.repo_file.len() > 0 {
+        die!("Cannot provide both a repo file path and a --group");
+    }
+    if cmd.output_branch.is_some() {
+        die!("Cannot use --output-branch when splitting out multiple repo files");
+    }
+    let repo_file_paths = match workspace::get_workspace_repo_files(cmd.group.as_deref()) {
+        Ok(p) => p,
+        Err(e) => die!("{}", e),
+    };
+    let starting_branch = core::get_current_ref()
+        .unwrap_or_else(|| die!("Failed to get current branch name. Cannot continue"));
+    for repo_file_path in repo_file_paths {
+        println!("---\nSplitting out {:?}", repo_file_path);
+        let repo_file = repo_file::parse_repo_file_from_toml_path(&repo_file_path);
+        cmd.output_branch = None;
+        run_split_out_from_repo_file(cmd, repo_file, Some(&repo_file_path));
+        core::checkout_output_branch(Some(starting_branch.clone()), cmd.dry_run, cmd.verbose);
+    }
 }
 
 pub fn run_split_out_as(cmd: &mut MgtCommandSplit) {
@@ -30,16 +57,22 @@ pub fn run_split_out_as(cmd: &mut MgtCommandSplit) {
     let mut repo_file = RepoFile::new();
     repo_file.include_as = Some(vec![include_as_src.into(), " ".into()]);
     repo_file.repo_name = Some(output_branch.into());
-    run_split_out_from_repo_file(cmd, repo_file)
+    run_split_out_from_repo_file(cmd, repo_file, None)
 }
 
-pub fn run_split_out_from_repo_file(cmd: &mut MgtCommandSplit, repo_file: RepoFile) {
+/// `repo_file_path` is where the repo file came from, if anywhere.
+/// it is needed to find when its inline overlay files were added
+pub fn run_split_out_from_repo_file(cmd: &mut MgtCommandSplit, repo_file: RepoFile, repo_file_path: Option<&Path>) {
     let mut repo_file = repo_file;
     core::verify_dependencies();
     validate_repo_file(&mut repo_file, &mut cmd.output_branch);
+    // it is relative to where we are now
+    let repo_file_path = repo_file_path.and_then(|p| p.canonicalize().ok());
     core::go_to_repo_root();
     core::safe_to_proceed();
-    let filter_rules = generate_gitfilter_filterrules(&repo_file, cmd.verbose);
+    let filter_rules = generate_history_gitfilter_filterrules(&repo_file, cmd.verbose);
+    // the commit we split from, for the trailer of a squashed commit
+    let source_commit = git_helpers3::get_full_commit_oid("HEAD").unwrap_or_default();
     core::make_and_checkout_output_branch(&cmd.output_branch, cmd.dry_run, cmd.verbose);
 
     let log_p = if cmd.dry_run { "   # " } else { "" };
@@ -54,7 +87,14 @@ pub fn run_split_out_from_repo_file(cmd: &mut MgtCommandSplit, repo_file: RepoFi
         Some(o) => o.clone(),
         None => die!("Failed to find output branch"),
     };
-    core::perform_gitfilter(filter_rules, output_branch, cmd.dry_run, cmd.verbose);
+    core::perform_gitfilter(
+        filter_rules,
+        output_branch,
+        Some(&repo_file),
+        repo_file_path.as_deref(),
+        cmd.dry_run,
+        cmd.verbose,
+    );
 
     // for split out, rebase is a bit different because
     // we actually need to fetch the remote repo|branch that
@@ -90,6 +130,7 @@ pub fn run_split_out_from_repo_file(cmd: &mut MgtCommandSplit, repo_file: RepoFi
             cmd.dry_run,
         );
         let current_ref = core::get_current_ref();
+        let remote_tip = git_helpers3::get_full_commit_oid(tmp_remote_branch).unwrap_or_default();
 
         core::checkout_output_branch(cmd.output_branch.clone(), cmd.dry_run, cmd.verbose);
 
@@ -115,6 +156,15 @@ pub fn run_split_out_from_repo_file(cmd: &mut MgtCommandSplit, repo_file: RepoFi
             Ok(())
         };
 
+        if res.is_ok() && repo_file.squash {
+            println!("{}Squashing into a single commit", log_p);
+            if !cmd.dry_run {
+                if let Err(e) = squash_onto_remote(cmd.output_branch.as_deref().unwrap_or_default(), &remote_tip, &source_commit) {
+                    die!("Failed to squash the output branch:\n{}", e);
+                }
+            }
+        }
+
         if let Ok(_) = res {
             println!("{}Success!", log_p);
         } else if let Err(e) = res {
@@ -123,6 +173,25 @@ pub fn run_split_out_from_repo_file(cmd: &mut MgtCommandSplit, repo_file: RepoFi
     }
 }
 
+/// squash every commit of the output branch that is above the remote into one.
+/// the filtered commits no longer know which local commits they came
+/// from, so the trailer lists the commit that we split from
+pub fn squash_onto_remote(output_branch: &str, remote_tip: &str, source_commit: &str) -> io::Result<()> {
+    let range = format!("{}..{}", remote_tip, output_branch);
+    let mut commits = git_helpers3::get_all_commits_from_ref(&range, None)
+        .map_err(|e| ioerr!("{}", e))?;
+    if commits.is_empty() {
+        return Ok(());
+    }
+    commits.reverse();
+    let summaries: Vec<String> = commits.iter()
+        .filter(|c| !c.is_merge)
+        .map(|c| c.summary.clone())
+        .collect();
+    let message = squash::make_squash_message(&summaries, &[source_commit.to_string()]);
+    squash::squash_branch_onto(output_branch, remote_tip, &message)
+}
+
 pub fn validate_repo_file_res(
     repo_file: &mut RepoFile,
     output_branch: &mut Option<String>,
@@ -163,13 +232,26 @@ pub fn validate_repo_file(repo_file: &mut RepoFile, output_branch: &mut Option<S
     }
 }
 
+/// the rules that apply to the current state of the repository,
+/// including the versioned rules that apply now. this is the same
+/// set of rules that split in uses, just in the other direction
 pub fn generate_gitfilter_filterrules(
     repo_file: &RepoFile,
     _verbose: bool,
 ) -> gitfilter::filter::FilterRules {
-    let mut file_ops = verify::get_vec_of_file_ops(&repo_file);
-    let filter_rules = verify::make_filter_rules(&mut file_ops);
-    filter_rules
+    let mut file_ops = verify::get_vec_of_current_file_ops_with_order(repo_file, true);
+    verify::make_filter_rules(&mut file_ops)
+}
+
+/// the rules for filtering the history. these leave out the versioned
+/// rules, because gitfilter gets those separately and only applies
+/// them to the part of the history that they are for
+pub fn generate_history_gitfilter_filterrules(
+    repo_file: &RepoFile,
+    _verbose: bool,
+) -> gitfilter::filter::FilterRules {
+    let mut file_ops = verify::get_vec_of_file_ops(repo_file);
+    verify::make_filter_rules(&mut file_ops)
 }
 
 pub fn get_remote_branch_from_args(cmd: &MgtCommandSplit) -> Option<&String> {
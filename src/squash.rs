@@ -0,0 +1,95 @@
+use super::git_helpers3;
+use super::ledger::LedgerEntry;
+use std::io;
+
+/// every commit that went into a squashed commit gets one of these
+/// trailers, so that the next sync can find where the two sides meet
+pub const SQUASH_TRAILER: &str = "Mgt-Squashed-Commit";
+/// how many of the most recent squashed commits of the remote
+/// we look at when searching for a fork point
+const MAX_SQUASHED_COMMITS: usize = 50;
+
+/// a message made of the summaries of the squashed commits,
+/// oldest first, followed by a trailer for each of `commit_ids`
+pub fn make_squash_message(summaries: &[String], commit_ids: &[String]) -> String {
+    let subject = match summaries {
+        [summary] => summary.clone(),
+        _ => format!("Squash {} commits", summaries.len()),
+    };
+    let mut message = format!("{}\n\n", subject);
+    if summaries.len() > 1 {
+        for summary in summaries {
+            message.push_str(&format!("- {}\n", summary));
+        }
+        message.push('\n');
+    }
+    for id in commit_ids {
+        message.push_str(&format!("{}: {}\n", SQUASH_TRAILER, id));
+    }
+    message
+}
+
+/// the commit ids listed in the trailers of a squashed commit, in order
+pub fn parse_squashed_commit_ids(message: &str) -> Vec<String> {
+    let prefix = format!("{}: ", SQUASH_TRAILER);
+    message.lines()
+        .filter_map(|line| line.strip_prefix(&prefix))
+        .map(|id| id.trim().to_string())
+        .collect()
+}
+
+/// replace every commit of `branch` that is above `onto` with a single
+/// commit that has the same tree. this does not touch the index or the
+/// worktree, so it is safe to do while `branch` is checked out
+pub fn squash_branch_onto(branch: &str, onto: &str, message: &str) -> io::Result<()> {
+    let tree = format!("{}^{{tree}}", branch);
+    let squashed = git_helpers3::git_output(&["commit-tree", &tree, "-p", onto, "-m", message])?;
+    let branch_ref = format!("refs/heads/{}", branch);
+    git_helpers3::git_output(&["update-ref", "-m", "mgt squash", &branch_ref, &squashed])?;
+    Ok(())
+}
+
+/// search the remote for the most recent squashed commit whose newest
+/// squashed commit is still part of the local branch. that pair is a fork
+/// point, the same as if it was recorded in the ledger
+pub fn find_squashed_fork(local_branch: &str, remote_branch: &str) -> Option<LedgerEntry> {
+    let grep = format!("--grep=^{}: ", SQUASH_TRAILER);
+    let max = MAX_SQUASHED_COMMITS.to_string();
+    // %x1e separates commits, %x1f separates the hash from the message
+    let log = git_helpers3::git_output(&["log", &grep, "-n", &max, "--format=%H%x1f%B%x1e", remote_branch]).ok()?;
+    for record in log.split('\x1e') {
+        let mut parts = record.trim_start().splitn(2, '\x1f');
+        let remote_commit = parts.next().unwrap_or("");
+        let ids = parse_squashed_commit_ids(parts.next().unwrap_or(""));
+        // the newest squashed commit is the one that all of the others are part of
+        let newest = ids.iter().find(|id| ids.iter().all(|other| git_helpers3::is_ancestor(other, id)));
+        if let Some(local_commit) = newest {
+            if git_helpers3::is_ancestor(local_commit, local_branch) {
+                return Some(LedgerEntry {
+                    direction: "push".into(),
+                    local_commit: local_commit.clone(),
+                    remote_commit: remote_commit.to_string(),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn squash_message_lists_summaries_and_trailers() {
+        let summaries = vec!["add a".to_string(), "fix b".to_string()];
+        let ids = vec!["a".repeat(40), "b".repeat(40)];
+        let message = make_squash_message(&summaries, &ids);
+        assert!(message.starts_with("Squash 2 commits\n\n- add a\n- fix b\n\n"));
+        assert_eq!(parse_squashed_commit_ids(&message), ids);
+
+        let message = make_squash_message(&summaries[..1], &ids[..1]);
+        assert_eq!(message, format!("add a\n\n{}: {}\n", SQUASH_TRAILER, ids[0]));
+    }
+}
@@ -3,17 +3,25 @@ use super::cli::MgtCommandSync;
 use super::core;
 use super::die;
 use super::git_helpers3;
+use super::graft;
 use super::interact;
+use super::jobs;
+use super::ledger;
+use super::preview;
 use super::repo_file;
+use super::squash;
+use super::tags;
+use super::tui;
+use super::workspace;
 use crate::{
-    check::blob_path_applies_to_repo_file, ioerr, ioerre, split_in,
-    split_out::generate_gitfilter_filterrules, topbase,
+    check::blob_applies_to_repo_file, ioerr, ioerre, split_in,
+    split_out::generate_history_gitfilter_filterrules, topbase,
 };
 use git_helpers3::{Commit, CommitWithBlobs, RawBlobSummary};
 use gitfilter::filter::FilterRule;
 use repo_file::RepoFile;
-use std::{fmt::Display, process::Stdio, time::SystemTime};
-use std::{io, path::PathBuf};
+use std::{collections::HashMap, fmt::Display, process::Stdio, time::SystemTime};
+use std::{io, path::Path, path::PathBuf};
 use topbase::SuccessfulTopbaseResult;
 
 /// What kind of sync are we doing? There are 5 possible
@@ -32,6 +40,11 @@ pub enum SyncType {
     Disjoint,    // failed to find a fork point
 }
 
+/// the commits the user chose to take, in the order they should be
+/// applied. each entry is an index into the non-merge commits, oldest
+/// first, and whether that commit gets squashed into the one before it
+pub type CommitPlan = Vec<(usize, bool)>;
+
 pub fn get_all_repo_files_ex(list: &Vec<PathBuf>) -> Vec<PathBuf> {
     let mut out_vec = vec![];
     for path in list {
@@ -67,6 +80,32 @@ pub fn make_random_branch_name(backup_number: usize) -> String {
     }
 }
 
+/// the name of a new branch to push a sync to for review, ie:
+/// mgt/sync/<repo_name>/2021-03-04-052107. the date is in UTC
+pub fn make_review_branch_name(repo_name: &str, now: SystemTime) -> String {
+    let secs = match now.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(_) => 0,
+    };
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+    // convert days since the epoch to a year, month, and day.
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "mgt/sync/{}/{:04}-{:02}-{:02}-{:02}{:02}{:02}",
+        repo_name, year, month, day,
+        secs_of_day / 3600, secs_of_day % 3600 / 60, secs_of_day % 60,
+    )
+}
+
 /// returns an error message string.
 /// the same string is returned for result ok or result err.
 /// result ok means it was able to checkout back to starting branch
@@ -199,11 +238,16 @@ pub fn try_perform_gitfilter(
     branch: String,
     starting_branch_name: &str,
     filter_rules: Vec<FilterRule>,
+    split_out_repo_file: Option<(&RepoFile, &Path)>,
 ) -> io::Result<String> {
     let is_verbose = false;
     let is_dry_run = false;
-    let filtered =
-        core::perform_gitfilter_res(filter_rules, branch.clone(), is_dry_run, is_verbose);
+    let (repo_file, repo_file_path) = match split_out_repo_file {
+        Some((repo_file, path)) => (Some(repo_file), Some(path)),
+        None => (None, None),
+    };
+    let filtered = core::perform_gitfilter_res(
+        filter_rules, branch.clone(), repo_file, repo_file_path, is_dry_run, is_verbose);
     if let Err(e) = filtered {
         // cleanup operation?
         // TODO: tricky one. probably need
@@ -393,7 +437,10 @@ pub fn try_get_new_commits_after_filter(
     }
 }
 
-pub fn get_rebase_interactive_string_and_number(commits_to_take: &Vec<Commit>) -> (usize, String) {
+pub fn get_rebase_interactive_string_and_number(
+    commits_to_take: &Vec<Commit>,
+    commit_plan: Option<&CommitPlan>,
+) -> (usize, String) {
     // ok when we do git rebase -i <from>~N <from>
     // we are not guarnateed that rebase will actually
     // only take N, especially because our branches have no related history...
@@ -417,10 +464,111 @@ pub fn get_rebase_interactive_string_and_number(commits_to_take: &Vec<Commit>) -
         })
         .collect();
     rebase_interactive_segments.reverse();
+    if let Some(plan) = commit_plan {
+        // the range we rebase stays the same, but only the commits in
+        // the plan get picked, so the rest of them are dropped
+        rebase_interactive_segments.retain(|segment| !segment.is_empty());
+        rebase_interactive_segments = plan.iter().map(|(index, squash)| {
+            if *squash {
+                // the rebase does not open an editor, so the
+                // squashed commit keeps the messages of both
+                rebase_interactive_segments[*index].replacen("pick", "squash", 1)
+            } else {
+                rebase_interactive_segments[*index].clone()
+            }
+        }).collect();
+    }
     let rebase_interactive_string = rebase_interactive_segments.join("");
     (num_commits_to_take, rebase_interactive_string)
 }
 
+/// the non-merge commits that a sync takes, oldest first,
+/// or in the order of the plan if there is one
+pub fn get_commits_to_take(commits: &[Commit], commit_plan: Option<&CommitPlan>) -> Vec<Commit> {
+    let mut non_merge_commits: Vec<&Commit> = commits.iter().filter(|c| !c.is_merge).collect();
+    non_merge_commits.reverse();
+    match commit_plan {
+        None => non_merge_commits.into_iter().cloned().collect(),
+        Some(plan) => plan.iter().map(|(index, _)| non_merge_commits[*index].clone()).collect(),
+    }
+}
+
+/// the plan refers to the commits by their position before filtering,
+/// so that is only the same commit after filtering if none of them
+/// got dropped or added by the filter
+pub fn check_commit_plan_after_filter(
+    commits_before_filter: &[CommitWithBlobs],
+    commits_after_filter: &[Commit],
+) -> io::Result<()> {
+    let num_before = commits_before_filter.iter().filter(|c| !c.commit.is_merge).count();
+    let num_after = commits_after_filter.iter().filter(|c| !c.is_merge).count();
+    if num_before != num_after {
+        return ioerre!(
+            "Cannot take only some of the commits, because {} of them were chosen from, but there are {} after filtering. Sync all of them instead",
+            num_before, num_after
+        );
+    }
+    Ok(())
+}
+
+/// parse a plan like "1 3 2s" where every number refers to one of
+/// `num_commits` commits, and an s after the number means that
+/// commit gets squashed into the one before it
+pub fn parse_commit_plan(input: &str, num_commits: usize) -> Result<CommitPlan, String> {
+    let mut plan: CommitPlan = vec![];
+    for part in input.split(|c: char| c == ',' || c.is_whitespace()).filter(|p| !p.is_empty()) {
+        let (number, squash) = match part.strip_suffix('s') {
+            Some(number) => (number, true),
+            None => (part, false),
+        };
+        let index = match number.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= num_commits => n - 1,
+            _ => return Err(format!("'{}' is not a number between 1 and {}", number, num_commits)),
+        };
+        if plan.iter().any(|(i, _)| *i == index) {
+            return Err(format!("{} was chosen more than once", index + 1));
+        }
+        if squash && plan.is_empty() {
+            return Err(format!("{} cannot be squashed because there is no commit before it", index + 1));
+        }
+        plan.push((index, squash));
+    }
+    if plan.is_empty() {
+        return Err("Must choose at least one commit".into());
+    }
+    Ok(plan)
+}
+
+/// show the commits that can be taken, oldest first, and
+/// ask which of them to take, in which order
+pub fn ask_commit_plan(cmd: &MgtCommandSync, commits: &[CommitWithBlobs]) -> io::Result<CommitPlan> {
+    let mut non_merge_commits: Vec<&CommitWithBlobs> = commits.iter()
+        .filter(|c| !c.commit.is_merge)
+        .collect();
+    non_merge_commits.reverse();
+    println!();
+    for (i, commit) in non_merge_commits.iter().enumerate() {
+        println!("{}: {} {}", i + 1, commit.commit.id.short(), commit.commit.summary);
+    }
+    let mut attempts = 0;
+    loop {
+        let mut plan_choice = interact::InteractChoices::choose_word(
+            "Which commits, in which order? Add an s to squash a commit into the one before it (ie: 1 3 2s). Commits that are not listed are held back");
+        plan_choice.max_loop = cmd.max_interactive_attempts;
+        let answer = interact::interact_word(plan_choice)?;
+        match parse_commit_plan(&answer, non_merge_commits.len()) {
+            Ok(plan) => return Ok(plan),
+            Err(e) => println!("{}", e),
+        }
+        attempts += 1;
+        if let Some(max) = cmd.max_interactive_attempts {
+            if attempts >= max {
+                return ioerre!("Too many invalid commit choices");
+            }
+        }
+    }
+}
+
 /// returns true if user wants to merge
 pub fn try_get_merge_choice(
     cmd: &MgtCommandSync,
@@ -478,10 +626,12 @@ pub fn try_fast_forward_merge(branch_name: &str, starting_branch_name: &str) ->
 pub fn try_sync_in(
     cmd: &MgtCommandSync,
     repo_file: &RepoFile,
+    repo_file_path: &Path,
     starting_branch_name: &str,
     fork_point_local: &str,
     // num_commits_to_pull: usize,
     commits_to_pull: &Vec<CommitWithBlobs>,
+    commit_plan: Option<&CommitPlan>,
 ) -> io::Result<()> {
     // eprintln!("Try sync in commits to pull: {:#?}", commits_to_pull);
     let is_verbose = false;
@@ -497,13 +647,19 @@ pub fn try_sync_in(
     try_making_branch_from(&random_branch, "FETCH_HEAD", starting_branch_name)?;
 
     println!("- Filtering branch according to repo file");
-    let random_branch = try_perform_gitfilter(random_branch, starting_branch_name, filter_rules)?;
+    let random_branch = try_perform_gitfilter(
+        random_branch, starting_branch_name, filter_rules, None)?;
 
     let new_commits_to_pull =
         try_get_new_commits_after_filter(&random_branch, &commits_to_pull, starting_branch_name)?;
+    if commit_plan.is_some() {
+        if let Err(e) = check_commit_plan_after_filter(commits_to_pull, &new_commits_to_pull) {
+            return Err(try_back_to_start_and_delete_branch(starting_branch_name, &random_branch, e));
+        }
+    }
     // eprintln!("New commits to pull: {:#?}", new_commits_to_pull);
     let (num_commits_to_pull, rebase_interactive_string) =
-        get_rebase_interactive_string_and_number(&new_commits_to_pull);
+        get_rebase_interactive_string_and_number(&new_commits_to_pull, commit_plan);
 
     println!("- Rebasing onto calculated fork point");
     try_rebase_onto(
@@ -530,6 +686,12 @@ pub fn try_sync_in(
         // if that succeeded, then we can delete the temporary branch
         println!("- Successfully merged. Deleting temporary branch");
         git_helpers3::delete_branch(&random_branch).map_err(|e| ioerr!("{}", e))?;
+        // if only some of the commits were taken, the remote commit does
+        // not correspond to ours, so the next sync cannot start from it
+        if commit_plan.is_none() {
+            let repo_remote_url = repo_file.remote_repo.as_deref().unwrap_or_default();
+            ledger::try_record_sync("pull", repo_file_path, repo_remote_url, starting_branch_name, "FETCH_HEAD");
+        }
         return Ok(());
     }
     // otherwise, if user did not want to merge,
@@ -553,13 +715,23 @@ pub fn try_sync_in(
 pub fn try_sync_out(
     cmd: &MgtCommandSync,
     repo_file: &RepoFile,
-    repo_remote_url: &str,
+    repo_file_path: &Path,
     starting_branch_name: &str,
     fork_point_remote: &str,
     commits_to_push: &Vec<CommitWithBlobs>,
+    commit_plan: Option<&CommitPlan>,
 ) -> io::Result<()> {
+    let repo_remote_url = repo_file.remote_repo.as_ref().ok_or(ioerr!(
+        "Failed to find a remote repo in the repo file: {:?}",
+        repo_file_path
+    ))?;
+    if let Some(ref push_mode) = repo_file.push_mode {
+        if !repo_file::PUSH_MODES.contains(&push_mode.as_str()) {
+            return ioerre!("Unknown push_mode '{}' in {:?}. It must be one of {:?}", push_mode, repo_file_path, repo_file::PUSH_MODES);
+        }
+    }
     let is_verbose = false;
-    let filter_rules = generate_gitfilter_filterrules(&repo_file, is_verbose);
+    let filter_rules = generate_history_gitfilter_filterrules(&repo_file, is_verbose);
     let random_number = match repo_file.remote_repo {
         Some(ref s) => s.len(),
         None => 12353143, // very secure, got it from some .gov website
@@ -574,12 +746,18 @@ pub fn try_sync_out(
     try_checkout_new_branch(&random_branch, starting_branch_name)?;
 
     println!("- Filtering branch according to repo file");
-    let random_branch = try_perform_gitfilter(random_branch, starting_branch_name, filter_rules)?;
+    let random_branch = try_perform_gitfilter(
+        random_branch, starting_branch_name, filter_rules, Some((repo_file, repo_file_path)))?;
 
     let new_commits_to_push =
         try_get_new_commits_after_filter(&random_branch, &commits_to_push, starting_branch_name)?;
+    if commit_plan.is_some() {
+        if let Err(e) = check_commit_plan_after_filter(commits_to_push, &new_commits_to_push) {
+            return Err(try_back_to_start_and_delete_branch(starting_branch_name, &random_branch, e));
+        }
+    }
     let (num_commits_to_push, rebase_interactive_string) =
-        get_rebase_interactive_string_and_number(&new_commits_to_push);
+        get_rebase_interactive_string_and_number(&new_commits_to_push, commit_plan);
 
     println!("- Rebasing onto calculated fork point");
     try_rebase_onto(
@@ -589,9 +767,36 @@ pub fn try_sync_out(
         &rebase_interactive_string,
     )?;
 
-    let push_branch_name = match &repo_file.remote_branch {
-        Some(branch) => branch.clone(),
-        None => try_get_output_branch_name(cmd, &random_branch, starting_branch_name)?,
+    if repo_file.squash {
+        println!("- Squashing into a single commit");
+        let original_commits: Vec<Commit> = commits_to_push.iter().map(|c| c.commit.clone()).collect();
+        let summaries: Vec<String> = get_commits_to_take(&new_commits_to_push, commit_plan)
+            .iter().map(|c| c.summary.clone()).collect();
+        // the same as for the ledger, pushing only some of the commits
+        // does not correspond to our branch, so the next sync must not start from it
+        let commit_ids: Vec<String> = if commit_plan.is_some() {
+            vec![]
+        } else {
+            original_commits.iter().map(|c| c.id.hash.clone()).collect()
+        };
+        let message = squash::make_squash_message(&summaries, &commit_ids);
+        if let Err(e) = squash::squash_branch_onto(&random_branch, fork_point_remote, &message) {
+            return Err(try_back_to_start_and_delete_branch(starting_branch_name, &random_branch, e));
+        }
+    }
+
+    let is_review_branch = repo_file.push_mode.as_deref() == Some("branch");
+    let push_branch_name = if is_review_branch {
+        let repo_name = match repo_file.repo_name {
+            Some(ref name) => name.clone(),
+            None => core::try_get_repo_name_from_remote_repo(repo_remote_url.clone()),
+        };
+        make_review_branch_name(&repo_name, SystemTime::now())
+    } else {
+        match &repo_file.remote_branch {
+            Some(branch) => branch.clone(),
+            None => try_get_output_branch_name(cmd, &random_branch, starting_branch_name)?,
+        }
     };
     println!(
         "- git push {} {}:{}",
@@ -604,6 +809,18 @@ pub fn try_sync_out(
         starting_branch_name,
     )?;
 
+    // the temporary branch is deleted below, so record
+    // the sync while we can still refer to what we pushed.
+    // if only some of the commits were pushed, what we pushed
+    // does not correspond to our branch, so there is nothing to record
+    if commit_plan.is_none() {
+        ledger::try_record_sync("push", repo_file_path, repo_remote_url, starting_branch_name, &random_branch);
+    }
+
+    if is_review_branch {
+        let target = repo_file.remote_branch.as_deref().unwrap_or("the default branch");
+        println!("- Pushed to the review branch {}. Merge it into {} to finish this sync", push_branch_name, target);
+    }
     println!(
         "- Successfully git pushed. Changing back to original branch: {}",
         starting_branch_name
@@ -640,8 +857,8 @@ pub fn try_sync_in_then_out() -> io::Result<()> {
 
 pub fn handle_sync2(
     cmd: &MgtCommandSync,
-    remote_url: &str,
     repo_file: &RepoFile,
+    repo_file_path: &Path,
     sync_type: SyncType,
     topbase_success: SuccessfulTopbaseResult<CommitWithBlobs>,
     starting_branch_name: &str,
@@ -666,6 +883,15 @@ pub fn handle_sync2(
     choices.push("skip");
     let mut can_push = !left_ahead.is_empty();
     let mut can_pull = !right_ahead.is_empty();
+    // which files each commit would conflict in if it was pulled.
+    // if we cant tell, we just dont show it
+    let mut conflicts = HashMap::new();
+    if can_pull {
+        match preview::preview_pull(repo_file, "HEAD", right_ahead) {
+            Ok(previews) => conflicts.extend(previews),
+            Err(e) => eprintln!("- Warning: failed to check which commits would conflict:\n{}", e),
+        }
+    }
     if can_push {
         let mut has_non_merge = false;
         let mut out_str = "\nYou can push:".to_string();
@@ -681,6 +907,9 @@ pub fn handle_sync2(
             }
         }
         if has_non_merge {
+            // the choices get reversed below, so this
+            // puts "push" before "push some of these commits"
+            choices.push("push some of these commits");
             choices.push("push");
             println!("{}", out_str);
         } else {
@@ -701,9 +930,18 @@ pub fn handle_sync2(
                     commit.commit.id.short(),
                     commit.commit.summary
                 );
+                match conflicts.get(&commit.commit.id.hash) {
+                    Some(paths) if !paths.is_empty() => {
+                        out_str = format!("{} (would conflict in {})", out_str, paths.join(", "));
+                    }
+                    _ => (),
+                }
             }
         }
         if has_non_merge {
+            // the choices get reversed below, so this
+            // puts "pull" before "pull some of these commits"
+            choices.push("pull some of these commits");
             choices.push("pull");
             println!("{}", out_str);
         } else {
@@ -715,11 +953,8 @@ pub fn handle_sync2(
     if can_pull && can_push {
         choices.push("pull --rebase, then push");
         // TODO: other cool options.
-        // eg: be able to interactively choose how the pull gets rebased
-        // into your local changes by dragging and dropping the
-        // commits in your local changes in the order you want...
-        // advanced feature, might want to look into ncurses type lib
-        // because thats probably too complex to do myself
+        // eg: a pull --rebase where you choose how the pull gets
+        // rebased into your local changes
     }
 
     // this is determined by if the user ran the
@@ -741,41 +976,72 @@ pub fn handle_sync2(
         return Ok(());
     }
 
-    // the nicest order is actually the reverse because
-    // we want exit and skip to be at the bottom:
-    choices.reverse();
-
-    println!();
-    let mut i_choices: interact::InteractChoices = (&choices[..]).into();
-    i_choices.max_loop = cmd.max_interactive_attempts;
-    let selection = interact::interact_number(i_choices)?;
-    let selection_index = selection - 1;
-    let selection = choices[selection_index];
+    // with --tui the commits and their order are chosen in
+    // the terminal UI, so we dont ask about them again below
+    let mut tui_plan = None;
+    let selection = if cmd.tui {
+        let push_commits: &[CommitWithBlobs] = if can_push { left_ahead } else { &[] };
+        let pull_commits: &[CommitWithBlobs] = if can_pull { right_ahead } else { &[] };
+        let title = format!("mgt sync {}", repo_file_path.display());
+        match tui::run_sync_tui(&title, push_commits, pull_commits, conflicts)? {
+            None => return Ok(()),
+            Some((direction, plan)) => {
+                tui_plan = plan;
+                match direction {
+                    tui::TuiDirection::Push => "push",
+                    tui::TuiDirection::Pull => "pull",
+                }
+            }
+        }
+    } else {
+        // the nicest order is actually the reverse because
+        // we want exit and skip to be at the bottom:
+        choices.reverse();
+
+        println!();
+        let mut i_choices: interact::InteractChoices = (&choices[..]).into();
+        i_choices.max_loop = cmd.max_interactive_attempts;
+        let selection = interact::interact_number(i_choices)?;
+        choices[selection - 1]
+    };
 
     match selection {
         "skip" => return Ok(()),
         "exit" => std::process::exit(0),
-        "pull" => {
+        "pull" | "pull some of these commits" => {
             let local_fork = &topbase_success.fork_point.0.commit.id.hash;
             let take_commits = &topbase_success.top_right_commits;
+            let commit_plan = if selection == "pull" {
+                tui_plan
+            } else {
+                Some(ask_commit_plan(cmd, take_commits)?)
+            };
             try_sync_in(
                 cmd,
                 &repo_file,
+                repo_file_path,
                 starting_branch_name,
                 local_fork,
                 take_commits,
+                commit_plan.as_ref(),
             )
         }
-        "push" => {
+        "push" | "push some of these commits" => {
             let remote_fork = &topbase_success.fork_point.1.commit.id.hash;
             let take_commits = &topbase_success.top_commits;
+            let commit_plan = if selection == "push" {
+                tui_plan
+            } else {
+                Some(ask_commit_plan(cmd, take_commits)?)
+            };
             try_sync_out(
                 cmd,
                 &repo_file,
-                remote_url,
+                repo_file_path,
                 starting_branch_name,
                 remote_fork,
                 take_commits,
+                commit_plan.as_ref(),
             )
         }
 
@@ -786,8 +1052,8 @@ pub fn handle_sync2(
 
 pub fn handle_sync(
     cmd: &MgtCommandSync,
-    remote_url: &str,
     repo_file: &RepoFile,
+    repo_file_path: &Path,
     sync_type: SyncType,
     topbase_opt: Option<SuccessfulTopbaseResult<CommitWithBlobs>>,
     starting_branch_name: &str,
@@ -795,14 +1061,17 @@ pub fn handle_sync(
 ) -> io::Result<()> {
     match topbase_opt {
         None => {
-            // TODO: come up with something better than just saying this
-            println!("Branches are disjoint. cannot sync");
+            if can_push_pull {
+                println!("Branches are disjoint. cannot sync");
+            } else {
+                println!("Branches are disjoint. Run sync without --summary-only to choose a fork point for them");
+            }
             Ok(())
         }
         Some(s) => handle_sync2(
             cmd,
-            remote_url,
             repo_file,
+            repo_file_path,
             sync_type,
             s,
             starting_branch_name,
@@ -811,15 +1080,38 @@ pub fn handle_sync(
     }
 }
 
-pub fn sync_repo_file(
-    starting_branch_name: &str,
-    repo_file_path: &PathBuf,
+/// a repo file, and the remote branch that we fetch for it
+pub struct SyncTarget {
+    pub repo_file_path: PathBuf,
+    pub repo_file: RepoFile,
+    pub repo_url: String,
+    pub repo_branch: String,
+    /// the remote branch gets fetched into this ref, so that
+    /// fetching the remotes of other repo files does not overwrite it
+    pub fetch_ref: String,
+}
+
+/// what we found out about a repo file before asking the user anything
+pub struct SyncAnalysis {
+    pub target: SyncTarget,
+    /// HEAD at the time of the analysis. if it moved since then,
+    /// because we synced another repo file, it has to be done again
+    pub local_commit: String,
+    /// printed before the summary of this repo file
+    pub notes: Vec<String>,
+    pub topbase_ok: Option<SuccessfulTopbaseResult<CommitWithBlobs>>,
+}
+
+/// parse the repo file, and find out which remote branch to fetch. this
+/// can ask the user, so it is done before fetching anything
+pub fn prepare_sync(
     cmd: &MgtCommandSync,
-    can_push_pull: bool,
-) -> io::Result<()> {
+    repo_file_path: &Path,
+    fetch_ref: String,
+) -> io::Result<SyncTarget> {
     let repo_file = repo_file::parse_repo_file_from_toml_path_res(repo_file_path)?;
     let default_branch = "HEAD".to_string();
-    let repo_url = repo_file.remote_repo.as_ref().ok_or(ioerr!(
+    let repo_url = repo_file.remote_repo.clone().ok_or(ioerr!(
         "Failed to find a remote repo in the repo file: {:?}",
         repo_file_path
     ))?;
@@ -849,19 +1141,26 @@ pub fn sync_repo_file(
     } else {
         repo_branch.to_string()
     };
+    Ok(SyncTarget {
+        repo_file_path: repo_file_path.to_path_buf(),
+        repo_file,
+        repo_url,
+        repo_branch,
+        fetch_ref,
+    })
+}
 
-    let divider = "=".repeat(15);
-    println!(
-        "\n{} Fetching {}:{} {}",
-        divider, repo_url, repo_branch, divider
-    );
-    git_helpers3::fetch_branch(repo_url, &repo_branch).map_err(|e| ioerr!("{}", e))?;
-
-    // TODO: support sync from a different branch other than the one
-    // we are currently on?
-    let local_branch = "HEAD";
-    let remote_branch = "FETCH_HEAD";
-
+/// find where the local and remote branches of the repo file meet, and what
+/// each of them has on top of that. this only reads from the repository,
+/// so it can run for several repo files at the same time
+pub fn analyze_sync(
+    target: &SyncTarget,
+    local_branch: &str,
+    remote_branch: &str,
+    notes: &mut Vec<String>,
+) -> io::Result<Option<SuccessfulTopbaseResult<CommitWithBlobs>>> {
+    let repo_file = &target.repo_file;
+    let repo_file_path = &target.repo_file_path;
     // this is important because we are not filtering any of the paths, so
     // with BlobHashingMode::Full, we would fail to find the correct fork point
     // because the paths are most likely different
@@ -873,16 +1172,136 @@ pub fn sync_repo_file(
     let should_rewind = true;
     let should_use_blob_cb = |c: &mut RawBlobSummary, b: &str| {
         let this_is_a_remote_blob = b == remote_branch;
-        blob_path_applies_to_repo_file(&c.path_dest, &repo_file, this_is_a_remote_blob)
+        blob_applies_to_repo_file(c, repo_file, this_is_a_remote_blob)
+    };
+    // if we synced this repo file before, and nothing was rewritten
+    // since then, start from that sync instead of searching for a fork point
+    let last_sync = match ledger::read_ledger() {
+        Ok(entries) => {
+            let ledger_name = ledger::get_ledger_name(repo_file_path)?;
+            let repo_file_hash = ledger::get_repo_file_hash(repo_file_path)?;
+            ledger::find_last_sync(&entries, &ledger_name, &repo_file_hash, local_branch, remote_branch)
+        }
+        Err(e) => {
+            notes.push(format!("- Warning: failed to read {}:\n{}", ledger::LEDGER_REF, e));
+            None
+        }
+    };
+    // a squashed push cannot be found by comparing commits, but its
+    // trailers tell us which local commits it was made of
+    let last_sync = last_sync.or_else(|| squash::find_squashed_fork(local_branch, remote_branch));
+    match last_sync {
+        Some(entry) => {
+            if entry.time.is_empty() {
+                notes.push(format!("- Starting from the squashed commit {}", entry.remote_commit));
+            } else {
+                notes.push(format!("- Starting from the last sync on {}", entry.time));
+            }
+            Ok(Some(ledger::topbase_from_last_sync(
+                &entry, local_branch, remote_branch, false, should_use_blob_cb)?))
+        }
+        None => topbase::find_a_b_difference2::<CommitWithBlobs, _>(
+            local_branch,
+            remote_branch,
+            Some(traverse_at_a_time),
+            hashing_mode,
+            should_rewind,
+            Some(should_use_blob_cb),
+        ),
+    }
+}
+
+/// fetch the remote branch of the repo file, and analyze it. this does not
+/// ask the user anything, so it can run for several repo files at the same time
+pub fn fetch_and_analyze(target: SyncTarget) -> io::Result<SyncAnalysis> {
+    git_helpers3::fetch_branch_into(&target.repo_url, &target.repo_branch, &target.fetch_ref)
+        .map_err(|e| ioerr!("{}", e))?;
+    // TODO: support sync from a different branch other than the one
+    // we are currently on?
+    let local_branch = "HEAD";
+    let local_commit = git_helpers3::get_full_commit_oid(local_branch).map_err(|e| ioerr!("{}", e))?;
+    let mut notes = vec![];
+    let topbase_ok = analyze_sync(&target, local_branch, &target.fetch_ref, &mut notes);
+    let topbase_ok = match topbase_ok {
+        Ok(t) => t,
+        Err(e) => {
+            let _ = git_helpers3::delete_ref(&target.fetch_ref);
+            return Err(e);
+        }
+    };
+    Ok(SyncAnalysis { target, local_commit, notes, topbase_ok })
+}
+
+/// show the summary of an analyzed repo file, and push or
+/// pull whatever the user chooses
+pub fn finish_sync(
+    starting_branch_name: &str,
+    cmd: &MgtCommandSync,
+    can_push_pull: bool,
+    analysis: SyncAnalysis,
+) -> io::Result<()> {
+    let SyncAnalysis { target, local_commit, mut notes, topbase_ok } = analysis;
+    let divider = "=".repeat(15);
+    println!(
+        "\n{} Fetching {}:{} {}",
+        divider, target.repo_url, target.repo_branch, divider
+    );
+    let result = finish_sync_from_ref(starting_branch_name, cmd, can_push_pull, &target, &local_commit, &mut notes, topbase_ok);
+    let _ = git_helpers3::delete_ref(&target.fetch_ref);
+    result
+}
+
+/// the (local, remote) commit ids of the fork point of a topbase
+fn get_fork_point(topbase_opt: &Option<SuccessfulTopbaseResult<CommitWithBlobs>>) -> Option<(String, String)> {
+    topbase_opt.as_ref()
+        .map(|o| (o.fork_point.0.commit.id.hash.clone(), o.fork_point.1.commit.id.hash.clone()))
+}
+
+fn finish_sync_from_ref(
+    starting_branch_name: &str,
+    cmd: &MgtCommandSync,
+    can_push_pull: bool,
+    target: &SyncTarget,
+    local_commit: &str,
+    notes: &mut Vec<String>,
+    topbase_ok: Option<SuccessfulTopbaseResult<CommitWithBlobs>>,
+) -> io::Result<()> {
+    let local_branch = "HEAD";
+    // pulling creates its branch from FETCH_HEAD, so it has to
+    // point to the remote of this repo file again
+    git_helpers3::fetch_branch(".", &target.fetch_ref).map_err(|e| ioerr!("{}", e))?;
+    let remote_branch = "FETCH_HEAD";
+    let head_moved = git_helpers3::get_full_commit_oid(local_branch).ok().as_deref() != Some(local_commit);
+    let topbase_ok = if head_moved {
+        notes.clear();
+        notes.push(format!("- {} moved since it was analyzed, analyzing again", local_branch));
+        analyze_sync(target, local_branch, remote_branch, notes)?
+    } else {
+        topbase_ok
+    };
+    for note in notes.iter() {
+        println!("{}", note);
+    }
+
+    let repo_file = &target.repo_file;
+    let repo_file_path = &target.repo_file_path;
+    // histories that were not split by mgt dont have a fork point. let
+    // the user graft them together, and then sync from that graft
+    let topbase_ok = match topbase_ok {
+        None if can_push_pull => {
+            match graft::try_guided_graft(cmd, repo_file, repo_file_path, &target.repo_url, local_branch, remote_branch)? {
+                Some(entry) => {
+                    let should_use_blob_cb = |c: &mut RawBlobSummary, b: &str| {
+                        blob_applies_to_repo_file(c, repo_file, b == remote_branch)
+                    };
+                    Some(ledger::topbase_from_last_sync(
+                        &entry, local_branch, remote_branch, false, should_use_blob_cb)?)
+                }
+                None => None,
+            }
+        }
+        topbase_ok => topbase_ok,
     };
-    let topbase_ok = topbase::find_a_b_difference2::<CommitWithBlobs, _>(
-        local_branch,
-        remote_branch,
-        Some(traverse_at_a_time),
-        hashing_mode,
-        should_rewind,
-        Some(should_use_blob_cb),
-    )?;
     let (sync_type, topbase_ok) = match topbase_ok {
         None => (SyncType::Disjoint, None),
         Some(o) => {
@@ -918,15 +1337,77 @@ pub fn sync_repo_file(
             (sync_type, Some(o))
         }
     };
+    let fork_point = get_fork_point(&topbase_ok);
+    let before_sync = (
+        git_helpers3::get_full_commit_oid(local_branch).ok(),
+        git_helpers3::get_full_commit_oid(remote_branch).ok(),
+    );
     handle_sync(
         cmd,
-        repo_url,
-        &repo_file,
+        repo_file,
+        repo_file_path,
         sync_type,
         topbase_ok,
         starting_branch_name,
         can_push_pull,
-    )
+    )?;
+    // pushing or pulling moves the fork point up to the commits that were
+    // just synced, otherwise their tags would look like they are not synced yet
+    let fork_point = match fork_point {
+        Some(_) if can_push_pull => {
+            // fetch_ref is from before the push, so the remote has to be fetched again
+            git_helpers3::fetch_branch_into(&target.repo_url, &target.repo_branch, &target.fetch_ref)
+                .map_err(|e| ioerr!("{}", e))?;
+            git_helpers3::fetch_branch(".", &target.fetch_ref).map_err(|e| ioerr!("{}", e))?;
+            let after_sync = (
+                git_helpers3::get_full_commit_oid(local_branch).ok(),
+                git_helpers3::get_full_commit_oid(remote_branch).ok(),
+            );
+            if after_sync == before_sync {
+                fork_point
+            } else {
+                get_fork_point(&analyze_sync(target, local_branch, remote_branch, &mut vec![])?)
+            }
+        }
+        fork_point => fork_point,
+    };
+    match fork_point {
+        Some((local_fork, remote_fork)) => tags::sync_tags(
+            cmd, repo_file, &target.repo_url, &local_fork, &remote_fork, can_push_pull),
+        None => Ok(()),
+    }
+}
+
+/// fetch, analyze, and sync one repo file
+pub fn sync_repo_file(
+    starting_branch_name: &str,
+    repo_file_path: &Path,
+    cmd: &MgtCommandSync,
+    can_push_pull: bool,
+) -> io::Result<()> {
+    let target = prepare_sync(cmd, repo_file_path, get_fetch_ref(0))?;
+    let analysis = fetch_and_analyze(target)?;
+    finish_sync(starting_branch_name, cmd, can_push_pull, analysis)
+}
+
+/// where the remote branch of the nth repo file gets fetched
+pub fn get_fetch_ref(index: usize) -> String {
+    format!("refs/mgt/fetch/{}", index)
+}
+
+/// fetch and analyze up to `cmd.jobs` repo files at the same time.
+/// the results are in the same order as the repo files. the questions
+/// of --ask-branches are asked before fetching anything
+pub fn fetch_and_analyze_all(
+    cmd: &MgtCommandSync,
+    repo_file_paths: &[PathBuf],
+) -> Vec<io::Result<SyncAnalysis>> {
+    let targets: Vec<io::Result<SyncTarget>> = repo_file_paths.iter().enumerate()
+        .map(|(index, path)| prepare_sync(cmd, path, get_fetch_ref(index)))
+        .collect();
+    let max_jobs = cmd.jobs.unwrap_or(1);
+    println!("Fetching {} repo files, up to {} at a time", targets.len(), max_jobs);
+    jobs::run_jobs(targets, max_jobs, |target| target.and_then(fetch_and_analyze))
 }
 
 pub fn canonicalize_all_repo_file_paths(paths: &Vec<PathBuf>) -> Vec<PathBuf> {
@@ -999,6 +1480,15 @@ pub fn run_sync(cmd: &mut MgtCommandSync) {
     // before we go to the repo root, we want to canonicalize
     // all of the paths the user provided, otherwise they wont work anymore
     // from a new directory
+    if cmd.repo_files.is_empty() || cmd.group.is_some() {
+        if !cmd.repo_files.is_empty() {
+            die!("Cannot provide both repo file paths and a --group");
+        }
+        cmd.repo_files = match workspace::get_workspace_repo_files(cmd.group.as_deref()) {
+            Ok(files) => files,
+            Err(e) => die!("{}", e),
+        };
+    }
     cmd.repo_files = canonicalize_all_repo_file_paths(&cmd.repo_files);
     core::verify_dependencies();
     core::go_to_repo_root();
@@ -1024,11 +1514,26 @@ pub fn run_sync(cmd: &mut MgtCommandSync) {
     println!("Found {:#?} repo files to sync", all_repo_files);
     println!("Found {} repo files to sync", all_repo_files.len());
 
-    for (_index, repo_file) in all_repo_files.drain(..).enumerate() {
+    // with --jobs, every repo file is fetched and analyzed up front.
+    // otherwise each one is fetched right before it is synced
+    let mut analyses = if cmd.jobs.unwrap_or(1) > 1 {
+        fetch_and_analyze_all(cmd, &all_repo_files).into_iter().map(Some).collect()
+    } else {
+        vec![]
+    };
+    for (index, repo_file) in all_repo_files.drain(..).enumerate() {
         let potential_err = format!("Error trying to sync {:?} :", repo_file);
-        if let Err(e) = sync_repo_file(&starting_branch_name, &repo_file, cmd, can_pull_push) {
+        let result = match analyses.get_mut(index).and_then(|a| a.take()) {
+            Some(analysis) => analysis.and_then(|a| finish_sync(&starting_branch_name, cmd, can_pull_push, a)),
+            None => sync_repo_file(&starting_branch_name, &repo_file, cmd, can_pull_push),
+        };
+        if let Err(e) = result {
             eprintln!("{}\n{}", potential_err, e);
             if cmd.fail_fast {
+                // the repo files that we analyzed but wont sync
+                for analysis in analyses.iter_mut().filter_map(|a| a.take()).flatten() {
+                    let _ = git_helpers3::delete_ref(&analysis.target.fetch_ref);
+                }
                 if should_stash_pop {
                     if let Err(e) = git_helpers3::stash(true) {
                         die!("Failed to perform git stash pop:\n{}\nThis might be because the sync operation resulted in your files being modified. Check if you have a conflict and resolve it. Otherwise if this error occurred, but you do not have a conflict, then this is an unexpected error and you should report it.", e);
@@ -1045,3 +1550,46 @@ pub fn run_sync(cmd: &mut MgtCommandSync) {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn review_branch_name_has_the_date() {
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1614835267);
+        assert_eq!(make_review_branch_name("mylib", time), "mgt/sync/mylib/2021-03-04-052107");
+        assert_eq!(make_review_branch_name("mylib", SystemTime::UNIX_EPOCH), "mgt/sync/mylib/1970-01-01-000000");
+    }
+
+    #[test]
+    fn commit_plan_picks_reorders_and_squashes() {
+        assert_eq!(parse_commit_plan("1 3 2s", 3), Ok(vec![(0, false), (2, false), (1, true)]));
+        assert!(parse_commit_plan("1s 2", 3).is_err());
+        assert!(parse_commit_plan("1 1", 3).is_err());
+        assert!(parse_commit_plan("4", 3).is_err());
+        assert!(parse_commit_plan("", 3).is_err());
+
+        // git log lists the newest commit first
+        let commits = vec![
+            Commit::new("ccc", "third".into(), false),
+            Commit::new("mmm", "merge".into(), true),
+            Commit::new("bbb", "second".into(), false),
+            Commit::new("aaa", "first".into(), false),
+        ];
+        let (num, todo) = get_rebase_interactive_string_and_number(&commits, None);
+        assert_eq!(num, 3);
+        assert_eq!(todo, "pick aaa first\npick bbb second\npick ccc third\n");
+        let plan = vec![(2, false), (0, true)];
+        let (num, todo) = get_rebase_interactive_string_and_number(&commits, Some(&plan));
+        assert_eq!(num, 3);
+        assert_eq!(todo, "pick ccc third\nsquash aaa first\n");
+
+        // the plan was chosen from 3 commits, so it cannot be used if one was filtered away
+        let before: Vec<CommitWithBlobs> = commits.iter()
+            .map(|c| CommitWithBlobs { commit: c.clone(), blobs: vec![] })
+            .collect();
+        assert!(check_commit_plan_after_filter(&before, &commits).is_ok());
+        assert!(check_commit_plan_after_filter(&before, &commits[1..]).is_err());
+    }
+}
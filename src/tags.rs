@@ -0,0 +1,358 @@
+use super::cli::MgtCommandSync;
+use super::git_helpers3;
+use super::git_helpers3::Commit;
+use super::graft;
+use super::interact;
+use super::ioerr;
+use super::repo_file::RepoFile;
+use super::split_in;
+use super::split_out;
+use gitfilter::filter::FilterRules;
+use std::io;
+
+/// how many commits below the fork point we search for
+/// the commit that corresponds to a tagged commit
+const MAX_TAG_CANDIDATES: usize = 100;
+
+/// the (path, blob hash) pairs of a commit, see `graft::get_content_key`
+type ContentKey = Vec<(String, String)>;
+
+/// a tag that one side has, and the other side does not
+#[derive(Debug, PartialEq, Clone)]
+pub struct MissingTag {
+    /// the name of the tag on the side that has it
+    pub source_name: String,
+    /// the name it gets on the side that does not have it
+    pub name: String,
+    /// the commit it points to on the side that does not have it
+    pub commit: String,
+}
+
+/// a rule is a pair of patterns that both have a single `*`, or neither does
+pub fn validate_tag_rule(local: &str, remote: &str) -> Result<(), String> {
+    let local_stars = local.matches('*').count();
+    let remote_stars = remote.matches('*').count();
+    if local_stars > 1 || remote_stars > 1 {
+        return Err(format!("tag rule '{}' = '{}' can only have one '*' per pattern", local, remote));
+    }
+    if local_stars != remote_stars {
+        return Err(format!("tag rule '{}' = '{}' must have a '*' in both patterns, or in neither", local, remote));
+    }
+    Ok(())
+}
+
+/// the part of `name` that the `*` of `pattern` matches. a
+/// pattern without a `*` only matches itself
+pub fn match_tag_pattern<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+    let star = match pattern.find('*') {
+        Some(i) => i,
+        None => return if pattern == name { Some("") } else { None },
+    };
+    let (prefix, suffix) = (&pattern[..star], &pattern[star + 1..]);
+    let matches = name.len() >= prefix.len() + suffix.len()
+        && name.starts_with(prefix)
+        && name.ends_with(suffix);
+    if matches {
+        Some(&name[prefix.len()..name.len() - suffix.len()])
+    } else {
+        None
+    }
+}
+
+/// the name of a tag on the other side, from the first rule that matches it
+pub fn map_tag_name(rules: &[(String, String)], name: &str, to_remote: bool) -> Option<String> {
+    rules.iter().find_map(|(local, remote)| {
+        let (from, to) = if to_remote { (local, remote) } else { (remote, local) };
+        let matched = match_tag_pattern(from, name)?;
+        Some(to.replacen('*', matched, 1)).filter(|mapped| !mapped.is_empty())
+    })
+}
+
+/// the (source name, mapped name, commit) of every tag of `from`
+/// that a rule maps to a name that `to` does not have yet
+pub fn find_unmapped_tags(
+    rules: &[(String, String)],
+    from: &[(String, String)],
+    to: &[(String, String)],
+    to_remote: bool,
+) -> Vec<(String, String, String)> {
+    from.iter().filter_map(|(name, commit)| {
+        let mapped = map_tag_name(rules, name, to_remote)?;
+        if to.iter().any(|(n, _)| *n == mapped) {
+            return None;
+        }
+        Some((name.clone(), mapped, commit.clone()))
+    }).collect()
+}
+
+/// the (name, commit) of every tag in the output of `git ls-remote --tags`.
+/// annotated tags are listed twice, the `^{}` one is the commit they point to
+pub fn parse_ls_remote_tags(output: &str) -> Vec<(String, String)> {
+    let mut tags: Vec<(String, String)> = vec![];
+    for line in output.lines() {
+        let mut parts = line.split_whitespace();
+        let (hash, refname) = match (parts.next(), parts.next()) {
+            (Some(h), Some(r)) => (h, r),
+            _ => continue,
+        };
+        let name = match refname.strip_prefix("refs/tags/") {
+            Some(n) => n,
+            None => continue,
+        };
+        let (name, is_peeled) = match name.strip_suffix("^{}") {
+            Some(n) => (n, true),
+            None => (name, false),
+        };
+        match tags.iter_mut().find(|(n, _)| n == name) {
+            Some(existing) if is_peeled => existing.1 = hash.to_string(),
+            Some(_) => (),
+            None => tags.push((name.to_string(), hash.to_string())),
+        }
+    }
+    tags
+}
+
+pub fn get_remote_tags(repo_url: &str) -> io::Result<Vec<(String, String)>> {
+    let output = git_helpers3::git_output(&["ls-remote", "--tags", repo_url])?;
+    Ok(parse_ls_remote_tags(&output))
+}
+
+/// the (name, commit) of every local tag
+pub fn get_local_tags() -> io::Result<Vec<(String, String)>> {
+    // *objectname is the commit that an annotated tag points to
+    let output = git_helpers3::git_output(&[
+        "for-each-ref", "refs/tags", "--format=%(refname:strip=2) %(objectname) %(*objectname)",
+    ])?;
+    Ok(output.lines().filter_map(|line| {
+        let mut parts = line.split_whitespace();
+        let name = parts.next()?;
+        let object = parts.next()?;
+        let commit = parts.next().unwrap_or(object);
+        Some((name.to_string(), commit.to_string()))
+    }).collect())
+}
+
+/// the index of the oldest commit in the first run of commits that
+/// have `key`. `keys` are newest first, and a run is several commits in a
+/// row with the same contents, of which the oldest one introduced them
+pub fn find_oldest_in_run(keys: &[ContentKey], key: &[(String, String)]) -> Option<usize> {
+    if key.is_empty() {
+        return None;
+    }
+    let first = keys.iter().position(|k| k.as_slice() == key)?;
+    let run_length = keys[first..].iter().take_while(|k| k.as_slice() == key).count();
+    Some(first + run_length - 1)
+}
+
+/// finds the commit of the other side that corresponds to a commit of
+/// this side. only commits at or below the fork point have one
+struct CommitMapper<'a> {
+    fork: &'a str,
+    other_fork: &'a str,
+    filter_rules: FilterRules,
+    map_paths: bool,
+    other_filter_rules: FilterRules,
+    other_map_paths: bool,
+    /// the commits of the other side below its fork point, and their
+    /// content keys. they are only loaded if a tag needs them
+    other_commits: Option<(Vec<Commit>, Vec<ContentKey>)>,
+}
+
+impl<'a> CommitMapper<'a> {
+    fn find(&mut self, commit: &str) -> io::Result<Option<String>> {
+        if commit == self.fork {
+            return Ok(Some(self.other_fork.to_string()));
+        }
+        if !git_helpers3::is_ancestor(commit, self.fork) {
+            return Ok(None);
+        }
+        if self.other_commits.is_none() {
+            let commits = git_helpers3::get_all_commits_from_ref(self.other_fork, Some(MAX_TAG_CANDIDATES))
+                .map_err(|e| ioerr!("{}", e))?;
+            let keys = graft::get_content_keys(&commits, &self.other_filter_rules, self.other_map_paths)?;
+            self.other_commits = Some((commits, keys));
+        }
+        let blobs = git_helpers3::get_blobs_at_rev(commit).map_err(|e| ioerr!("{}", e))?;
+        let key = graft::get_content_key(&blobs, &self.filter_rules, self.map_paths);
+        let (commits, keys) = self.other_commits.as_ref().unwrap();
+        Ok(find_oldest_in_run(keys, &key).map(|i| commits[i].id.hash.clone()))
+    }
+}
+
+/// the tags that `from` has and that the other side is missing, and the
+/// names of the tags that we cant map because their commit is not synced yet
+fn find_missing_tags(
+    rules: &[(String, String)],
+    from: &[(String, String)],
+    to: &[(String, String)],
+    to_remote: bool,
+    mapper: &mut CommitMapper,
+) -> io::Result<(Vec<MissingTag>, Vec<String>)> {
+    let mut missing = vec![];
+    let mut not_synced = vec![];
+    for (source_name, name, commit) in find_unmapped_tags(rules, from, to, to_remote) {
+        match mapper.find(&commit)? {
+            Some(commit) => missing.push(MissingTag { source_name, name, commit }),
+            None => not_synced.push(source_name),
+        }
+    }
+    Ok((missing, not_synced))
+}
+
+fn print_missing_tags(description: &str, tags: &[MissingTag]) {
+    if tags.is_empty() {
+        return;
+    }
+    println!("- {}:", description);
+    for tag in tags {
+        println!("  {} -> {} at {}", tag.source_name, tag.name, tag.commit.get(0..7).unwrap_or(&tag.commit));
+    }
+}
+
+pub fn create_local_tags(tags: &[MissingTag]) -> io::Result<()> {
+    for tag in tags {
+        git_helpers3::git_output(&["tag", &tag.name, &tag.commit])?;
+        println!("Created tag {}", tag.name);
+    }
+    Ok(())
+}
+
+pub fn push_remote_tags(repo_url: &str, tags: &[MissingTag]) -> io::Result<()> {
+    let refspecs: Vec<String> = tags.iter()
+        .map(|tag| format!("{}:refs/tags/{}", tag.commit, tag.name))
+        .collect();
+    let mut args = vec!["push", repo_url];
+    args.extend(refspecs.iter().map(|r| r.as_str()));
+    git_helpers3::git_output(&args)?;
+    for tag in tags {
+        println!("Pushed tag {}", tag.name);
+    }
+    Ok(())
+}
+
+/// find the tags that match the `[tags]` rules of the repo file on one side,
+/// but not on the other, and offer to create them there. a tag goes on the
+/// commit that corresponds to the tagged one through the fork point
+pub fn sync_tags(
+    cmd: &MgtCommandSync,
+    repo_file: &RepoFile,
+    repo_url: &str,
+    local_fork: &str,
+    remote_fork: &str,
+    can_push_pull: bool,
+) -> io::Result<()> {
+    let rules = match repo_file.tags {
+        Some(ref rules) if !rules.is_empty() => rules,
+        _ => return Ok(()),
+    };
+    for (local, remote) in rules {
+        validate_tag_rule(local, remote).map_err(|e| ioerr!("{}", e))?;
+    }
+    let local_tags = get_local_tags()?;
+    let remote_tags = get_remote_tags(repo_url)?;
+    // local paths get renamed to where they end up in the split repository.
+    // remote paths are already there, we only drop the ones that split-in would drop
+    let local_filter_rules = || split_out::generate_gitfilter_filterrules(repo_file, false);
+    let remote_filter_rules = || split_in::generate_gitfilter_filterrules(repo_file, false);
+    let mut to_local = CommitMapper {
+        fork: remote_fork,
+        other_fork: local_fork,
+        filter_rules: remote_filter_rules(),
+        map_paths: false,
+        other_filter_rules: local_filter_rules(),
+        other_map_paths: true,
+        other_commits: None,
+    };
+    let mut to_remote = CommitMapper {
+        fork: local_fork,
+        other_fork: remote_fork,
+        filter_rules: local_filter_rules(),
+        map_paths: true,
+        other_filter_rules: remote_filter_rules(),
+        other_map_paths: false,
+        other_commits: None,
+    };
+    let (tags_to_pull, mut not_synced) = find_missing_tags(rules, &remote_tags, &local_tags, false, &mut to_local)?;
+    let (tags_to_push, not_pushed) = find_missing_tags(rules, &local_tags, &remote_tags, true, &mut to_remote)?;
+    not_synced.extend(not_pushed);
+
+    if !not_synced.is_empty() {
+        println!("- Skipping tags of commits that are not synced yet: {}", not_synced.join(", "));
+    }
+    if tags_to_pull.is_empty() && tags_to_push.is_empty() {
+        println!("- Tags are up to date");
+        return Ok(());
+    }
+    print_missing_tags("Remote tags that are missing locally", &tags_to_pull);
+    print_missing_tags("Local tags that are missing in the remote", &tags_to_push);
+    if !can_push_pull {
+        return Ok(());
+    }
+
+    let mut choices = vec![];
+    if !tags_to_pull.is_empty() {
+        choices.push("create the local tags");
+    }
+    if !tags_to_push.is_empty() {
+        choices.push("push the remote tags");
+    }
+    if choices.len() == 2 {
+        choices.push("both");
+    }
+    choices.push("skip");
+    let mut tag_choice: interact::InteractChoices = (&choices[..]).into();
+    tag_choice.description = Some("What would you like to do with these tags?".into());
+    tag_choice.max_loop = cmd.max_interactive_attempts;
+    let selection = interact::interact_number(tag_choice)?;
+    let selection = choices[selection - 1];
+    if selection == "create the local tags" || selection == "both" {
+        create_local_tags(&tags_to_pull)?;
+    }
+    if selection == "push the remote tags" || selection == "both" {
+        push_remote_tags(repo_url, &tags_to_push)
+            .map_err(|e| ioerr!("Failed to push tags to {}:\n{}", repo_url, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tags(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter().map(|(n, c)| (n.to_string(), c.to_string())).collect()
+    }
+
+    #[test]
+    fn tag_names_get_mapped_between_sides() {
+        let rules = tags(&[("foo/v*", "v*"), ("foo-latest", "latest")]);
+        assert_eq!(map_tag_name(&rules, "v1.2.3", false), Some("foo/v1.2.3".into()));
+        assert_eq!(map_tag_name(&rules, "foo/v1.2.3", true), Some("v1.2.3".into()));
+        assert_eq!(map_tag_name(&rules, "latest", false), Some("foo-latest".into()));
+        assert_eq!(map_tag_name(&rules, "bar/v1.0", true), None);
+        assert_eq!(map_tag_name(&rules, "release-1", false), None);
+        assert!(validate_tag_rule("foo/*", "v*").is_ok());
+        assert!(validate_tag_rule("foo/*", "latest").is_err());
+        assert!(validate_tag_rule("foo/*/*", "*/*").is_err());
+
+        let remote = parse_ls_remote_tags(
+            "aaa\trefs/tags/v1.0\nbbb\trefs/tags/v1.1\nccc\trefs/tags/v1.1^{}\nddd\trefs/tags/other\n",
+        );
+        assert_eq!(remote, tags(&[("v1.0", "aaa"), ("v1.1", "ccc"), ("other", "ddd")]));
+        let local = tags(&[("foo/v1.0", "111"), ("foo/v2.0", "222")]);
+        assert_eq!(find_unmapped_tags(&rules, &remote, &local, false), vec![
+            ("v1.1".to_string(), "foo/v1.1".to_string(), "ccc".to_string()),
+        ]);
+        assert_eq!(find_unmapped_tags(&rules, &local, &remote, true), vec![
+            ("foo/v2.0".to_string(), "v2.0".to_string(), "222".to_string()),
+        ]);
+
+        // the oldest commit with those contents is the one that introduced them
+        let key = |s: &str| vec![("a.txt".to_string(), s.to_string())];
+        let keys = vec![key("3"), key("2"), key("2"), key("1")];
+        assert_eq!(find_oldest_in_run(&keys, &key("2")), Some(2));
+        assert_eq!(find_oldest_in_run(&keys, &key("1")), Some(3));
+        assert_eq!(find_oldest_in_run(&keys, &key("4")), None);
+        assert_eq!(find_oldest_in_run(&keys, &[]), None);
+    }
+}
@@ -0,0 +1,383 @@
+use super::git_helpers3;
+use super::git_helpers3::CommitWithBlobs;
+use super::sync::CommitPlan;
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::{cursor, event, execute, queue, terminal};
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+const HELP_LINE: &str = "j/k move  tab switch side  space include  s squash  J/K reorder  enter diff  a apply  q skip";
+const DIFF_HELP_LINE: &str = "j/k scroll  space/b page  q back";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TuiDirection {
+    Push,
+    Pull,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PlanEntry {
+    /// index into the commits of the column, oldest first
+    pub index: usize,
+    pub included: bool,
+    /// squashed into the included commit before it
+    pub squash: bool,
+}
+
+/// the commits of one side, and the order the user put them in
+pub struct PlanColumn {
+    pub title: String,
+    pub direction: TuiDirection,
+    /// the non-merge commits, oldest first
+    pub commits: Vec<CommitWithBlobs>,
+    pub entries: Vec<PlanEntry>,
+    pub cursor: usize,
+}
+
+impl PlanColumn {
+    /// `commits` are most recent first, the same as a topbase result
+    pub fn new(title: &str, direction: TuiDirection, commits: &[CommitWithBlobs]) -> PlanColumn {
+        let mut commits: Vec<CommitWithBlobs> = commits.iter()
+            .filter(|c| !c.commit.is_merge)
+            .cloned()
+            .collect();
+        commits.reverse();
+        let entries = (0..commits.len())
+            .map(|index| PlanEntry { index, included: true, squash: false })
+            .collect();
+        PlanColumn { title: title.to_string(), direction, commits, entries, cursor: 0 }
+    }
+
+    pub fn selected(&self) -> Option<&CommitWithBlobs> {
+        self.entries.get(self.cursor).map(|e| &self.commits[e.index])
+    }
+
+    pub fn move_cursor(&mut self, down: bool) {
+        if down && self.cursor + 1 < self.entries.len() {
+            self.cursor += 1;
+        } else if !down && self.cursor > 0 {
+            self.cursor -= 1;
+        }
+    }
+
+    pub fn toggle_included(&mut self) {
+        if let Some(entry) = self.entries.get_mut(self.cursor) {
+            entry.included = !entry.included;
+        }
+    }
+
+    pub fn toggle_squash(&mut self) {
+        if let Some(entry) = self.entries.get_mut(self.cursor) {
+            entry.squash = !entry.squash;
+        }
+    }
+
+    /// swap the selected commit with the one below or above it.
+    /// the cursor stays on the commit that moved
+    pub fn move_entry(&mut self, down: bool) {
+        let other = if down {
+            self.cursor + 1
+        } else if self.cursor > 0 {
+            self.cursor - 1
+        } else {
+            return;
+        };
+        if other < self.entries.len() {
+            self.entries.swap(self.cursor, other);
+            self.cursor = other;
+        }
+    }
+
+    /// the plan for the commits that are included, in the order they are shown.
+    /// None if that is every commit in its original order, without squashing
+    pub fn get_plan(&self) -> Result<Option<CommitPlan>, String> {
+        let plan: CommitPlan = self.entries.iter()
+            .filter(|e| e.included)
+            .map(|e| (e.index, e.squash))
+            .collect();
+        match plan.first() {
+            None => return Err("Must choose at least one commit".into()),
+            Some((index, true)) => return Err(format!(
+                "{} cannot be squashed because there is no commit before it",
+                self.commits[*index].commit.id.short(),
+            )),
+            _ => (),
+        }
+        let is_unchanged = plan.len() == self.commits.len()
+            && plan.iter().enumerate().all(|(i, (index, squash))| i == *index && !squash);
+        if is_unchanged {
+            Ok(None)
+        } else {
+            Ok(Some(plan))
+        }
+    }
+}
+
+pub enum TuiAction {
+    Continue,
+    Skip,
+    ShowDiff(String),
+    Apply(TuiDirection, Option<CommitPlan>),
+}
+
+pub struct SyncTui {
+    pub title: String,
+    pub columns: Vec<PlanColumn>,
+    pub focus: usize,
+    /// commit hash -> the files it would conflict in if it was pulled
+    pub conflicts: HashMap<String, Vec<String>>,
+    /// shown in place of the help line until the next key
+    pub message: String,
+}
+
+impl SyncTui {
+    fn focused(&mut self) -> &mut PlanColumn {
+        &mut self.columns[self.focus]
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> TuiAction {
+        self.message.clear();
+        let is_shift = key.modifiers.contains(KeyModifiers::SHIFT);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return TuiAction::Skip,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return TuiAction::Skip,
+            KeyCode::Char('J') => self.focused().move_entry(true),
+            KeyCode::Char('K') => self.focused().move_entry(false),
+            KeyCode::Down if is_shift => self.focused().move_entry(true),
+            KeyCode::Up if is_shift => self.focused().move_entry(false),
+            KeyCode::Char('j') | KeyCode::Down => self.focused().move_cursor(true),
+            KeyCode::Char('k') | KeyCode::Up => self.focused().move_cursor(false),
+            KeyCode::Tab | KeyCode::BackTab | KeyCode::Left | KeyCode::Right
+            | KeyCode::Char('h') | KeyCode::Char('l') => {
+                self.focus = (self.focus + 1) % self.columns.len();
+            }
+            KeyCode::Char(' ') | KeyCode::Char('x') => self.focused().toggle_included(),
+            KeyCode::Char('s') => self.focused().toggle_squash(),
+            KeyCode::Enter | KeyCode::Char('d') => {
+                if let Some(commit) = self.focused().selected() {
+                    return TuiAction::ShowDiff(commit.commit.id.hash.clone());
+                }
+            }
+            KeyCode::Char('a') => {
+                let column = &self.columns[self.focus];
+                match column.get_plan() {
+                    Ok(plan) => return TuiAction::Apply(column.direction, plan),
+                    Err(e) => self.message = e,
+                }
+            }
+            _ => (),
+        }
+        TuiAction::Continue
+    }
+
+    fn entry_line(&self, column: &PlanColumn, entry: &PlanEntry) -> String {
+        let commit = &column.commits[entry.index].commit;
+        let mark = match (entry.included, entry.squash) {
+            (false, _) => ' ',
+            (true, false) => 'x',
+            (true, true) => 's',
+        };
+        let conflict = if column.direction == TuiDirection::Pull
+            && matches!(self.conflicts.get(&commit.id.hash), Some(c) if !c.is_empty())
+        {
+            "! "
+        } else {
+            ""
+        };
+        format!("[{}] {} {}{}", mark, commit.id.short(), conflict, commit.summary)
+    }
+
+    /// the lines below the columns: the files that the selected commit changes
+    fn detail_lines(&self) -> Vec<String> {
+        let column = &self.columns[self.focus];
+        let commit = match column.selected() {
+            Some(c) => c,
+            None => return vec![],
+        };
+        let mut lines = vec![format!("{} {}", commit.commit.id.short(), commit.commit.summary)];
+        if let Some(paths) = self.conflicts.get(&commit.commit.id.hash) {
+            if column.direction == TuiDirection::Pull && !paths.is_empty() {
+                lines.push(format!("would conflict in {}", paths.join(", ")));
+            }
+        }
+        for blob in commit.blobs.iter() {
+            lines.push(format!("  {}", blob.path_dest));
+        }
+        lines
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        queue!(out, terminal::Clear(terminal::ClearType::All))?;
+        print_at(out, 0, 0, &self.title, width, true)?;
+
+        // the top half has a column for each side, the bottom
+        // half has the files of the selected commit
+        let list_height = height.saturating_sub(3) / 2;
+        let column_width = width / self.columns.len();
+        for (i, column) in self.columns.iter().enumerate() {
+            let x = i * column_width;
+            let title = format!("{} ({} of {})", column.title,
+                column.entries.iter().filter(|e| e.included).count(), column.entries.len());
+            print_at(out, x, 1, &title, column_width.saturating_sub(1), i == self.focus)?;
+            // scroll so that the cursor is always visible
+            let first = (column.cursor + 1).saturating_sub(list_height);
+            for (row, entry) in column.entries.iter().enumerate().skip(first).take(list_height) {
+                let line = self.entry_line(column, entry);
+                let is_selected = i == self.focus && row == column.cursor;
+                print_at(out, x, 2 + row - first, &line, column_width.saturating_sub(1), is_selected)?;
+            }
+        }
+        let detail_start = 3 + list_height;
+        let detail_height = height.saturating_sub(detail_start + 1);
+        for (row, line) in self.detail_lines().iter().take(detail_height).enumerate() {
+            print_at(out, 0, detail_start + row, line, width, false)?;
+        }
+        let help = if self.message.is_empty() { HELP_LINE } else { self.message.as_str() };
+        print_at(out, 0, height.saturating_sub(1), help, width, true)?;
+        out.flush()
+    }
+}
+
+fn print_at(out: &mut impl Write, x: usize, y: usize, text: &str, width: usize, highlight: bool) -> io::Result<()> {
+    let text: String = text.chars().take(width).collect();
+    queue!(out, cursor::MoveTo(x as u16, y as u16))?;
+    if highlight {
+        queue!(out, SetAttribute(Attribute::Reverse), Print(text), SetAttribute(Attribute::Reset))?;
+    } else {
+        queue!(out, Print(text))?;
+    }
+    Ok(())
+}
+
+/// puts the terminal back the way it was, even if we return early with an error
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn read_key() -> io::Result<KeyEvent> {
+    loop {
+        if let Event::Key(key) = event::read()? {
+            return Ok(key);
+        }
+    }
+}
+
+fn show_diff(out: &mut impl Write, hash: &str) -> io::Result<()> {
+    let diff = git_helpers3::git_output(&["--no-pager", "show", "--no-color", "--stat", "--patch", hash])?;
+    let lines: Vec<&str> = diff.lines().collect();
+    let mut scroll = 0;
+    loop {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let page = height.saturating_sub(1).max(1);
+        queue!(out, terminal::Clear(terminal::ClearType::All))?;
+        for (row, line) in lines.iter().skip(scroll).take(page).enumerate() {
+            print_at(out, 0, row, &line.replace('\t', "    "), width, false)?;
+        }
+        print_at(out, 0, height.saturating_sub(1), DIFF_HELP_LINE, width, true)?;
+        out.flush()?;
+        let max_scroll = lines.len().saturating_sub(page);
+        scroll = match read_key()?.code {
+            KeyCode::Char('q') | KeyCode::Esc | KeyCode::Enter => return Ok(()),
+            KeyCode::Char('j') | KeyCode::Down => scroll + 1,
+            KeyCode::Char('k') | KeyCode::Up => scroll.saturating_sub(1),
+            KeyCode::Char(' ') | KeyCode::PageDown => scroll + page,
+            KeyCode::Char('b') | KeyCode::PageUp => scroll.saturating_sub(page),
+            _ => scroll,
+        }.min(max_scroll);
+    }
+}
+
+/// let the user choose which commits to push or pull in a full screen
+/// terminal UI. `push_commits` and `pull_commits` are most recent first, and
+/// either one can be empty. returns None if the user chose to skip
+pub fn run_sync_tui(
+    title: &str,
+    push_commits: &[CommitWithBlobs],
+    pull_commits: &[CommitWithBlobs],
+    conflicts: HashMap<String, Vec<String>>,
+) -> io::Result<Option<(TuiDirection, Option<CommitPlan>)>> {
+    let mut columns = vec![];
+    if !push_commits.is_empty() {
+        columns.push(PlanColumn::new("push (local)", TuiDirection::Push, push_commits));
+    }
+    if !pull_commits.is_empty() {
+        columns.push(PlanColumn::new("pull (remote)", TuiDirection::Pull, pull_commits));
+    }
+    if columns.is_empty() {
+        return Ok(None);
+    }
+    let mut tui = SyncTui { title: title.to_string(), columns, focus: 0, conflicts, message: String::new() };
+
+    let _guard = TerminalGuard::enter()?;
+    let mut out = io::stdout();
+    loop {
+        tui.draw(&mut out)?;
+        match tui.handle_key(read_key()?) {
+            TuiAction::Continue => (),
+            TuiAction::Skip => return Ok(None),
+            TuiAction::ShowDiff(hash) => {
+                if let Err(e) = show_diff(&mut out, &hash) {
+                    tui.message = format!("Failed to show {}: {}", hash, e);
+                }
+            }
+            TuiAction::Apply(direction, plan) => return Ok(Some((direction, plan))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use git_helpers3::Commit;
+
+    fn commit(hash: &str, is_merge: bool) -> CommitWithBlobs {
+        CommitWithBlobs { commit: Commit::new(&hash.repeat(40), hash.to_string(), is_merge), blobs: vec![] }
+    }
+
+    #[test]
+    fn tui_column_makes_a_plan() {
+        // most recent first, like a topbase result
+        let commits = vec![commit("c", false), commit("m", true), commit("b", false), commit("a", false)];
+        let mut column = PlanColumn::new("pull", TuiDirection::Pull, &commits);
+        assert_eq!(column.commits.len(), 3);
+        assert_eq!(column.selected().unwrap().commit.summary, "a");
+        assert_eq!(column.get_plan(), Ok(None));
+
+        // a squash on the first commit has nothing to squash into
+        column.toggle_squash();
+        assert!(column.get_plan().is_err());
+        column.toggle_squash();
+
+        // move a below b, squash it into b, and leave out c
+        column.move_entry(true);
+        assert_eq!(column.cursor, 1);
+        column.toggle_squash();
+        column.move_cursor(true);
+        column.toggle_included();
+        assert_eq!(column.get_plan(), Ok(Some(vec![(1, false), (0, true)])));
+
+        column.move_cursor(false);
+        column.toggle_included();
+        column.move_cursor(false);
+        column.toggle_included();
+        assert!(column.get_plan().is_err());
+    }
+}
@@ -2,9 +2,16 @@ use super::cli::MgtCommandVerify;
 use super::die;
 use super::repo_file;
 use super::git_helpers3;
+use super::ioerr;
+use super::ioerre;
+use super::split_in;
+use super::split_out;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
 use gitfilter::filter::FilterRules;
 use gitfilter::filter::FilterRule;
+use gitfilter::gitmodules::should_use_gitlink;
 
 #[derive(Debug)]
 pub enum FileOpType<'a> {
@@ -18,9 +25,36 @@ pub enum FileOpType<'a> {
 pub fn get_vec_of_file_ops_with_order<'a>(
     repo_file: &'a repo_file::RepoFile,
     src_to_dest: bool,
+) -> Vec<FileOpType<'a>> {
+    get_vec_of_file_ops_from_rules(
+        &repo_file.include_as, &repo_file.include, &repo_file.exclude, src_to_dest)
+}
+
+/// same as `get_vec_of_file_ops_with_order`, but also includes
+/// the versioned rules that apply to the current state of
+/// the repository, ie: the ones without an `until`
+pub fn get_vec_of_current_file_ops_with_order<'a>(
+    repo_file: &'a repo_file::RepoFile,
+    src_to_dest: bool,
+) -> Vec<FileOpType<'a>> {
+    let mut out_vec = get_vec_of_file_ops_with_order(repo_file, src_to_dest);
+    if let Some(ref versioned) = repo_file.versioned {
+        for rules in versioned.iter().filter(|v| v.until.is_none()) {
+            out_vec.extend(get_vec_of_file_ops_from_rules(
+                &rules.include_as, &rules.include, &rules.exclude, src_to_dest));
+        }
+    }
+    out_vec
+}
+
+pub fn get_vec_of_file_ops_from_rules<'a>(
+    include_as: &'a Option<Vec<String>>,
+    include: &'a Option<Vec<String>>,
+    exclude: &'a Option<Vec<String>>,
+    src_to_dest: bool,
 ) -> Vec<FileOpType<'a>> {
     let mut out_vec = vec![];
-    if let Some(ref include_as) = repo_file.include_as {
+    if let Some(ref include_as) = include_as {
         for (i, _path) in include_as.iter().enumerate() {
             if i % 2 != 0 {
                 if src_to_dest {
@@ -31,12 +65,12 @@ pub fn get_vec_of_file_ops_with_order<'a>(
             }
         }
     }
-    if let Some(ref include) = repo_file.include {
+    if let Some(ref include) = include {
         for path in include {
             out_vec.push(FileOpType::Include(path));
         }
     }
-    if let Some(ref exclude) = repo_file.exclude {
+    if let Some(ref exclude) = exclude {
         for path in exclude {
             out_vec.push(FileOpType::Exclude(path));
         }
@@ -134,14 +168,22 @@ pub fn make_filter_rules<'a>(
 /// most of it is dummy data because we are only using
 /// the part of gitfilter where we decide whether or not
 /// to keep a file/what to rename it.
+/// gitlinks are the paths of any submodules in all_local_files,
+/// which gitfilter treats as directories.
 pub fn apply_expected_gitfilter<'a>(
     all_local_files: &'a Vec<String>,
+    gitlinks: &[String],
     filter_rules: &FilterRules,
 ) -> Vec<(usize, String)> {
     let mut filtered = vec![];
     for (i, path) in all_local_files.iter().enumerate() {
         let mut new_path = path.clone();
-        if gitfilter::filter::should_use_file(&mut new_path, filter_rules, false) {
+        let should_use = if gitlinks.contains(path) {
+            should_use_gitlink(&mut new_path, filter_rules, false)
+        } else {
+            gitfilter::filter::should_use_file(&mut new_path, filter_rules, false)
+        };
+        if should_use {
             filtered.push((i, new_path));
         }
     }
@@ -149,17 +191,409 @@ pub fn apply_expected_gitfilter<'a>(
     filtered
 }
 
+/// find the submodules that get included by the filter rules
+/// but do not have an entry in .gitmodules. when filtering, only
+/// the .gitmodules entries get carried over, so these would end up
+/// as gitlinks that git doesnt know where to clone from.
+pub fn get_submodules_missing_gitmodules(
+    gitlinks: &[String],
+    gitmodules_paths: &[String],
+    filter_rules: &FilterRules,
+) -> Vec<String> {
+    let mut missing = vec![];
+    for path in gitlinks {
+        let mut new_path = path.clone();
+        if should_use_gitlink(&mut new_path, filter_rules, false) && !gitmodules_paths.contains(path) {
+            missing.push(path.clone());
+        }
+    }
+    missing
+}
+
+/// describe a filter rule the way it would be written in a repo file
+pub fn describe_filter_rule(filter_rule: &FilterRule) -> String {
+    match filter_rule {
+        FilterRule::FilterRulePathInclude(src) => format!("include {}", src),
+        FilterRule::FilterRulePathExclude(src) => format!("exclude {}", src),
+        FilterRule::FilterRulePathRename(src, dest) => {
+            let dest = if dest.is_empty() { "(root)" } else { dest };
+            format!("include_as {} -> {}", src, dest)
+        }
+    }
+}
+
+/// the rules that matched a path, the rule that decided
+/// what happens to it, and what that is
+#[derive(Debug, PartialEq)]
+pub struct PathExplanation {
+    pub path: String,
+    pub matched: Vec<usize>,
+    pub decided_by: Option<usize>,
+    /// None if the path is excluded, otherwise its destination
+    pub result: Option<String>,
+    /// why the path is excluded even though the rule that decided it keeps it
+    pub dropped_because: Option<&'static str>,
+}
+
+pub fn explain_path(
+    path: &str,
+    is_gitlink: bool,
+    filter_rules: &FilterRules,
+) -> PathExplanation {
+    // gitlinks are treated as directories. see should_use_gitlink
+    let mut new_path = if is_gitlink { format!("{}/", path) } else { path.to_string() };
+    let mut matched = vec![];
+    let (keep, decided_by) = gitfilter::filter::should_use_file_ex(
+        &mut new_path, filter_rules, false, |i| matched.push(i));
+    let mut dropped_because = None;
+    let result = if is_gitlink {
+        // the filter uses should_use_gitlink, which can drop a gitlink that the rules keep
+        let mut gitlink_path = path.to_string();
+        if should_use_gitlink(&mut gitlink_path, filter_rules, false) {
+            Some(gitlink_path)
+        } else {
+            if keep {
+                dropped_because = Some("a submodule cannot be at the root of the repository");
+            }
+            None
+        }
+    } else if keep {
+        Some(new_path)
+    } else {
+        None
+    };
+    PathExplanation {
+        path: path.to_string(),
+        matched,
+        decided_by,
+        result,
+        dropped_because,
+    }
+}
+
+pub fn print_path_explanation(explanation: &PathExplanation, filter_rules: &FilterRules) {
+    println!("{}", explanation.path);
+    for i in explanation.matched.iter() {
+        println!("    matched: {}", describe_filter_rule(&filter_rules[*i]));
+    }
+    match explanation.decided_by {
+        Some(i) => println!("    decided by: {}", describe_filter_rule(&filter_rules[i])),
+        None => println!("    decided by: no rule matched, so it is excluded by default"),
+    }
+    match (&explanation.result, explanation.dropped_because) {
+        (Some(dest), _) => println!("    result: included as {}", dest),
+        (None, Some(reason)) => println!("    result: excluded, because {}", reason),
+        (None, None) => println!("    result: excluded"),
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct VerifySummary {
+    pub excluded_files: Vec<String>,
+    pub kept_count: usize,
+    pub dropped_count: usize,
+    /// None if we dont know the sizes of the files
+    pub kept_bytes: Option<u64>,
+    pub dropped_bytes: Option<u64>,
+}
+
+/// count what the filter rules keep and drop from `all_files`,
+/// given the `remaining_files` of `apply_expected_gitfilter`
+pub fn summarize_verify(
+    all_files: &[String],
+    remaining_files: &[(usize, String)],
+    file_sizes: &Option<HashMap<String, u64>>,
+) -> VerifySummary {
+    let mut is_kept = vec![false; all_files.len()];
+    for (original_index, _) in remaining_files {
+        is_kept[*original_index] = true;
+    }
+    let mut summary = VerifySummary::default();
+    if file_sizes.is_some() {
+        summary.kept_bytes = Some(0);
+        summary.dropped_bytes = Some(0);
+    }
+    for (i, path) in all_files.iter().enumerate() {
+        if path.is_empty() {
+            continue;
+        }
+        let size = file_sizes.as_ref().and_then(|s| s.get(path)).copied().unwrap_or(0);
+        let (count, bytes) = if is_kept[i] {
+            (&mut summary.kept_count, &mut summary.kept_bytes)
+        } else {
+            summary.excluded_files.push(path.clone());
+            (&mut summary.dropped_count, &mut summary.dropped_bytes)
+        };
+        *count += 1;
+        if let Some(b) = bytes {
+            *b += size;
+        }
+    }
+    summary
+}
+
+pub fn print_excluded_files(summary: &VerifySummary) {
+    println!("\nExcluded:");
+    for path in summary.excluded_files.iter() {
+        println!("{}", path);
+    }
+}
+
+/// the summary and the warnings go to stderr, so that the
+/// list of files can still be piped into other commands
+pub fn print_verify_summary(summary: &VerifySummary) {
+    eprintln!("\nSummary:");
+    match (summary.kept_bytes, summary.dropped_bytes) {
+        (Some(kept_bytes), Some(dropped_bytes)) => {
+            eprintln!("Kept {} files ({} bytes)", summary.kept_count, kept_bytes);
+            eprintln!("Dropped {} files ({} bytes)", summary.dropped_count, dropped_bytes);
+        }
+        _ => {
+            eprintln!("Kept {} files", summary.kept_count);
+            eprintln!("Dropped {} files", summary.dropped_count);
+        }
+    }
+}
+
+/// find every destination path that more than one source path
+/// ends up at. returns pairs of (destination, sources)
+pub fn find_destination_collisions(
+    all_files: &[String],
+    remaining_files: &[(usize, String)],
+) -> Vec<(String, Vec<String>)> {
+    let mut sources_by_dest: HashMap<&str, Vec<String>> = HashMap::new();
+    let mut dest_order = vec![];
+    for (original_index, dest) in remaining_files {
+        let sources = sources_by_dest.entry(dest).or_insert_with(|| {
+            dest_order.push(dest.clone());
+            vec![]
+        });
+        sources.push(all_files[*original_index].clone());
+    }
+    dest_order.into_iter().filter_map(|dest| {
+        match sources_by_dest.remove(dest.as_str()) {
+            Some(sources) if sources.len() > 1 => Some((dest, sources)),
+            _ => None,
+        }
+    }).collect()
+}
+
+pub fn print_destination_collisions(collisions: &[(String, Vec<String>)], allowed: bool) {
+    if collisions.is_empty() {
+        return;
+    }
+    if allowed {
+        eprintln!("\nWARNING: the following destinations have more than one source:");
+    } else {
+        eprintln!("\nERROR: the following destinations have more than one source, which would mix their histories together. If this is intended, set allow_destination_collisions = true in the [repo] section of your repo file:");
+    }
+    for (dest, sources) in collisions {
+        eprintln!("{} <- {}", dest, sources.join(", "));
+    }
+}
+
+/// for split-in: find the destinations of incoming files that already
+/// exist locally, but that the repo file does not map to the remote.
+/// split-in would overwrite these even though they belong to something else
+pub fn find_local_paths_outside_mapping(
+    incoming_files: &[(usize, String)],
+    local_files: &Vec<String>,
+    local_gitlinks: &[String],
+    local_filter_rules: &FilterRules,
+) -> Vec<String> {
+    let mapped: HashSet<usize> = apply_expected_gitfilter(local_files, local_gitlinks, local_filter_rules)
+        .into_iter().map(|(index, _)| index).collect();
+    let outside: HashSet<&str> = local_files.iter().enumerate()
+        .filter(|(index, _)| !mapped.contains(index))
+        .map(|(_, path)| path.as_str())
+        .collect();
+    incoming_files.iter()
+        .filter(|(_, dest)| outside.contains(dest.as_str()))
+        .map(|(_, dest)| dest.clone())
+        .collect()
+}
+
+pub fn print_local_paths_outside_mapping(paths: &[String]) {
+    if paths.is_empty() {
+        return;
+    }
+    eprintln!("\nWARNING: the following destinations already exist locally, but are not mapped by this repo file. split-in would overwrite them:");
+    for path in paths {
+        eprintln!("{}", path);
+    }
+}
+
+/// fetch the remote_repo/remote_branch of the repo file
+/// so that its tree is available at FETCH_HEAD
+fn fetch_remote_of_repo_file(repo_file: &repo_file::RepoFile) {
+    let remote = match repo_file.remote_repo {
+        Some(ref r) => r,
+        None => die!("--direction in requires a remote_repo in your repo file, or a --rev to read the remote files from"),
+    };
+    let branch = repo_file.remote_branch.as_deref().unwrap_or("HEAD");
+    if let Err(e) = git_helpers3::fetch_branch(remote, branch) {
+        die!("Failed to fetch {} {}:\n{}", remote, branch, e);
+    }
+}
+
+pub fn print_submodules_missing_gitmodules(missing: &[String]) {
+    if missing.is_empty() {
+        return;
+    }
+    eprintln!("\nWARNING: the following submodules are included, but are not in .gitmodules:");
+    for path in missing {
+        eprintln!("{}", path);
+    }
+}
+
+/// read the repo file at `repo_file_path` as it was at `rev`
+pub fn get_repo_file_at_rev(repo_file_path: &str, rev: &str) -> io::Result<repo_file::RepoFile> {
+    let repo_root = match git_helpers3::get_repo_root() {
+        Ok(r) => PathBuf::from(r),
+        Err(e) => return ioerre!("Failed to find repo root: {}", e),
+    };
+    let full_path = Path::new(repo_file_path).canonicalize()
+        .map_err(|e| ioerr!("Failed to find repo file {}: {}", repo_file_path, e))?;
+    let relative_path = match full_path.strip_prefix(&repo_root) {
+        Ok(p) => p.to_string_lossy().to_string(),
+        Err(_) => return ioerre!("Repo file {} is not in this repository", repo_file_path),
+    };
+    let contents = match git_helpers3::get_file_contents_at_rev(rev, &relative_path) {
+        Ok(c) => c,
+        Err(e) => return ioerre!("Failed to read {} at {}:\n{}", relative_path, rev, e),
+    };
+    let lines = contents.lines().map(|l| l.to_string()).collect();
+    repo_file::parse_repo_file_from_toml_lines_resolved(lines, &full_path)
+}
+
+/// what changes for every file when going from one set of filter rules to another
+#[derive(Debug, Default, PartialEq)]
+pub struct FilterRulesDiff {
+    /// (source path, new destination)
+    pub newly_included: Vec<(String, String)>,
+    /// (source path, old destination)
+    pub newly_excluded: Vec<(String, String)>,
+    /// (source path, old destination, new destination)
+    pub remapped: Vec<(String, String, String)>,
+}
+
+pub fn diff_filter_rules(
+    all_files: &Vec<String>,
+    gitlinks: &[String],
+    old_filter_rules: &FilterRules,
+    new_filter_rules: &FilterRules,
+) -> FilterRulesDiff {
+    let mut old_dests: Vec<Option<String>> = vec![None; all_files.len()];
+    for (i, dest) in apply_expected_gitfilter(all_files, gitlinks, old_filter_rules) {
+        old_dests[i] = Some(dest);
+    }
+    let mut new_dests: Vec<Option<String>> = vec![None; all_files.len()];
+    for (i, dest) in apply_expected_gitfilter(all_files, gitlinks, new_filter_rules) {
+        new_dests[i] = Some(dest);
+    }
+
+    let mut diff = FilterRulesDiff::default();
+    for (i, path) in all_files.iter().enumerate() {
+        match (old_dests[i].take(), new_dests[i].take()) {
+            (None, Some(new)) => diff.newly_included.push((path.clone(), new)),
+            (Some(old), None) => diff.newly_excluded.push((path.clone(), old)),
+            (Some(old), Some(new)) if old != new => diff.remapped.push((path.clone(), old, new)),
+            _ => (),
+        }
+    }
+    diff
+}
+
+pub fn print_filter_rules_diff(diff: &FilterRulesDiff) {
+    if diff.newly_included.is_empty() && diff.newly_excluded.is_empty() && diff.remapped.is_empty() {
+        println!("Both versions of the repo file include the same files");
+        return;
+    }
+    if !diff.newly_included.is_empty() {
+        println!("\nNewly included:");
+        for (src, dest) in diff.newly_included.iter() {
+            println!("+ {} -> {}", src, dest);
+        }
+    }
+    if !diff.newly_excluded.is_empty() {
+        println!("\nNewly excluded:");
+        for (src, dest) in diff.newly_excluded.iter() {
+            println!("- {} -> {}", src, dest);
+        }
+    }
+    if !diff.remapped.is_empty() {
+        println!("\nRemapped:");
+        for (src, old_dest, new_dest) in diff.remapped.iter() {
+            println!("~ {} -> {} (was {})", src, new_dest, old_dest);
+        }
+    }
+}
+
 pub fn run_verify(
     cmd: &mut MgtCommandVerify,
 ) {
-    let repo_file_path = if cmd.repo_file.len() < 1 {
+    if cmd.diff && cmd.diff_rev.is_some() {
+        die!("Cannot use both --diff and --diff-rev");
+    }
+    // with --diff the repo file we verify is the new one,
+    // and the old one is only used to compare against
+    let repo_file_path = if cmd.diff {
+        if cmd.repo_file.len() != 2 {
+            die!("--diff requires exactly two repo files: <old> <new>");
+        }
+        cmd.repo_file[1].clone()
+    } else if cmd.repo_file.len() < 1 {
         die!("Must provide repo path argument");
     } else {
         cmd.repo_file[0].clone()
     };
     let repo_file = repo_file::parse_repo_file_from_toml_path(&repo_file_path);
-    let mut file_ops = get_vec_of_file_ops(&repo_file);
-    let filter_rules = make_filter_rules(&mut file_ops);
+    if cmd.show_resolved {
+        print!("{}", repo_file::generate_repo_file_toml(&repo_file));
+        return;
+    }
+    // with --direction in, we verify what split-in would do: the files
+    // come from the remote, and get mapped back into the local repository
+    let split_in = match cmd.direction.as_deref() {
+        None | Some("out") => false,
+        Some("in") => true,
+        Some(other) => die!("Invalid --direction '{}'. Must be either 'in' or 'out'", other),
+    };
+    if split_in && (cmd.diff || cmd.diff_rev.is_some()) {
+        die!("Cannot use --direction in with --diff or --diff-rev");
+    }
+    let filter_rules = if split_in {
+        split_in::generate_gitfilter_filterrules(&repo_file, cmd.verbose)
+    } else {
+        let mut file_ops = get_vec_of_current_file_ops_with_order(&repo_file, true);
+        make_filter_rules(&mut file_ops)
+    };
+    let old_repo_file = if cmd.diff {
+        Some(repo_file::parse_repo_file_from_toml_path(&cmd.repo_file[0]))
+    } else if let Some(ref diff_rev) = cmd.diff_rev {
+        match get_repo_file_at_rev(&repo_file_path, diff_rev) {
+            Ok(rf) => Some(rf),
+            Err(e) => die!("{}", e),
+        }
+    } else {
+        None
+    };
+    let old_filter_rules = old_repo_file.map(|old_repo_file| {
+        let mut old_file_ops = get_vec_of_current_file_ops_with_order(&old_repo_file, true);
+        make_filter_rules(&mut old_file_ops)
+    });
+    if cmd.stdin && cmd.rev.is_some() {
+        die!("Cannot use both --stdin and --rev");
+    }
+    let rev = match cmd.rev {
+        Some(ref rev) => rev.clone(),
+        None if split_in && !cmd.stdin => {
+            fetch_remote_of_repo_file(&repo_file);
+            "FETCH_HEAD".to_string()
+        }
+        None => "HEAD".to_string(),
+    };
+    let rev = rev.as_str();
     let all_files: Vec<String> = if cmd.stdin {
         let stdin = io::stdin();
         let mut out = vec![];
@@ -172,18 +606,71 @@ pub fn run_verify(
         }
         out
     } else {
-        match git_helpers3::get_all_files_in_repo() {
+        match git_helpers3::get_all_files_at_rev(rev) {
             Ok(text) => {
                 text.split('\n').map(|line| line.to_string()).collect()
             }
             Err(e) => {
-                die!("Failed to get all files in git repo:\n{}", e);
+                die!("Failed to get all files of {}:\n{}", rev, e);
             }
         }
     };
 
+    // when reading from stdin we only have paths, so we dont know
+    // which of them are submodules
+    let gitlinks = if cmd.stdin {
+        vec![]
+    } else {
+        match git_helpers3::get_all_gitlinks_at_rev(rev) {
+            Ok(g) => g,
+            Err(e) => die!("Failed to get submodules in git repo:\n{}", e),
+        }
+    };
+    let missing_gitmodules = if gitlinks.is_empty() {
+        vec![]
+    } else {
+        let gitmodules_paths = git_helpers3::get_gitmodules_paths_at_rev(rev);
+        get_submodules_missing_gitmodules(&gitlinks, &gitmodules_paths, &filter_rules)
+    };
+
+    if let Some(ref old_filter_rules) = old_filter_rules {
+        let diff = diff_filter_rules(&all_files, &gitlinks, old_filter_rules, &filter_rules);
+        print_filter_rules_diff(&diff);
+        return;
+    }
+
+    if cmd.explain || cmd.path.is_some() {
+        let paths = match cmd.path {
+            Some(ref p) => vec![p.clone()],
+            None => all_files.iter().filter(|p| !p.is_empty()).cloned().collect(),
+        };
+        for path in paths {
+            let explanation = explain_path(&path, gitlinks.contains(&path), &filter_rules);
+            print_path_explanation(&explanation, &filter_rules);
+        }
+        return;
+    }
+
     // eprintln!("ALL FILES: {:?}", all_files);
-    let remaining_files = apply_expected_gitfilter(&all_files, &filter_rules);
+    let remaining_files = apply_expected_gitfilter(&all_files, &gitlinks, &filter_rules);
+    // when reading from stdin we only have paths, so we dont know their sizes
+    let file_sizes = if cmd.stdin {
+        None
+    } else {
+        match git_helpers3::get_file_sizes_at_rev(rev) {
+            Ok(s) => Some(s),
+            Err(e) => die!("Failed to get file sizes of {}:\n{}", rev, e),
+        }
+    };
+    let summary = summarize_verify(&all_files, &remaining_files, &file_sizes);
+    // split-in has no allow_destination_collisions. only split-out refuses
+    // to proceed when two paths end up at the same destination
+    let collisions = if split_in {
+        vec![]
+    } else {
+        find_destination_collisions(&all_files, &remaining_files)
+    };
+    let allow_collisions = repo_file.allow_destination_collisions;
 
     // if pretty, we imply verbose, but we print verbosely in a pretty way
     // by calculating the column size:
@@ -216,9 +703,9 @@ pub fn run_verify(
         }
         println!();
 
-        for (original_index, file) in remaining_files {
-            let original_file_len = all_files[original_index].len();
-            print!("{}", all_files[original_index]);
+        for (original_index, file) in remaining_files.iter() {
+            let original_file_len = all_files[*original_index].len();
+            print!("{}", all_files[*original_index]);
             for _ in original_file_len..max_left_column+col_margin_left {
                 print!(" ");
             }
@@ -229,15 +716,172 @@ pub fn run_verify(
             println!("{}", file);
         }
 
-        return;
+    } else {
+        println!("\n{} -> {}", label_src, label_dest);
+        for (original_index, file) in remaining_files.iter() {
+            if cmd.verbose {
+                println!("{} -> {}", all_files[*original_index], file);
+            } else {
+                println!("{}", file);
+            }
+        }
     }
+    if cmd.show_excluded {
+        print_excluded_files(&summary);
+    }
+    print_verify_summary(&summary);
+    print_submodules_missing_gitmodules(&missing_gitmodules);
+    print_destination_collisions(&collisions, allow_collisions);
+    if split_in {
+        let local_files: Vec<String> = match git_helpers3::get_all_files_in_repo() {
+            Ok(text) => text.split('\n').map(|line| line.to_string()).collect(),
+            Err(e) => die!("Failed to get all files in git repo:\n{}", e),
+        };
+        let local_gitlinks = match git_helpers3::get_all_gitlinks_in_repo() {
+            Ok(g) => g,
+            Err(e) => die!("Failed to get submodules in git repo:\n{}", e),
+        };
+        let mut local_file_ops = get_vec_of_current_file_ops_with_order(&repo_file, true);
+        let local_filter_rules = make_filter_rules(&mut local_file_ops);
+        let overwritten = find_local_paths_outside_mapping(
+            &remaining_files, &local_files, &local_gitlinks, &local_filter_rules);
+        print_local_paths_outside_mapping(&overwritten);
+    }
+    if !collisions.is_empty() && !allow_collisions {
+        std::process::exit(1);
+    }
+}
 
-    println!("\n{} -> {}", label_src, label_dest);
-    for (original_index, file) in remaining_files {
-        if cmd.verbose {
-            println!("{} -> {}", all_files[original_index], file);
-        } else {
-            println!("{}", file);
-        }
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn explain_path_reports_every_matching_rule() {
+        let repo_file_str = "include_as = { \"lib/\" = \" \" }\n\n\nexclude = [\"lib/secret/\"]\n";
+        let lines = repo_file_str.split('\n').map(|s| s.to_string()).collect();
+        let repo_file = repo_file::parse_repo_file_from_toml_lines(lines).unwrap();
+        let mut file_ops = get_vec_of_file_ops(&repo_file);
+        let filter_rules = make_filter_rules(&mut file_ops);
+
+        let explanation = explain_path("lib/secret/key.txt", false, &filter_rules);
+        assert_eq!(explanation.matched, vec![0, 1]);
+        assert_eq!(explanation.decided_by, Some(1));
+        assert_eq!(explanation.result, None);
+        assert_eq!(describe_filter_rule(&filter_rules[1]), "exclude lib/secret/");
+
+        let explanation = explain_path("lib/a.txt", false, &filter_rules);
+        assert_eq!(explanation.decided_by, Some(0));
+        assert_eq!(explanation.result, Some("a.txt".into()));
+        assert_eq!(describe_filter_rule(&filter_rules[0]), "include_as lib/ -> (root)");
+
+        // the filter drops a submodule that would end up at the root
+        let explanation = explain_path("lib", true, &filter_rules);
+        assert_eq!(explanation.decided_by, Some(0));
+        assert_eq!(explanation.result, None);
+        assert!(explanation.dropped_because.is_some());
+
+        let explanation = explain_path("lib/vendor", true, &filter_rules);
+        assert_eq!(explanation.result, Some("vendor".into()));
+        assert_eq!(explanation.dropped_because, None);
+    }
+
+    #[test]
+    fn diff_filter_rules_finds_included_excluded_and_remapped() {
+        let all_files: Vec<String> = vec![
+            "lib/a.txt".into(), "lib/b.txt".into(), "docs/c.md".into(), "other.txt".into(),
+        ];
+        let old_filter_rules = vec![
+            FilterRule::FilterRulePathRename("lib/".into(), "".into()),
+            FilterRule::FilterRulePathExclude("lib/b.txt".into()),
+            FilterRule::FilterRulePathInclude("other.txt".into()),
+        ];
+        let new_filter_rules = vec![
+            FilterRule::FilterRulePathRename("lib/".into(), "src/".into()),
+            FilterRule::FilterRulePathInclude("docs/".into()),
+        ];
+        let diff = diff_filter_rules(&all_files, &[], &old_filter_rules, &new_filter_rules);
+        assert_eq!(diff, FilterRulesDiff {
+            newly_included: vec![
+                ("lib/b.txt".into(), "src/b.txt".into()),
+                ("docs/c.md".into(), "docs/c.md".into()),
+            ],
+            newly_excluded: vec![("other.txt".into(), "other.txt".into())],
+            remapped: vec![("lib/a.txt".into(), "a.txt".into(), "src/a.txt".into())],
+        });
+    }
+
+    #[test]
+    fn split_in_flags_local_paths_outside_mapping() {
+        let repo_file_str = "include_as = { \"lib/\" = \" \" }\n";
+        let lines = repo_file_str.split('\n').map(|s| s.to_string()).collect();
+        let repo_file = repo_file::parse_repo_file_from_toml_lines(lines).unwrap();
+        let remote_files: Vec<String> = vec!["a.txt".into(), "README.md".into()];
+        let incoming_rules = split_in::generate_gitfilter_filterrules(&repo_file, false);
+        let incoming = apply_expected_gitfilter(&remote_files, &[], &incoming_rules);
+        assert_eq!(incoming, vec![(0, "lib/a.txt".to_string()), (1, "lib/README.md".to_string())]);
+
+        let local_files: Vec<String> = vec!["lib/a.txt".into(), "README.md".into()];
+        let mut local_file_ops = get_vec_of_file_ops(&repo_file);
+        let local_rules = make_filter_rules(&mut local_file_ops);
+        let outside = find_local_paths_outside_mapping(&incoming, &local_files, &[], &local_rules);
+        assert!(outside.is_empty());
+
+        let mut local_rules = local_rules;
+        local_rules.push(FilterRule::FilterRulePathExclude("lib/a.txt".into()));
+        let outside = find_local_paths_outside_mapping(&incoming, &local_files, &[], &local_rules);
+        assert_eq!(outside, vec!["lib/a.txt".to_string()]);
+    }
+
+    #[test]
+    fn split_out_and_split_in_use_the_current_versioned_rules() {
+        let repo_file_str = "[[versioned]]\nuntil = \"abc\"\ninclude_as = { \"libs/foo/\" = \" \" }\n\n\n[[versioned]]\nsince = \"abc\"\ninclude_as = { \"platform/foo/\" = \" \" }\n";
+        let lines = repo_file_str.split('\n').map(|s| s.to_string()).collect();
+        let repo_file = repo_file::parse_repo_file_from_toml_lines(lines).unwrap();
+        let local_files: Vec<String> = vec!["platform/foo/a.txt".into(), "libs/foo/b.txt".into()];
+        let outgoing_rules = split_out::generate_gitfilter_filterrules(&repo_file, false);
+        let outgoing = apply_expected_gitfilter(&local_files, &[], &outgoing_rules);
+        assert_eq!(outgoing, vec![(0, "a.txt".to_string())]);
+
+        let remote_files: Vec<String> = vec!["a.txt".into()];
+        let incoming_rules = split_in::generate_gitfilter_filterrules(&repo_file, false);
+        let incoming = apply_expected_gitfilter(&remote_files, &[], &incoming_rules);
+        assert_eq!(incoming, vec![(0, "platform/foo/a.txt".to_string())]);
+    }
+
+    #[test]
+    fn destination_collisions_are_found() {
+        let all_files: Vec<String> = vec!["a/config.yml".into(), "b/config.yml".into(), "b/x.txt".into()];
+        let remaining_files = vec![
+            (0, "config.yml".to_string()),
+            (1, "config.yml".to_string()),
+            (2, "x.txt".to_string()),
+        ];
+        let collisions = find_destination_collisions(&all_files, &remaining_files);
+        assert_eq!(collisions, vec![
+            ("config.yml".to_string(), vec!["a/config.yml".to_string(), "b/config.yml".to_string()]),
+        ]);
+    }
+
+    #[test]
+    fn summary_counts_kept_and_dropped_bytes() {
+        let all_files: Vec<String> = vec!["a.txt".into(), "b.txt".into(), "c.txt".into()];
+        let remaining_files = vec![(1, "b.txt".to_string())];
+        let mut sizes = HashMap::new();
+        sizes.insert("a.txt".to_string(), 10);
+        sizes.insert("b.txt".to_string(), 20);
+        sizes.insert("c.txt".to_string(), 30);
+        let summary = summarize_verify(&all_files, &remaining_files, &Some(sizes));
+        assert_eq!(summary, VerifySummary {
+            excluded_files: vec!["a.txt".into(), "c.txt".into()],
+            kept_count: 1,
+            dropped_count: 2,
+            kept_bytes: Some(20),
+            dropped_bytes: Some(40),
+        });
+
+        let summary = summarize_verify(&all_files, &remaining_files, &None);
+        assert_eq!(summary.kept_bytes, None);
+        assert_eq!(summary.dropped_count, 2);
     }
 }
@@ -0,0 +1,203 @@
+use super::ioerr;
+use super::ioerre;
+use super::repo_file;
+use super::repo_file::RepoFile;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// name of the workspace manifest. it lives at the root of the repository
+pub const WORKSPACE_FILE_NAME: &str = "mgt.toml";
+
+#[derive(Debug, Default)]
+pub struct Workspace {
+    /// the directory that contains the mgt.toml
+    pub root: PathBuf,
+    /// every repo file of the workspace, relative to the root
+    pub repo_files: Vec<String>,
+    /// pairs of (group name, repo files in that group)
+    pub groups: Vec<(String, Vec<String>)>,
+    /// every repo file of the workspace gets applied on top of these
+    pub defaults: Option<RepoFile>,
+    /// directories where every path should be claimed by
+    /// at least one repo file. see audit.rs
+    pub watch: Vec<String>,
+}
+
+impl Workspace {
+    /// get the paths of the repo files of `group`, or every repo
+    /// file of the workspace if no group is provided
+    pub fn get_repo_file_paths(&self, group: Option<&str>) -> io::Result<Vec<PathBuf>> {
+        let repo_files = match group {
+            None => &self.repo_files,
+            Some(name) => match self.groups.iter().find(|(n, _)| n == name) {
+                Some((_, files)) => files,
+                None => {
+                    let group_names: Vec<&str> = self.groups.iter()
+                        .map(|(n, _)| n.as_str()).collect();
+                    return ioerre!(
+                        "No group named '{}' in {:?}. Available groups are: {:?}",
+                        name, self.root.join(WORKSPACE_FILE_NAME), group_names
+                    );
+                }
+            },
+        };
+        Ok(repo_files.iter().map(|f| self.root.join(f)).collect())
+    }
+
+    pub fn contains_repo_file(&self, path: &Path) -> bool {
+        let path = match path.canonicalize() {
+            Ok(p) => p,
+            Err(_) => return false,
+        };
+        self.repo_files.iter().any(|f| {
+            match self.root.join(f).canonicalize() {
+                Ok(p) => p == path,
+                Err(_) => false,
+            }
+        })
+    }
+}
+
+fn toml_value_to_vec(toml_value: &Value) -> Vec<String> {
+    match toml_value {
+        Value::Array(a) => a.iter().filter_map(|v| v.as_str()).map(|s| s.to_string()).collect(),
+        Value::String(s) => vec![s.clone()],
+        _ => vec![],
+    }
+}
+
+pub fn parse_workspace_from_str(contents: &str) -> io::Result<Workspace> {
+    let toml_value = contents.parse::<Value>()
+        .map_err(|e| ioerr!("Failed to parse {}: {}", WORKSPACE_FILE_NAME, e))?;
+    let mut workspace = Workspace::default();
+    let t = match toml_value {
+        Value::Table(t) => t,
+        _ => return Ok(workspace),
+    };
+    for (k, v) in t.iter() {
+        match k.as_str() {
+            "repo_files" => workspace.repo_files = toml_value_to_vec(v),
+            "watch" => workspace.watch = toml_value_to_vec(v),
+            "groups" => if let Value::Table(groups) = v {
+                for (name, files) in groups {
+                    workspace.groups.push((name.clone(), toml_value_to_vec(files)));
+                }
+            },
+            "defaults" => if let Value::Table(defaults_table) = v {
+                let mut defaults = RepoFile::default();
+                repo_file::parse_repo_file_from_toml_table(defaults_table, &mut defaults);
+                workspace.defaults = Some(defaults);
+            },
+            _ => (),
+        }
+    }
+
+    // a repo file that is in a group is part of the workspace
+    // even if it was not listed in repo_files
+    for (_, files) in workspace.groups.iter() {
+        for f in files {
+            if !workspace.repo_files.contains(f) {
+                workspace.repo_files.push(f.clone());
+            }
+        }
+    }
+    Ok(workspace)
+}
+
+pub fn parse_workspace<P: AsRef<Path>>(path: P) -> io::Result<Workspace> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| ioerr!("Failed to read {:?}: {}", path, e))?;
+    let mut workspace = parse_workspace_from_str(&contents)?;
+    workspace.root = match path.parent() {
+        Some(p) => p.to_path_buf(),
+        None => PathBuf::from("."),
+    };
+    Ok(workspace)
+}
+
+/// look for a mgt.toml in `dir` or any of its parents.
+/// stops looking once it reaches the root of the git repository
+pub fn find_workspace_file<P: AsRef<Path>>(dir: P) -> Option<PathBuf> {
+    let mut current = Some(dir.as_ref());
+    while let Some(dir) = current {
+        let candidate = dir.join(WORKSPACE_FILE_NAME);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        if dir.join(".git").exists() {
+            return None;
+        }
+        current = dir.parent();
+    }
+    None
+}
+
+/// used by commands that were not given a path to a repo file.
+/// find the workspace of the current directory and get the paths
+/// of the repo files of `group`, or of the whole workspace
+pub fn get_workspace_repo_files(group: Option<&str>) -> io::Result<Vec<PathBuf>> {
+    find_current_workspace()?.get_repo_file_paths(group)
+}
+
+/// find and parse the workspace of the current directory
+pub fn find_current_workspace() -> io::Result<Workspace> {
+    let current_dir = std::env::current_dir()?;
+    let workspace_file = match find_workspace_file(&current_dir) {
+        Some(f) => f,
+        None => return ioerre!(
+            "Must provide a repo file path, or have a {} at the root of your repository",
+            WORKSPACE_FILE_NAME
+        ),
+    };
+    parse_workspace(&workspace_file)
+}
+
+/// if the repo file at `repo_file_path` is part of a workspace, get
+/// the defaults of that workspace
+pub fn get_workspace_defaults(repo_file_path: &Path) -> io::Result<Option<RepoFile>> {
+    let canonical_path = match repo_file_path.canonicalize() {
+        Ok(p) => p,
+        Err(_) => return Ok(None),
+    };
+    let workspace_file = match canonical_path.parent().and_then(find_workspace_file) {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+    let mut workspace = parse_workspace(&workspace_file)?;
+    if !workspace.contains_repo_file(&canonical_path) {
+        return Ok(None);
+    }
+    Ok(workspace.defaults.take())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn workspace_parses_groups_and_defaults() {
+        let contents = r#"
+            repo_files = ["repos/foo.rf", "repos/bar.rf"]
+            [groups]
+            public = ["repos/foo.rf", "repos/baz.rf"]
+            [defaults]
+            exclude = ["target/"]
+            [defaults.repo]
+            branch = "main"
+        "#;
+        let mut workspace = parse_workspace_from_str(contents).unwrap();
+        workspace.root = PathBuf::from("/monorepo");
+        assert_eq!(workspace.repo_files, vec!["repos/foo.rf", "repos/bar.rf", "repos/baz.rf"]);
+        let public = workspace.get_repo_file_paths(Some("public")).unwrap();
+        assert_eq!(public, vec![
+            PathBuf::from("/monorepo/repos/foo.rf"),
+            PathBuf::from("/monorepo/repos/baz.rf"),
+        ]);
+        assert!(workspace.get_repo_file_paths(Some("private")).is_err());
+        let defaults = workspace.defaults.unwrap();
+        assert_eq!(defaults.exclude, Some(vec!["target/".into()]));
+        assert_eq!(defaults.remote_branch, Some("main".into()));
+    }
+}
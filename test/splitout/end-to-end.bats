@@ -959,3 +959,35 @@ function teardown() {
     [[ $output == *"Success"* ]]
     [[ $status == "0" ]]
 }
+
+@test 'overlay files get added to the split out history' {
+    repo_file_contents="
+    [repo]
+    name = \"overlayed\"
+
+
+    [include_as]
+    \"lib/\" = \" \"
+
+
+    [overlay]
+    \"LICENSE\" = \"legal/LICENSE\"
+    \"README.md\" = { content = \"inline readme\" }
+    "
+    echo "$repo_file_contents" > repo_file.sh
+
+    mkdir -p lib/ legal/
+    echo "license text" > legal/LICENSE
+    echo "libfile1.txt" > lib/libfile1.txt
+    echo "not this one" > lib/README.md
+    git add legal/ lib/ && git commit -m "libfile1"
+
+    run $PROGRAM_PATH split-out repo_file.sh --verbose
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$(git branch --show-current)" == "overlayed" ]]
+    [[ -f libfile1.txt ]]
+    [[ "$(cat LICENSE)" == "license text" ]]
+    [[ "$(cat README.md)" == "inline readme" ]]
+    [[ ! -d legal ]]
+}
@@ -30,6 +30,9 @@ function teardown() {
     if [[ -d test_remote_repo2 ]]; then
         rm -rf test_remote_repo2
     fi
+    if [[ -d test_remote_bare.git ]]; then
+        rm -rf test_remote_bare.git
+    fi
     cd ..
     if [[ -d check ]]; then
         rm -rf check/
@@ -311,3 +314,49 @@ function setup() {
     abc_contents="$(cat abc.txt)"
     [[ "$abc_contents" == *"qqq"* ]]
 }
+
+@test 'sync out with push_mode = branch pushes to a new review branch' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    remote_head_before="$(git rev-parse HEAD)"
+    remote_default_branch="$(git symbolic-ref --short HEAD)"
+    cd "$BATS_TMPDIR"
+    test_remote_bare="test_remote_bare.git"
+    git clone --bare test_remote_repo2 "$test_remote_bare"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_bare\"
+    branch = \"$remote_default_branch\"
+    push_mode = \"branch\"
+
+
+    include=[\"abc.txt\", \"xyz.txt\"]
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    # this is the local commit we have that can be pushed up to remote
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+
+    # 1. select push
+    # we should not be asked for a branch name
+    interact="1\n"
+    echo -e "$interact" > interact.txt
+
+    run $PROGRAM_PATH sync repo_file.rf --max-interactive-attempts 1 < interact.txt
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"You can push"* ]]
+    [[ $output == *"Pushed to the review branch mgt/sync/"* ]]
+
+    # the remote got a new review branch, and its default branch did not change
+    cd "$BATS_TMPDIR/$test_remote_bare"
+    remote_has_branches="$(git branch)"
+    echo "Remote has branches now:"
+    echo "$remote_has_branches"
+    [[ "$remote_has_branches" == *"mgt/sync/"* ]]
+    [[ "$(git rev-parse $remote_default_branch)" == "$remote_head_before" ]]
+}